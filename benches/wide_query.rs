@@ -0,0 +1,72 @@
+//! Compares sequential and parallel generation of a query with many top level fields.
+//!
+//! Run with `cargo bench --bench wide_query`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, body::Bytes};
+use serde_json_bytes::{json, serde_json};
+use std::{fs, hint::black_box, sync::Arc, time::Duration};
+use subgraph_mock::{
+    handle::handle_request,
+    latency::{LatencyConfig, LatencyGenerator},
+    state::{Config, FederatedSchema, State},
+};
+use tokio::runtime::Runtime;
+
+/// The number of aliased top level fields in the benchmarked query
+const WIDTH: usize = 64;
+
+fn wide_query() -> String {
+    let fields: Vec<String> = (0..WIDTH)
+        .map(|i| format!("f{i}: users {{ id name email posts {{ id title content }} }}"))
+        .collect();
+    format!("{{ {} }}", fields.join(" "))
+}
+
+fn state(parallel_generation_threshold: Option<usize>) -> Arc<State> {
+    let source = fs::read_to_string("tests/data/schema.graphql").expect("schema exists");
+    let schema = FederatedSchema::parse_string(source, "schema.graphql").expect("schema is valid");
+
+    // Generate every response from scratch, and without any injected latency to drown out the difference
+    let mut config = Config {
+        cache_responses: false,
+        latency_generator: LatencyGenerator::new(LatencyConfig {
+            base: Duration::ZERO,
+            saw: None,
+            sine: None,
+            square: None,
+            triangle: None,
+            jitter_pct: 0.0,
+        }),
+        ..Default::default()
+    };
+    config.response_generation.parallel_generation_threshold = parallel_generation_threshold;
+
+    Arc::new(State::from_schema(config, schema).expect("state can be created"))
+}
+
+fn bench_wide_query(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("runtime can be built");
+    let body = Bytes::from(serde_json::to_vec(&json!({ "query": wide_query() })).unwrap());
+
+    let mut group = c.benchmark_group("wide_query");
+    for (name, threshold) in [("sequential", None), ("parallel", Some(2))] {
+        let state = state(threshold);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let req = Request::post("/")
+                        .body(Full::new(body.clone()))
+                        .expect("request is valid");
+                    let resp = handle_request(req, state.clone()).await.unwrap();
+                    black_box(resp.into_body().collect().await.unwrap().to_bytes())
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_wide_query);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+use std::{io, path::PathBuf};
+
+/// The ways that loading a schema or config for the mock can fail, for embedders that need to tell them apart
+#[derive(Debug, thiserror::Error)]
+pub enum SubgraphMockError {
+    /// A file or directory couldn't be read
+    #[error("unable to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// A schema isn't syntactically valid GraphQL, or its files can't be merged into one schema
+    #[error(transparent)]
+    Parse(anyhow::Error),
+    /// A schema parsed, but isn't a valid GraphQL schema, even after being patched for federation
+    #[error(transparent)]
+    Validation(anyhow::Error),
+    /// A config has values that can't be used, or doesn't have the expected structure
+    #[error(transparent)]
+    Config(anyhow::Error),
+}
+
+impl SubgraphMockError {
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Io { path, source }
+    }
+}
@@ -0,0 +1,85 @@
+//! Cross-origin resource sharing, so that browser based clients can talk to the mock directly
+use crate::handle::ByteResponse;
+use anyhow::Context;
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    HeaderMap, Response, StatusCode,
+    header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue, ORIGIN, VARY,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// Which browser origins may make requests to the mock, and with what
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins that are allowed to make requests, or `*` to allow any origin
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    vec!["content-type".to_string()]
+}
+
+impl CorsConfig {
+    /// Ensures that the allowed methods and headers can be sent as header values
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.allow_methods()?;
+        self.allow_headers()?;
+        Ok(())
+    }
+
+    /// Respond to a preflight `OPTIONS` request. Origins that aren't allowed get a response without any CORS headers,
+    /// which the browser treats as a rejection.
+    pub fn preflight(&self, request_headers: &HeaderMap) -> anyhow::Result<ByteResponse> {
+        let mut resp = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Empty::new().map_err(|never| match never {}).boxed())?;
+
+        if self.add_headers(request_headers, resp.headers_mut()) {
+            let headers = resp.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.allow_methods()?);
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, self.allow_headers()?);
+        }
+
+        Ok(resp)
+    }
+
+    /// Allow the request's origin to read the response, if it is an allowed origin. Returns whether it was.
+    pub fn add_headers(&self, request_headers: &HeaderMap, headers: &mut HeaderMap) -> bool {
+        // The response depends on the origin, so it must not be served from a cache to another origin
+        headers.append(VARY, HeaderValue::from_static("origin"));
+
+        let Some(origin) = request_headers.get(ORIGIN) else {
+            return false;
+        };
+        let allowed = self
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin == allowed.as_str());
+        if allowed {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        }
+
+        allowed
+    }
+
+    fn allow_methods(&self) -> anyhow::Result<HeaderValue> {
+        HeaderValue::try_from(self.allowed_methods.join(", "))
+            .context("invalid method in cors.allowed_methods")
+    }
+
+    fn allow_headers(&self) -> anyhow::Result<HeaderValue> {
+        HeaderValue::try_from(self.allowed_headers.join(", "))
+            .context("invalid header in cors.allowed_headers")
+    }
+}
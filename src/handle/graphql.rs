@@ -1,46 +1,162 @@
 use crate::{
-    handle::ByteResponse,
-    state::{Config, FederatedSchema, State},
+    error::SubgraphMockError,
+    handle::{ByteResponse, REQUEST_ID_HEADER},
+    state::{Config, FederatedSchema, ResponseFixtures, State},
 };
 use anyhow::anyhow;
 use apollo_compiler::schema::UnionType;
 use apollo_compiler::{
     ExecutableDocument, Name, Node, Schema,
-    ast::OperationType,
-    executable::{Field, Selection, SelectionSet},
+    ast::{self, OperationType},
+    collections::IndexMap,
+    executable::{Field, Operation, Selection, SelectionSet},
+    parser::LineColumn,
     request::coerce_variable_values,
-    response::JsonMap,
+    response::{GraphQLError, JsonMap},
     schema::ExtendedType,
     validation::{Valid, WithErrors},
 };
-use cached::proc_macro::cached;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use cached::{Cached, proc_macro::cached};
 use http_body_util::{BodyExt, Empty, Full};
 use hyper::{
     HeaderMap, Response, StatusCode,
     body::Bytes,
-    header::{HeaderName, HeaderValue},
+    header::{
+        ACCEPT, AGE, CONTENT_TYPE, ETAG, HeaderName, HeaderValue, IF_NONE_MATCH, RETRY_AFTER,
+    },
 };
 use ordered_float::OrderedFloat;
-use rand::{RngExt, rngs::ThreadRng, seq::IteratorRandom};
+use rand::{
+    Rng, RngExt, SeedableRng,
+    rngs::StdRng,
+    seq::{IndexedRandom, IteratorRandom},
+};
+use rayon::prelude::*;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json_bytes::{
     ByteString, Map, Value, json,
     serde_json::{self, Number},
 };
+use sha2::{Digest, Sha256};
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     hash::{DefaultHasher, Hash, Hasher},
     mem,
     ops::RangeInclusive,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::SystemTime,
 };
-use tracing::{debug, error, trace};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
+
+/// Request header that forces the response to be an empty one with the given HTTP status. Only honored when
+/// [Config::allow_error_override] is enabled.
+pub const FORCE_STATUS_HEADER: &str = "x-mock-force-status";
+
+/// Request header that forces the response to be a GraphQL request error with the header's value as its message.
+/// Only honored when [Config::allow_error_override] is enabled.
+pub const FORCE_ERROR_HEADER: &str = "x-mock-force-error";
+
+/// Request header that seeds every random decision about a request with the given `u64`, so that a response can be
+/// reproduced exactly. Seeded responses are always generated fresh, never cached. Only honored when
+/// [Config::allow_seed_override] is enabled.
+pub const SEED_HEADER: &str = "x-mock-seed";
+
+/// Request header that selects one of the [Config::profiles] to generate the response with, instead of the
+/// response generation config of the subgraph being requested. Unknown profiles are ignored.
+pub const PROFILE_HEADER: &str = "x-mock-profile";
+
+/// Response extension holding the [Config::field_latency] of the fields that a response's operation selects, which
+/// is added to the latency that the response is delayed by
+#[derive(Debug, Clone, Copy)]
+pub struct FieldLatency(pub Duration);
+
+/// Response extension holding a seed drawn from the RNG that a response was generated with, which the jitter of its
+/// latency is drawn from so that seeded and deterministic requests are delayed reproducibly as well
+#[derive(Debug, Clone, Copy)]
+pub struct JitterSeed(pub u64);
+
+/// The response generation config for a request, which is the profile that it selects with the [PROFILE_HEADER] if
+/// there is one, or else the config of its subgraph
+fn response_generation_config<'a>(
+    config: &'a Config,
+    subgraph_name: Option<&str>,
+    request_headers: &HeaderMap,
+) -> &'a ResponseGenerationConfig {
+    if let Some(value) = request_headers.get(PROFILE_HEADER) {
+        match value
+            .to_str()
+            .ok()
+            .and_then(|name| config.profiles.get(name.trim()))
+        {
+            Some(profile) => return profile,
+            None => warn!(?value, "ignoring unknown {PROFILE_HEADER}"),
+        }
+    }
+
+    subgraph_name
+        .and_then(|name| config.subgraph_overrides.response_generation.get(name))
+        .unwrap_or(&config.response_generation)
+}
 
 pub async fn handle(
     body_bytes: Vec<u8>,
+    request_headers: &HeaderMap,
     subgraph_name: Option<&str>,
     state: Arc<State>,
 ) -> anyhow::Result<ByteResponse> {
+    // A hanging request mustn't hold on to the config, or it would block reloads for as long as it hangs
+    let (mut rng, now, hang, seeded) = {
+        let config = state.config.read().await;
+        let rgen_cfg = response_generation_config(&config, subgraph_name, request_headers);
+        let seed = seed_override(&config, request_headers);
+        let mut rng = request_rng(&config, subgraph_name, seed, &body_bytes);
+        let now = reference_time(&config, seed);
+        let hang = rgen_cfg
+            .hang_ratio
+            .filter(|(numerator, denominator)| rng.random_ratio(*numerator, *denominator))
+            .map(|_| rgen_cfg.hang_duration);
+        (rng, now, hang, seed.is_some())
+    };
+    if let Some(duration) = hang {
+        debug!(
+            duration_ms = duration.map(|duration| duration.as_millis()),
+            "hanging request"
+        );
+        if !state.hang(duration).await {
+            return Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Empty::new().map_err(|never| match never {}).boxed())
+                .map_err(|err| err.into());
+        }
+    }
+
+    let config = state.config.read().await;
+
+    // A subgraph that is down doesn't look at the request at all
+    let overrides = &config.subgraph_overrides;
+    let outage = match subgraph_name {
+        Some(name) if overrides.response_generation.contains_key(name) => {
+            overrides.outage.get(name)
+        }
+        _ => config.outage.as_ref(),
+    };
+    if let Some(remaining) = outage.and_then(|outage| outage.remaining(Instant::now())) {
+        debug!(
+            remaining_ms = remaining.as_millis(),
+            "responding during scheduled outage"
+        );
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(RETRY_AFTER, remaining.as_secs_f64().ceil() as u64)
+            .body(Empty::new().map_err(|never| match never {}).boxed())
+            .map_err(|err| err.into());
+    }
+
     let req: GraphQLRequest = match serde_json::from_slice(&body_bytes) {
         Ok(req) => req,
         Err(err) => {
@@ -56,51 +172,545 @@ pub async fn handle(
         }
     };
 
-    let config = state.config.read().await;
-    let schema = state.schema.read().await;
-    let rgen_cfg = subgraph_name
-        .and_then(|name| config.subgraph_overrides.response_generation.get(name))
-        .unwrap_or_else(|| &config.response_generation);
+    let schema = state.schema_for(subgraph_name).read().await;
+    let rgen_cfg = response_generation_config(&config, subgraph_name, request_headers);
 
-    // Since the response gen config and schema can be reloaded, they need to be included in the cache hash
-    // alongside the query itself. This does mean that hot reloads will balloon memory over time since the old
-    // values aren't invalidated. If we find this to actually be a practical problem in test scenarios that
-    // demand a high cardinality of config/schema setups, we can set up more intelligent caching with invalidation.
-    let mut hasher = DefaultHasher::new();
-    req.query.hash(&mut hasher);
-    rgen_cfg.hash(&mut hasher);
-    schema.hash(&mut hasher);
-    let cache_hash = hasher.finish();
+    let request_error_status = rgen_cfg.content_type.request_error_status(request_headers);
+    let (doc_hash, cache_hash) = cache_hashes(&req, rgen_cfg, &schema, request_error_status);
 
-    if let Some((numerator, denominator)) = rgen_cfg.http_error_ratio {
-        let mut rng = rand::rng();
-        if rng.random_ratio(numerator, denominator) {
+    for (header_name, condition) in &rgen_cfg.header_conditions {
+        if !condition.is_satisfied_by(request_headers.get(header_name.as_str())) {
+            debug!(%header_name, status = condition.status, "request header condition not met");
             return Response::builder()
-                .status(rng.random_range(500..=504))
+                .status(StatusCode::from_u16(condition.status)?)
                 .body(Empty::new().map_err(|never| match never {}).boxed())
                 .map_err(|err| err.into());
         }
     }
 
-    let (bytes, status_code) = if subgraph_name
-        .and_then(|name| config.subgraph_overrides.cache_responses.get(name).copied())
-        .unwrap_or_else(|| config.cache_responses)
+    if config.allow_error_override
+        && let Some(resp) = forced_response(
+            &mut rng,
+            &config,
+            rgen_cfg,
+            subgraph_name,
+            req.operation_name.as_deref(),
+            request_headers,
+        )?
+    {
+        return Ok(resp);
+    }
+
+    if let Some((numerator, denominator)) = rgen_cfg.http_error_ratio
+        && rng.random_ratio(numerator, denominator)
+    {
+        let status = StatusCode::from_u16(rng.random_range(500..=504))?;
+        let mut resp = empty_response(rgen_cfg, status)?;
+        resp.extensions_mut().insert(JitterSeed(rng.random()));
+        return Ok(resp);
+    }
+
+    if let Some((numerator, denominator)) = rgen_cfg.reset_ratio
+        && rng.random_ratio(numerator, denominator)
     {
-        into_response_bytes_and_status_code(rgen_cfg, req, &schema, cache_hash).await
+        debug!("resetting connection");
+        return Err(ConnectionReset.into());
+    }
+
+    if !is_operation_allowed(rgen_cfg, &req, &schema, doc_hash) {
+        debug!(
+            operation_name = req.operation_name,
+            "rejecting disallowed operation"
+        );
+        let bytes = serde_json::to_vec(&json!({
+            "data": null,
+            "errors": [{
+                "message": "Operation is not allowed",
+                "extensions": { "code": "OPERATION_NOT_ALLOWED" },
+            }],
+        }))?;
+        let mut resp = Response::new(
+            Full::new(bytes.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *resp.status_mut() = request_error_status;
+        add_headers(
+            &mut rng,
+            &config,
+            rgen_cfg,
+            subgraph_name,
+            req.operation_name.as_deref(),
+            request_headers,
+            resp.headers_mut(),
+        );
+        return Ok(resp);
+    }
+
+    let field_latency = FieldLatency(field_latency(&config, &req, &schema, doc_hash));
+
+    let fixture = fixture_response(
+        &*state.response_fixtures.read().await,
+        rgen_cfg,
+        &req,
+        &schema,
+        doc_hash,
+    );
+    if let Some(response) = fixture {
+        debug!(
+            operation_name = req.operation_name,
+            "responding with response fixture"
+        );
+        let mut resp = Response::new(
+            Full::new(serde_json::to_vec(&response)?.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        resp.extensions_mut().insert(field_latency);
+        add_headers(
+            &mut rng,
+            &config,
+            rgen_cfg,
+            subgraph_name,
+            req.operation_name.as_deref(),
+            request_headers,
+            resp.headers_mut(),
+        );
+        resp.extensions_mut().insert(JitterSeed(rng.random()));
+        return Ok(resp);
+    }
+
+    // Responses to operations that use `@stream` are split up after caching, for clients that accept them
+    let stream_req = (accepts(request_headers, MULTIPART_MIXED) && req.query.contains("@stream"))
+        .then(|| req.clone());
+
+    // Identical requests already get identical responses in deterministic mode, and caching them would make a
+    // response depend on whichever request with the same query happened to come first. The same goes for seeded
+    // requests.
+    let is_cached = !config.deterministic
+        && !seeded
+        && subgraph_name
+            .and_then(|name| config.subgraph_overrides.cache_responses.get(name).copied())
+            .unwrap_or_else(|| config.cache_responses);
+    let operation_name = req.operation_name.clone();
+    let (bytes, status_code, generated_at, is_generated) = if is_cached {
+        into_response_bytes_and_status_code(
+            &mut rng,
+            rgen_cfg,
+            req,
+            &schema,
+            request_error_status,
+            doc_hash,
+            cache_hash,
+        )
+        .await
     } else {
-        into_response_bytes_and_status_code_no_cache(rgen_cfg, req, &schema, cache_hash).await
+        // Uncached responses may be seeded, so they are generated relative to the request's reference time
+        debug!(req.operation_name, "handling graphql request");
+        let (bytes, status_code, is_generated) = response_bytes_and_status_code(
+            &mut rng,
+            now,
+            rgen_cfg,
+            req,
+            &schema,
+            request_error_status,
+            doc_hash,
+        );
+        (bytes, status_code, Instant::now(), is_generated)
+    };
+
+    let incremental = stream_req
+        .filter(|_| status_code == StatusCode::OK)
+        .and_then(|req| incremental_response(&req, &schema, doc_hash, &bytes));
+
+    // Corrupted after caching, so that a malformed response is never served again from the cache
+    let malformed = is_generated
+        .then(|| malformed_response(&mut rng, rgen_cfg, &bytes))
+        .flatten();
+    let is_malformed = malformed.is_some();
+    let (bytes, status_code, is_incremental) = match (malformed, incremental) {
+        (Some(malformed), _) => (malformed, StatusCode::OK, false),
+        (None, Some(incremental)) => (incremental, status_code, true),
+        (None, None) => (bytes, status_code, false),
     };
 
+    // Cached responses can be revalidated, since they don't change until the cache is flushed
+    let etag = (is_cached && !is_malformed && status_code == StatusCode::OK)
+        .then(|| etag(cache_hash, &bytes));
+    let age = HeaderValue::from(generated_at.elapsed().as_secs());
+    if let Some(etag) = &etag
+        && is_not_modified(request_headers, etag)
+    {
+        debug!(?etag, "responding with not modified");
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, etag)
+            .header(AGE, age)
+            .body(Empty::new().map_err(|never| match never {}).boxed())
+            .map_err(|err| err.into());
+    }
+
     let mut resp = Response::new(Full::new(bytes).map_err(|never| match never {}).boxed());
     *resp.status_mut() = status_code;
+    resp.extensions_mut().insert(field_latency);
 
     let headers = resp.headers_mut();
-    add_headers(&config, rgen_cfg, subgraph_name, headers);
+    add_headers(
+        &mut rng,
+        &config,
+        rgen_cfg,
+        subgraph_name,
+        operation_name.as_deref(),
+        request_headers,
+        headers,
+    );
+    if is_incremental {
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(INCREMENTAL_CONTENT_TYPE),
+        );
+    }
+    if let Some(etag) = etag {
+        headers.insert(ETAG, etag);
+        headers.insert(AGE, age);
+    }
+    resp.extensions_mut().insert(JitterSeed(rng.random()));
 
     Ok(resp)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The strong `ETag` of a cached response, derived from the hash that it is cached by and the response itself, so
+/// that the response generated after a cache flush gets a new one
+fn etag(cache_hash: u64, bytes: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    cache_hash.hash(&mut hasher);
+    bytes.hash(&mut hasher);
+    HeaderValue::try_from(format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex is a valid header value")
+}
+
+/// Whether a request's `If-None-Match` header lists `etag`, or is `*`. Entity tags are compared weakly, as the
+/// HTTP spec says to for `If-None-Match`.
+fn is_not_modified(request_headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    request_headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The RNG that every random decision about a request is made with, which is seeded by the request's `seed` if it
+/// has one. Otherwise with [Config::deterministic] it's seeded from the subgraph name and the request body, so that
+/// identical requests always get identical responses, even across restarts.
+pub(crate) fn request_rng(
+    config: &Config,
+    subgraph_name: Option<&str>,
+    seed: Option<u64>,
+    body: &[u8],
+) -> StdRng {
+    if let Some(seed) = seed {
+        return StdRng::seed_from_u64(seed);
+    }
+    if !config.deterministic {
+        return rand::make_rng();
+    }
+
+    let seed = Sha256::new()
+        .chain_update(subgraph_name.unwrap_or_default())
+        // Separates the name from the body, so that the two can't run into each other
+        .chain_update([0])
+        .chain_update(body)
+        .finalize();
+    StdRng::from_seed(seed.into())
+}
+
+/// The time that a request's generated timestamps are relative to. Requests whose RNG is seeded by [request_rng]
+/// use a fixed time, 2025-01-01T00:00:00Z, instead of the current time, so that their timestamps are reproducible
+/// as well.
+pub(crate) fn reference_time(config: &Config, seed: Option<u64>) -> SystemTime {
+    if seed.is_some() || config.deterministic {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_735_689_600)
+    } else {
+        SystemTime::now()
+    }
+}
+
+/// The seed that a request asks for with the [SEED_HEADER], if seeds are allowed by the config. Invalid seeds are
+/// ignored.
+fn seed_override(config: &Config, request_headers: &HeaderMap) -> Option<u64> {
+    if !config.allow_seed_override {
+        return None;
+    }
+
+    let value = request_headers.get(SEED_HEADER)?;
+    let seed = value
+        .to_str()
+        .ok()
+        .and_then(|seed| seed.trim().parse().ok());
+    if seed.is_none() {
+        warn!(?value, "ignoring invalid {SEED_HEADER}");
+    }
+    seed
+}
+
+/// Pre-generate and cache the responses for every operation in [Config::warmup], for the base config and each
+/// subgraph that has response caching enabled. Operations that can't be read or fail validation against the schema
+/// they are warmed up for are logged and skipped.
+pub async fn warm_up(state: &State) {
+    let config = state.config.read().await;
+    if config.deterministic && !config.warmup.is_empty() {
+        info!("skipping warmup, since responses aren't cached in deterministic mode");
+        return;
+    }
+
+    let rgen_cfgs: Vec<_> = config
+        .cache_responses
+        .then_some((None, &config.response_generation))
+        .into_iter()
+        .chain(
+            config
+                .subgraph_overrides
+                .response_generation
+                .iter()
+                .filter(|(name, _)| {
+                    config
+                        .subgraph_overrides
+                        .cache_responses
+                        .get(*name)
+                        .copied()
+                        .unwrap_or(config.cache_responses)
+                })
+                .map(|(name, rgen_cfg)| (Some(name.as_str()), rgen_cfg)),
+        )
+        .collect();
+
+    for path in &config.warmup {
+        let query = match std::fs::read_to_string(path) {
+            Ok(query) => query,
+            Err(err) => {
+                warn!(%err, path=%path.display(), "unable to read warmup operation");
+                continue;
+            }
+        };
+        let req = GraphQLRequest {
+            query,
+            operation_name: None,
+            variables: JsonMap::new(),
+        };
+
+        for (subgraph_name, rgen_cfg) in &rgen_cfgs {
+            let schema = state.schema_for(*subgraph_name).read().await;
+            let (doc_hash, _) = cache_hashes(&req, rgen_cfg, &schema, StatusCode::OK);
+            if let Err(err) = parse_and_validate(&req, &schema, doc_hash) {
+                warn!(errors=%err.errors, path=%path.display(), ?subgraph_name, "warmup operation failed validation");
+                continue;
+            }
+
+            // Warmed up responses are for requests that don't send any headers
+            let request_error_status = rgen_cfg
+                .content_type
+                .request_error_status(&HeaderMap::new());
+            let (doc_hash, cache_hash) =
+                cache_hashes(&req, rgen_cfg, &schema, request_error_status);
+            let (_, status_code, _, _) = into_response_bytes_and_status_code(
+                &mut rand::make_rng(),
+                rgen_cfg,
+                req.clone(),
+                &schema,
+                request_error_status,
+                doc_hash,
+                cache_hash,
+            )
+            .await;
+            debug!(path=%path.display(), ?subgraph_name, %status_code, "warmed up operation");
+        }
+    }
+}
+
+/// The size, hits and misses of the validated document and response caches, for checking whether requests are
+/// actually being served from the cache
+pub async fn cache_stats() -> Value {
+    fn stats<V>(cache: &impl Cached<u64, V>) -> Value {
+        json!({
+            "size": cache.cache_size(),
+            "hits": cache.cache_hits(),
+            "misses": cache.cache_misses(),
+        })
+    }
+
+    let documents = stats(&*PARSE_AND_VALIDATE.lock());
+    let responses = stats(&*INTO_RESPONSE_BYTES_AND_STATUS_CODE.lock().await);
+    json!({ "documents": documents, "responses": responses })
+}
+
+/// Empty the validated document and response caches, so that every response is generated from scratch again.
+/// Returns the number of entries that were removed from each.
+pub async fn flush_caches() -> Value {
+    flush_with_responses(&mut *INTO_RESPONSE_BYTES_AND_STATUS_CODE.lock().await)
+}
+
+/// The same as [flush_caches], for use outside of the async runtime, like when the schema has been reloaded
+pub(crate) fn flush_caches_blocking() -> Value {
+    flush_with_responses(&mut *INTO_RESPONSE_BYTES_AND_STATUS_CODE.blocking_lock())
+}
+
+fn flush_with_responses<V>(responses: &mut impl Cached<u64, V>) -> Value {
+    let mut documents = PARSE_AND_VALIDATE.lock();
+    let flushed =
+        json!({ "documents": documents.cache_size(), "responses": responses.cache_size() });
+    documents.cache_clear();
+    responses.cache_clear();
+
+    debug!(%flushed, "flushed caches");
+    flushed
+}
+
+/// Whether the operation that `req` selects passes [ResponseGenerationConfig::allowed_operations] and
+/// [ResponseGenerationConfig::denied_operations]. Invalid queries are only matched by their hash, and are left for
+/// validation to reject if they pass.
+fn is_operation_allowed(
+    rgen_cfg: &ResponseGenerationConfig,
+    req: &GraphQLRequest,
+    schema: &FederatedSchema,
+    doc_hash: u64,
+) -> bool {
+    if rgen_cfg.allowed_operations.is_none() && rgen_cfg.denied_operations.is_empty() {
+        return true;
+    }
+
+    let query_hash = query_hash(&req.query);
+    let op_name = match &req.operation_name {
+        Some(op_name) => Some(op_name.clone()),
+        None => parse_and_validate(req, schema, doc_hash)
+            .ok()
+            .and_then(|doc| {
+                let op = doc.operations.get(None).ok()?;
+                op.name.as_ref().map(|name| name.to_string())
+            }),
+    };
+    let is_listed = |operations: &BTreeSet<String>| {
+        operations.contains(&query_hash)
+            || op_name
+                .as_ref()
+                .is_some_and(|name| operations.contains(name))
+    };
+
+    !is_listed(&rgen_cfg.denied_operations)
+        && rgen_cfg.allowed_operations.as_ref().is_none_or(is_listed)
+}
+
+/// The hash that operations can be listed by instead of their name, which is the SHA-256 hash of their query text as
+/// `sha256:<hex>`
+fn query_hash(query: &str) -> String {
+    format!("sha256:{:x}", Sha256::digest(query.as_bytes()))
+}
+
+/// The sum of the [Config::field_latency] of every distinct field that the request's operation selects, directly or
+/// through fragments. Each field is only counted once, however many times it is selected.
+fn field_latency(
+    config: &Config,
+    req: &GraphQLRequest,
+    schema: &FederatedSchema,
+    doc_hash: u64,
+) -> Duration {
+    fn selected_fields<'a>(
+        doc: &'a ExecutableDocument,
+        selection_set: &'a SelectionSet,
+        fields: &mut HashSet<String>,
+    ) {
+        for selection in &selection_set.selections {
+            match selection {
+                Selection::Field(field) => {
+                    fields.insert(format!("{}.{}", selection_set.ty, field.name));
+                    selected_fields(doc, &field.selection_set, fields);
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = doc.fragments.get(&spread.fragment_name) {
+                        selected_fields(doc, &fragment.selection_set, fields);
+                    }
+                }
+                Selection::InlineFragment(inline) => {
+                    selected_fields(doc, &inline.selection_set, fields)
+                }
+            }
+        }
+    }
+
+    if config.field_latency.is_empty() {
+        return Duration::ZERO;
+    }
+    let Ok(doc) = parse_and_validate(req, schema, doc_hash) else {
+        return Duration::ZERO;
+    };
+    let Ok(operation) = doc.operations.get(req.operation_name.as_deref()) else {
+        return Duration::ZERO;
+    };
+
+    let mut fields = HashSet::new();
+    selected_fields(&doc, &operation.selection_set, &mut fields);
+    fields
+        .iter()
+        .filter_map(|field| config.field_latency.get(field))
+        .sum()
+}
+
+/// The response fixture for the operation that `req` selects, if there is one. The query has to be valid for a fixture
+/// to be used, and response overrides in the config take precedence over fixtures.
+fn fixture_response(
+    fixtures: &ResponseFixtures,
+    rgen_cfg: &ResponseGenerationConfig,
+    req: &GraphQLRequest,
+    schema: &FederatedSchema,
+    doc_hash: u64,
+) -> Option<Value> {
+    if fixtures.is_empty() {
+        return None;
+    }
+
+    let doc = parse_and_validate(req, schema, doc_hash).ok()?;
+    let op = doc.operations.get(req.operation_name.as_deref()).ok()?;
+    if rgen_cfg.response_override(&doc, op).is_some() {
+        return None;
+    }
+
+    fixtures.get(op.name.as_ref()?.as_str()).cloned()
+}
+
+/// The hashes used to cache the validated document for the request's query and the response generated for it
+/// respectively. The response also depends on which of the document's operations was requested, and on the
+/// variables it was requested with, since they can pick the entities, list lengths and pages that are generated.
+///
+/// Since the response gen config and schema can be reloaded, they need to be included in the cache hash alongside
+/// the query itself. This does mean that hot reloads will balloon memory over time since the old values aren't
+/// invalidated. If we find this to actually be a practical problem in test scenarios that demand a high
+/// cardinality of config/schema setups, we can set up more intelligent caching with invalidation.
+///
+/// Validated documents only depend on the query and schema, so they are cached separately. That way they are
+/// shared between subgraphs and still reused when response caching is disabled. Responses also depend on the
+/// `request_error_status` that was negotiated for the request.
+fn cache_hashes(
+    req: &GraphQLRequest,
+    rgen_cfg: &ResponseGenerationConfig,
+    schema: &FederatedSchema,
+    request_error_status: StatusCode,
+) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    req.query.hash(&mut hasher);
+    schema.hash(&mut hasher);
+    let doc_hash = hasher.finish();
+    req.operation_name.hash(&mut hasher);
+    req.variables.hash(&mut hasher);
+    rgen_cfg.hash(&mut hasher);
+    request_error_status.hash(&mut hasher);
+    (doc_hash, hasher.finish())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GraphQLRequest {
     pub query: String,
@@ -120,14 +730,92 @@ where
     Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
-fn add_headers(
+/// The response requested with the [FORCE_STATUS_HEADER] or [FORCE_ERROR_HEADER], if either was sent. A forced
+/// status takes precedence over a forced GraphQL error, and invalid statuses are logged and ignored.
+fn forced_response<R: Rng + ?Sized>(
+    rng: &mut R,
+    config: &Config,
+    rgen_cfg: &ResponseGenerationConfig,
+    subgraph_name: Option<&str>,
+    operation_name: Option<&str>,
+    request_headers: &HeaderMap,
+) -> anyhow::Result<Option<ByteResponse>> {
+    if let Some(value) = request_headers.get(FORCE_STATUS_HEADER) {
+        match value
+            .to_str()
+            .ok()
+            .and_then(|status| status.trim().parse().ok())
+            .and_then(|status| StatusCode::from_u16(status).ok())
+        {
+            Some(status) => {
+                debug!(%status, "forcing response status");
+                return empty_response(rgen_cfg, status).map(Some);
+            }
+            None => warn!(?value, "ignoring invalid {FORCE_STATUS_HEADER} header"),
+        }
+    }
+
+    if let Some(value) = request_headers.get(FORCE_ERROR_HEADER) {
+        let message = value
+            .to_str()
+            .ok()
+            .filter(|message| !message.is_empty())
+            .unwrap_or("Request error forced");
+        debug!(%message, "forcing request error");
+
+        let bytes =
+            serde_json::to_vec(&json!({ "data": null, "errors": [{ "message": message }] }))?;
+        let mut resp = Response::new(
+            Full::new(bytes.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        );
+        *resp.status_mut() = rgen_cfg.content_type.request_error_status(request_headers);
+        add_headers(
+            rng,
+            config,
+            rgen_cfg,
+            subgraph_name,
+            operation_name,
+            request_headers,
+            resp.headers_mut(),
+        );
+        return Ok(Some(resp));
+    }
+
+    Ok(None)
+}
+
+/// An empty response with `status`, as sent for injected and forced HTTP errors. Rate limiting and overload responses
+/// tell the client when it is safe to try again with [ResponseGenerationConfig::retry_after].
+fn empty_response(
+    rgen_cfg: &ResponseGenerationConfig,
+    status: StatusCode,
+) -> anyhow::Result<ByteResponse> {
+    let mut builder = Response::builder().status(status);
+    if let Some(retry_after) = rgen_cfg.retry_after
+        && matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        )
+    {
+        builder = builder.header(RETRY_AFTER, retry_after);
+    }
+
+    builder
+        .body(Empty::new().map_err(|never| match never {}).boxed())
+        .map_err(|err| err.into())
+}
+
+fn add_headers<R: Rng + ?Sized>(
+    rng: &mut R,
     config: &Config,
     rgen_cfg: &ResponseGenerationConfig,
     subgraph_name: Option<&str>,
+    operation_name: Option<&str>,
+    request_headers: &HeaderMap,
     headers: &mut HeaderMap,
 ) {
-    let mut rng = rand::rng();
-
     // HeaderMap is a multimap and yields Some(HeaderName) only for the first element of each multimap.
     // We have to track the last one we saw and treat that as the key for all subsequent None values as such.
     // Based on that contract, the first iteration will *always* yield a value so we can safely just initialize
@@ -153,44 +841,237 @@ fn add_headers(
         }
     }
 
-    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+    let variables = HeaderTemplateVariables {
+        operation_name: operation_name.unwrap_or_default(),
+        subgraph: subgraph_name.unwrap_or_default(),
+        request_id: request_headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default(),
+    };
+    for (name, template) in &rgen_cfg.header_templates {
+        if rgen_cfg
+            .header_ratio
+            .get(name)
+            .is_some_and(|(numerator, denominator)| !rng.random_ratio(*numerator, *denominator))
+        {
+            continue;
+        }
+        // Both were validated when the config was loaded, but a rendered value can still contain characters that
+        // aren't allowed in headers
+        let value = render_header_template(template, &variables)
+            .ok()
+            .and_then(|value| HeaderValue::try_from(value).ok());
+        match (HeaderName::try_from(name), value) {
+            (Ok(name), Some(value)) => {
+                headers.insert(name, value);
+            }
+            _ => debug!(
+                header_name = name,
+                "skipping header that didn't render to a valid value"
+            ),
+        }
+    }
+
+    // Echoed headers are reflected with a prefix so that they can never clobber the headers we set ourselves.
+    // Headers that weren't sent on the request are simply skipped.
+    for name in &rgen_cfg.echo_request_headers {
+        let Ok(echo_name) = HeaderName::try_from(format!("x-echo-{name}")) else {
+            continue;
+        };
+
+        for value in request_headers.get_all(name.as_str()) {
+            headers.append(&echo_name, value.clone());
+        }
+    }
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(rgen_cfg.content_type.negotiate(request_headers)),
+    );
+}
+
+/// The values that [ResponseGenerationConfig::header_templates] can refer to
+#[derive(Debug, Default)]
+struct HeaderTemplateVariables<'a> {
+    operation_name: &'a str,
+    subgraph: &'a str,
+    request_id: &'a str,
 }
 
-#[cached(result = true, key = "u64", convert = "{_cache_hash}")]
+/// Replaces every `{variable}` in `template` with its value. Braces can't be escaped, since header values rarely
+/// need them, so any that don't enclose a known variable are an error.
+fn render_header_template(
+    template: &str,
+    variables: &HeaderTemplateVariables,
+) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed {{ in {template:?}"))?;
+        rendered.push_str(match &rest[start + 1..start + end] {
+            "operation_name" => variables.operation_name,
+            "subgraph" => variables.subgraph,
+            "request_id" => variables.request_id,
+            variable => return Err(anyhow!("unknown variable {{{variable}}} in {template:?}")),
+        });
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Parse and validate the request's query against `schema`, caching valid documents by `_doc_hash`, which must
+/// only be derived from the query and the schema
+#[cached(result = true, key = "u64", convert = "{_doc_hash}")]
 fn parse_and_validate(
     req: &GraphQLRequest,
     schema: &Valid<Schema>,
-    _cache_hash: u64,
+    _doc_hash: u64,
 ) -> Result<Valid<ExecutableDocument>, WithErrors<ExecutableDocument>> {
     let op_name = req.operation_name.as_deref().unwrap_or("unknown");
 
     ExecutableDocument::parse_and_validate(schema, &req.query, op_name)
 }
 
-#[tracing::instrument(skip(req, schema))]
+/// Converts the errors of an invalid document to GraphQL errors. Errors on a deprecated field get the reason that
+/// it is deprecated as their `deprecationReason` extension, since a client that still selects the field may be
+/// using it in ways that its schema no longer expects.
+fn validation_errors(err: &WithErrors<ExecutableDocument>, schema: &Schema) -> Vec<GraphQLError> {
+    fn collect<'doc>(selection_set: &'doc SelectionSet, fields: &mut Vec<&'doc Node<Field>>) {
+        for selection in &selection_set.selections {
+            match selection {
+                Selection::Field(field) => {
+                    fields.push(field);
+                    collect(&field.selection_set, fields);
+                }
+                Selection::InlineFragment(inline) => collect(&inline.selection_set, fields),
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+    }
+
+    let doc = &err.partial;
+    let mut fields = Vec::new();
+    for operation in doc.operations.iter() {
+        collect(&operation.selection_set, &mut fields);
+    }
+    for fragment in doc.fragments.values() {
+        collect(&fragment.selection_set, &mut fields);
+    }
+    let position = |line_column: LineColumn| (line_column.line, line_column.column);
+    let spans: Vec<_> = fields
+        .into_iter()
+        .filter_map(|field| {
+            let range = field.line_column_range(&doc.sources)?;
+            Some((position(range.start)..position(range.end), field))
+        })
+        .collect();
+
+    err.errors
+        .iter()
+        .map(|diagnostic| {
+            let mut error = diagnostic.to_json();
+            // Fields start after the fields that they're nested in, so the last one to start is the innermost
+            let field = diagnostic.line_column_range().and_then(|range| {
+                let start = position(range.start);
+                spans
+                    .iter()
+                    .filter(|(span, _)| span.contains(&start))
+                    .max_by_key(|(span, _)| span.start)
+                    .map(|(_, field)| field)
+            });
+            if let Some(deprecated) =
+                field.and_then(|field| field.definition.directives.get("deprecated"))
+            {
+                let reason = deprecated
+                    .argument_by_name("reason", schema)
+                    .ok()
+                    .and_then(|reason| reason.as_str())
+                    .unwrap_or("No longer supported");
+                error
+                    .extensions
+                    .insert("deprecationReason", Value::String(reason.into()));
+            }
+            error
+        })
+        .collect()
+}
+
+/// Generates the response to `req`, which is cached by `cache_hash` along with when it was generated. Cached
+/// responses are never seeded, so their timestamps are relative to the current time.
+#[tracing::instrument(skip(rng, req, schema))]
 #[cached(key = "u64", convert = "{cache_hash}")]
 async fn into_response_bytes_and_status_code(
+    rng: &mut StdRng,
     cfg: &ResponseGenerationConfig,
     req: GraphQLRequest,
     schema: &FederatedSchema,
+    request_error_status: StatusCode,
+    doc_hash: u64,
     cache_hash: u64,
-) -> (Bytes, StatusCode) {
+) -> (Bytes, StatusCode, Instant, bool) {
     debug!(%cache_hash, req.operation_name, "handling graphql request");
+    let (bytes, status_code, is_generated) = response_bytes_and_status_code(
+        rng,
+        SystemTime::now(),
+        cfg,
+        req,
+        schema,
+        request_error_status,
+        doc_hash,
+    );
+    (bytes, status_code, Instant::now(), is_generated)
+}
+
+/// The body and status of the response to `req`, and whether it was generated successfully. Responses that are
+/// errors or come from [ResponseGenerationConfig::response_overrides] weren't, so they are never corrupted by
+/// `malformed_response_ratio`.
+fn response_bytes_and_status_code(
+    rng: &mut StdRng,
+    now: SystemTime,
+    cfg: &ResponseGenerationConfig,
+    req: GraphQLRequest,
+    schema: &FederatedSchema,
+    request_error_status: StatusCode,
+    doc_hash: u64,
+) -> (Bytes, StatusCode, bool) {
     trace!(variables=?req.variables, "request variables");
 
-    let doc = match parse_and_validate(&req, schema, cache_hash) {
+    let doc = match parse_and_validate(&req, schema, doc_hash) {
         Ok(doc) => doc,
         Err(err) => {
-            let errs: Vec<_> = err.errors.iter().map(|d| d.to_json()).collect();
+            let errs = validation_errors(&err, schema);
             error!(?errs, query=%req.query, "invalid graphql query");
             let bytes = serde_json::to_vec(&json!({ "data": Value::Null, "errors": errs }))
                 .unwrap_or_default();
-            return (bytes.into(), StatusCode::BAD_REQUEST);
+            let status = cfg
+                .validation_error_status
+                .map_or(request_error_status, |status| {
+                    StatusCode::from_u16(status).expect("validated when the config was loaded")
+                });
+            return (bytes.into(), status, false);
+        }
+    };
+
+    let op_name = req.operation_name.as_deref();
+    let op = match doc.operations.get(op_name) {
+        Ok(op) => op,
+        Err(err) => {
+            debug!(?op_name, "unable to select operation");
+            let bytes = serde_json::to_vec(
+                &json!({ "data": Value::Null, "errors": [{ "message": err.message().to_string() }] }),
+            )
+            .unwrap_or_default();
+            return (bytes.into(), request_error_status, false);
         }
     };
 
-    let op = doc.operations.iter().next().unwrap();
-    let op_name = op.name.as_ref().map(|name| name.as_str());
+    let is_override = cfg.response_override(&doc, op).is_some();
 
     debug!(
         ?op_name,
@@ -199,15 +1080,42 @@ async fn into_response_bytes_and_status_code(
         "processing operation"
     );
 
-    let resp = match op.operation_type {
+    let mut resp = match op.operation_type {
         OperationType::Query => {
-            match generate_response(cfg, op_name, &doc, schema, &req.variables) {
+            match generate_response(rng, now, cfg, op_name, &doc, schema, &req.variables) {
                 Ok(resp) => resp,
+                Err(err) if err.is::<DepthLimitExceeded>() => {
+                    debug!(max_depth = cfg.max_depth, "query depth limit exceeded");
+                    let bytes = serde_json::to_vec(
+                        &json!({ "data": Value::Null, "errors": [{ "message": err.to_string() }] }),
+                    )
+                    .unwrap_or_default();
+                    let status = StatusCode::from_u16(cfg.depth_limit_status)
+                        .expect("validated when the config was loaded");
+                    return (bytes.into(), status, false);
+                }
+                Err(err) if err.is::<IntrospectionDisabled>() => {
+                    debug!("rejecting introspection query");
+                    let bytes = serde_json::to_vec(
+                        &json!({ "data": Value::Null, "errors": [{ "message": err.to_string() }] }),
+                    )
+                    .unwrap_or_default();
+                    return (bytes.into(), request_error_status, false);
+                }
+                Err(err) if err.is::<SimulatedRequestError>() => {
+                    debug!("simulating request error");
+                    let bytes = serde_json::to_vec(
+                        &json!({ "data": Value::Null, "errors": [{ "message": err.to_string() }] }),
+                    )
+                    .unwrap_or_default();
+                    return (bytes.into(), request_error_status, false);
+                }
                 Err(err) => {
                     error!(%err, "unable to generate response");
                     return (
                         Bytes::from("unable to generate response"),
                         StatusCode::INTERNAL_SERVER_ERROR,
+                        false,
                     );
                 }
             }
@@ -219,23 +1127,80 @@ async fn into_response_bytes_and_status_code(
             return (
                 Bytes::from("not implemented"),
                 StatusCode::INTERNAL_SERVER_ERROR,
+                false,
             );
         }
     };
 
+    if let Some(padding_bytes) = cfg.response_padding_bytes {
+        pad_response(&mut resp, padding_bytes);
+    }
+
     match serde_json::to_vec(&resp) {
-        Ok(bytes) => (bytes.into(), StatusCode::OK),
+        Ok(bytes) => (bytes.into(), StatusCode::OK, !is_override),
         Err(err) => {
             error!(%err, "unable to serialize response");
             (
                 Bytes::from(err.to_string().into_bytes()),
                 StatusCode::INTERNAL_SERVER_ERROR,
+                false,
             )
         }
     }
 }
 
-fn generate_response(
+/// Generates a response to `query` the way the server would for `cfg`, but without going through HTTP, making every
+/// random decision with `rng` and generating timestamps relative to `now`. Requests that the server would respond to
+/// with a GraphQL error, such as invalid queries, get that error as their response.
+pub fn generate_response_value<R: Rng + ?Sized>(
+    rng: &mut R,
+    now: SystemTime,
+    cfg: &ResponseGenerationConfig,
+    schema: &FederatedSchema,
+    query: &str,
+    operation_name: Option<&str>,
+    variables: &JsonMap,
+) -> anyhow::Result<Value> {
+    let request_error =
+        |message: String| json!({ "data": Value::Null, "errors": [{ "message": message }] });
+
+    let doc = match ExecutableDocument::parse_and_validate(
+        schema,
+        query,
+        operation_name.unwrap_or("query"),
+    ) {
+        Ok(doc) => doc,
+        Err(err) => {
+            let errs = validation_errors(&err, schema);
+            return Ok(json!({ "data": Value::Null, "errors": errs }));
+        }
+    };
+    let op = match doc.operations.get(operation_name) {
+        Ok(op) => op,
+        Err(err) => return Ok(request_error(err.message().to_string())),
+    };
+    if op.operation_type != OperationType::Query {
+        return Err(anyhow!(
+            "{} operations are not supported",
+            op.operation_type
+        ));
+    }
+
+    match generate_response(rng, now, cfg, operation_name, &doc, schema, variables) {
+        Err(err)
+            if err.is::<DepthLimitExceeded>()
+                || err.is::<IntrospectionDisabled>()
+                || err.is::<SimulatedRequestError>() =>
+        {
+            Ok(request_error(err.to_string()))
+        }
+        result => result,
+    }
+}
+
+fn generate_response<R: Rng + ?Sized>(
+    rng: &mut R,
+    now: SystemTime,
     cfg: &ResponseGenerationConfig,
     op_name: Option<&str>,
     doc: &Valid<ExecutableDocument>,
@@ -244,14 +1209,21 @@ fn generate_response(
 ) -> anyhow::Result<Value> {
     let op = match doc.operations.get(op_name) {
         Ok(op) => op,
-        Err(_) => return Ok(json!({ "data": null })),
+        Err(_) => return Ok(cfg.empty_selection.response()),
     };
-    let mut rng = rand::rng();
+    // Servers reject introspection while validating, so before anything else happens
+    if !cfg.introspection && selects_introspection(doc, &op.selection_set) {
+        return Err(IntrospectionDisabled.into());
+    }
+    if let Some(response) = cfg.response_override(doc, op) {
+        debug!(?op_name, "responding with response override");
+        return Ok(response.clone());
+    }
 
     if let Some((numerator, denominator)) = cfg.graphql_errors.request_error_ratio
         && rng.random_ratio(numerator, denominator)
     {
-        return Ok(json!({ "data": null, "errors": [{ "message": "Request error simulated" }]}));
+        return Err(SimulatedRequestError.into());
     }
 
     // Short-circuit introspection responses if a request is *only* introspection. This does mean that requests
@@ -272,39 +1244,299 @@ fn generate_response(
         .and_then(|result| serde_json_bytes::to_value(result).map_err(|err| anyhow!("{}", err)));
     }
 
-    let mut data =
-        ResponseBuilder::new(&mut rng, doc, schema, cfg).selection_set(&op.selection_set)?;
-
-    // Select a random number of top-level fields to "fail" if we are going to have field errors. For the sake of
-    // simplicity and performance, we won't traverse deeper into the response object.
-    if let Some((numerator, denominator)) = cfg.graphql_errors.field_error_ratio
-        && rng.random_ratio(numerator, denominator)
+    // The depth is checked up front rather than while generating, since randomly null or empty fields could
+    // otherwise let an overly deep query through
+    if let Some(max_depth) = cfg.max_depth
+        && exceeds_depth(doc, &op.selection_set, 1, max_depth)
     {
-        let drop_count = rng.random_range(1..=data.len());
-        let sampled_keys = data.keys().cloned().sample(&mut rng, drop_count);
-        let to_drop: HashSet<ByteString> = HashSet::from_iter(sampled_keys);
+        return Err(DepthLimitExceeded.into());
+    }
+
+    let budget = NodeBudget::default();
+    let mut builder = ResponseBuilder::new(rng, now, doc, schema, cfg, variables, &budget);
+    let mut data = builder.root_selection_set(&op.selection_set)?;
+    // A non-null field that fails would null out the whole of `data`, so only nullable fields are failed. Each is
+    // kept with the locations of the fields that select it, for its error.
+    let nullable_keys: Vec<(ByteString, Vec<Value>)> = builder
+        .collect_fields(&op.selection_set)?
+        .into_iter()
+        .filter(|(_, fields)| !fields[0].ty().is_non_null())
+        .map(|(key, fields)| (key.into(), field_locations(doc, &fields)))
+        .collect();
+    let truncated = budget.truncated.into_inner();
+
+    if data.is_empty() {
+        return Ok(cfg.empty_selection.response());
+    }
+
+    // Select nullable top-level fields to "fail", either a random number of them when the request has field errors
+    // or each one independently. For the sake of simplicity and performance, we won't traverse deeper into the
+    // response object.
+    let failed: Vec<(ByteString, Vec<Value>)> = match cfg.graphql_errors.field_error_ratio {
+        None => Vec::new(),
+        Some((numerator, denominator)) => match cfg.graphql_errors.field_error_mode {
+            FieldErrorMode::Request
+                if !nullable_keys.is_empty() && rng.random_ratio(numerator, denominator) =>
+            {
+                let fail_count = rng.random_range(1..=nullable_keys.len());
+                nullable_keys.into_iter().sample(rng, fail_count)
+            }
+            FieldErrorMode::Request => Vec::new(),
+            FieldErrorMode::Field => nullable_keys
+                .into_iter()
+                .filter(|_| rng.random_ratio(numerator, denominator))
+                .collect(),
+        },
+    };
 
-        data.retain(|key, _| !to_drop.contains(key));
+    if !failed.is_empty() {
+        for (key, _) in &failed {
+            data.insert(key.clone(), Value::Null);
+        }
 
-        let errors: Vec<Value> = to_drop
+        let errors: Vec<Value> = failed
             .into_iter()
-            .map(|key| {
-                json!({
+            .map(|(key, locations)| {
+                let mut error = json!({
                     "message": "Field error simulated",
                     "path": [key]
-                })
+                });
+                if !locations.is_empty() {
+                    error["locations"] = Value::Array(locations);
+                }
+                error
             })
             .collect();
 
-        Ok(json!({
-            "data": data,
-            "errors": errors,
-        }))
+        Ok(response_body(data, Some(errors), truncated))
     } else {
-        Ok(json!({ "data": data }))
+        Ok(response_body(data, None, truncated))
+    }
+}
+
+/// The line and column that each of `fields` starts at in the query, in the shape of a GraphQL error's `locations`
+fn field_locations(doc: &ExecutableDocument, fields: &[&Node<Field>]) -> Vec<Value> {
+    fields
+        .iter()
+        .filter_map(|field| field.line_column_range(&doc.sources))
+        .map(|range| json!({ "line": range.start.line, "column": range.start.column }))
+        .collect()
+}
+
+/// Assembles the response for generated `data`, noting in the response extensions if arrays were shrunk to stay
+/// within `max_response_nodes`.
+///
+/// `json!` isn't used for this since it round trips through [serde_json::Value], which doesn't preserve the order
+/// of the fields in `data`.
+fn response_body(
+    data: Map<ByteString, Value>,
+    errors: Option<Vec<Value>>,
+    truncated: bool,
+) -> Value {
+    let mut response = Map::new();
+    response.insert("data", Value::Object(data));
+    if let Some(errors) = errors {
+        response.insert("errors", Value::Array(errors));
+    }
+    if truncated {
+        response.insert("extensions", json!({ "truncated": true }));
+    }
+    Value::Object(response)
+}
+
+/// Adds an `extensions.__padding` string to `response` that brings it up to `padding_bytes` once serialized.
+/// Responses that are already that large, or whose `extensions` aren't an object, are left as they are.
+fn pad_response(response: &mut Value, padding_bytes: usize) {
+    let padding = |response: &mut Value| {
+        response
+            .as_object_mut()?
+            .entry("extensions")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()?
+            .insert("__padding", Value::from(""));
+        let unpadded = serde_json::to_vec(response).ok()?.len();
+        padding_bytes.checked_sub(unpadded)
+    };
+
+    let mut padded = response.clone();
+    if let Some(padding) = padding(&mut padded) {
+        padded["extensions"]["__padding"] = Value::from("x".repeat(padding));
+        *response = padded;
+    }
+}
+
+/// The media type of incremental responses, which clients opt in to with their `Accept` header
+const MULTIPART_MIXED: &str = "multipart/mixed";
+
+/// The `Content-Type` of incremental responses, in the format that the router sends them in
+const INCREMENTAL_CONTENT_TYPE: &str = "multipart/mixed;boundary=\"graphql\";deferSpec=20220824";
+
+/// The items of a list selected with `@stream` that come after its `initialCount`, which are sent incrementally
+struct StreamedItems {
+    /// The path to the list in the response
+    path: Vec<Value>,
+    /// The index in the list of the first streamed item
+    start: usize,
+    items: Vec<Value>,
+    label: Option<String>,
+}
+
+/// Splits the response to an operation that selects lists with `@stream` into an initial payload, with the first
+/// `initialCount` items of each of those lists, and an incremental payload for each of the rest of the items. Returns
+/// the `multipart/mixed` body of those payloads, or [None] if nothing is streamed. Lists nested inside streamed items
+/// are sent whole along with their item.
+fn incremental_response(
+    req: &GraphQLRequest,
+    schema: &FederatedSchema,
+    doc_hash: u64,
+    bytes: &Bytes,
+) -> Option<Bytes> {
+    let doc = parse_and_validate(req, schema, doc_hash).ok()?;
+    let op = doc.operations.get(req.operation_name.as_deref()).ok()?;
+    let mut response: Value = serde_json::from_slice(bytes).ok()?;
+
+    let mut streams = Vec::new();
+    split_streams(
+        &doc,
+        &op.selection_set,
+        &req.variables,
+        response.get_mut("data")?,
+        &mut Vec::new(),
+        &mut streams,
+    );
+    if streams.iter().all(|stream| stream.items.is_empty()) {
+        return None;
+    }
+
+    response
+        .as_object_mut()?
+        .insert("hasNext", Value::Bool(true));
+    let mut payloads = vec![response];
+    for stream in streams {
+        for (offset, item) in stream.items.into_iter().enumerate() {
+            let mut path = stream.path.clone();
+            path.push(Value::from(stream.start + offset));
+            let mut incremental = Map::new();
+            incremental.insert("items", Value::Array(vec![item]));
+            incremental.insert("path", Value::Array(path));
+            if let Some(label) = &stream.label {
+                incremental.insert("label", Value::from(label.as_str()));
+            }
+            payloads.push(json!({ "incremental": [incremental], "hasNext": true }));
+        }
+    }
+    if let Some(last) = payloads.last_mut().and_then(Value::as_object_mut) {
+        last.insert("hasNext", Value::Bool(false));
+    }
+
+    let mut body = Vec::new();
+    for payload in payloads {
+        body.extend_from_slice(b"\r\n--graphql\r\ncontent-type: application/json\r\n\r\n");
+        body.extend(serde_json::to_vec(&payload).ok()?);
+    }
+    body.extend_from_slice(b"\r\n--graphql--\r\n");
+    Some(body.into())
+}
+
+/// Takes the streamed items out of every list that `selection_set` selects with `@stream` in `value`, which is at
+/// `path` in the response, adding them to `streams`
+fn split_streams(
+    doc: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    variables: &JsonMap,
+    value: &mut Value,
+    path: &mut Vec<Value>,
+    streams: &mut Vec<StreamedItems>,
+) {
+    match value {
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push(Value::from(index));
+                split_streams(doc, selection_set, variables, item, path, streams);
+                path.pop();
+            }
+        }
+        Value::Object(object) => {
+            split_object_streams(doc, selection_set, variables, object, path, streams)
+        }
+        _ => {}
+    }
+}
+
+/// [split_streams] for an object, including the fields that its fragments select
+fn split_object_streams(
+    doc: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    variables: &JsonMap,
+    object: &mut Map<ByteString, Value>,
+    path: &mut Vec<Value>,
+    streams: &mut Vec<StreamedItems>,
+) {
+    for selection in &selection_set.selections {
+        let field = match selection {
+            Selection::Field(field) => field,
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = doc.fragments.get(&spread.fragment_name) {
+                    split_object_streams(
+                        doc,
+                        &fragment.selection_set,
+                        variables,
+                        object,
+                        path,
+                        streams,
+                    );
+                }
+                continue;
+            }
+            Selection::InlineFragment(inline) => {
+                split_object_streams(doc, &inline.selection_set, variables, object, path, streams);
+                continue;
+            }
+        };
+        let key = field.response_key();
+        let Some(child) = object.get_mut(key.as_str()) else {
+            continue;
+        };
+        path.push(Value::from(key.as_str()));
+
+        // A list that is selected more than once has already been split the first time
+        if let Some((initial_count, label)) = stream_arguments(field, variables)
+            && let Value::Array(items) = child
+            && items.len() > initial_count
+        {
+            streams.push(StreamedItems {
+                path: path.clone(),
+                start: initial_count,
+                items: items.split_off(initial_count),
+                label,
+            });
+        }
+        split_streams(doc, &field.selection_set, variables, child, path, streams);
+        path.pop();
     }
 }
 
+/// The `initialCount` and `label` of a field's `@stream`, unless it has none or it is turned off with `if: false`
+fn stream_arguments(field: &Field, variables: &JsonMap) -> Option<(usize, Option<String>)> {
+    let directive = field.directives.get("stream")?;
+    let argument = |name: &str| match directive.specified_argument_by_name(name)?.as_ref() {
+        ast::Value::Variable(variable) => variables.get(variable.as_str()).cloned(),
+        ast::Value::Int(int) => int.try_to_i32().ok().map(Value::from),
+        ast::Value::Boolean(boolean) => Some(Value::Bool(*boolean)),
+        ast::Value::String(string) => Some(Value::from(string.as_str())),
+        _ => None,
+    };
+
+    if argument("if").and_then(|value| value.as_bool()) == Some(false) {
+        return None;
+    }
+    let initial_count = argument("initialCount")
+        .and_then(|value| value.as_u64())
+        .unwrap_or_default();
+    let label = argument("label").and_then(|value| value.as_str().map(str::to_string));
+    Some((initial_count as usize, label))
+}
+
 pub type Ratio = (u32, u32);
 
 #[derive(Debug, Default, Clone, Hash, Serialize, Deserialize)]
@@ -313,7 +1545,9 @@ pub struct GraphQLErrorConfig {
     ///
     /// Defaults to no requests containing errors.
     pub request_error_ratio: Option<Ratio>,
-    /// The ratio of GraphQL requests that should include field-level errors and partial data.
+    /// The ratio of GraphQL requests that should include field-level errors and partial data. Only nullable
+    /// top-level fields fail, and they are responded to as null with an error giving their `path` and `locations`
+    /// in the query. Requests that don't select any nullable top-level fields never have field errors.
     /// Note that if both this field and the request error ratio are set, this ratio will be applicable
     /// to the subset of requests that do not have request errors.
     ///
@@ -322,416 +1556,3782 @@ pub struct GraphQLErrorConfig {
     ///
     /// Defaults to no requests containing errors.
     pub field_error_ratio: Option<Ratio>,
+    /// Whether `field_error_ratio` is the ratio of requests with field errors, or of fields that fail.
+    ///
+    /// Defaults to per request.
+    #[serde(default)]
+    pub field_error_mode: FieldErrorMode,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
-pub struct ResponseGenerationConfig {
-    #[serde(default = "default_scalar_config")]
-    pub scalars: BTreeMap<String, ScalarGenerator>,
-    #[serde(default = "default_array_size")]
-    pub array: ArraySize,
-    #[serde(default = "default_null_ratio")]
-    pub null_ratio: Option<Ratio>,
+/// What `field_error_ratio` applies to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldErrorMode {
+    /// The ratio of requests with field errors, each of which fails a random number of its nullable top-level
+    /// fields
+    #[default]
+    Request,
+    /// The ratio of nullable top-level fields that fail, independently of each other
+    Field,
+}
+
+/// Which types are generated as Relay-style connections. A connection's `edges` (and `nodes`, if it has them) hold
+/// one page of items, each edge's `cursor` points at its item, and `pageInfo` and `totalCount` describe that page.
+#[derive(Debug, Default, Clone, Hash, Serialize, Deserialize)]
+pub struct ConnectionConfig {
+    /// Whether connections are generated consistently. Any object type named `*Connection` that has an `edges`
+    /// field is treated as a connection.
     #[serde(default)]
-    pub header_ratio: BTreeMap<String, (u32, u32)>,
+    pub enabled: bool,
+    /// Further types to treat as connections, for schemas that don't follow the naming convention
     #[serde(default)]
-    pub http_error_ratio: Option<Ratio>,
+    pub types: BTreeSet<String>,
+}
+
+/// How `id` fields are reused within a response. Every object of a type gets one of a few `id`s for that type, so
+/// that the same entity shows up in several places (like a user that is both `user` and a post's `author`) the way
+/// it would in a real graph, which client-side caches normalize by.
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct StableIdConfig {
+    /// Whether `id`s are reused. Only fields named `id` are affected.
     #[serde(default)]
-    pub graphql_errors: GraphQLErrorConfig,
+    pub enabled: bool,
+    /// How many distinct `id`s each type has in a response
+    #[serde(default = "default_ids_per_type")]
+    pub ids_per_type: usize,
+    /// Whether objects that share an `id` are the same entity, with the same values for all of their scalar fields.
+    /// Each type keeps a pool of the entities generated so far in a response, which lists add new members to and
+    /// other fields mostly link to, so that a post's `author` is usually one of the `users` in the same response.
+    /// Top level fields are generated one after another in this mode, since the pools depend on their order.
+    #[serde(default)]
+    pub relational: bool,
 }
 
-impl ResponseGenerationConfig {
-    /// Merges the default scalar config with the provided config, allowing users to specify a partial set of scalar
-    /// generators while inheriting the default configuration for those they do not specify.
-    pub(crate) fn merge_default_scalars(&mut self) {
-        let default = default_scalar_config();
-        let provided = mem::replace(&mut self.scalars, default);
-        self.scalars.extend(provided);
-    }
+fn default_ids_per_type() -> usize {
+    3
 }
 
-impl Default for ResponseGenerationConfig {
+impl Default for StableIdConfig {
     fn default() -> Self {
         Self {
-            scalars: default_scalar_config(),
-            array: default_array_size(),
-            null_ratio: default_null_ratio(),
-            header_ratio: BTreeMap::new(),
-            graphql_errors: GraphQLErrorConfig::default(),
-            http_error_ratio: None,
+            enabled: false,
+            ids_per_type: default_ids_per_type(),
+            relational: false,
         }
     }
 }
 
-fn default_scalar_config() -> BTreeMap<String, ScalarGenerator> {
-    [
-        ("Boolean".into(), ScalarGenerator::Bool),
-        ("Int".into(), ScalarGenerator::Int { min: 0, max: 100 }),
-        ("ID".into(), ScalarGenerator::Int { min: 0, max: 100 }),
-        (
-            "Float".into(),
-            ScalarGenerator::Float {
-                min: OrderedFloat(-1.0),
-                max: OrderedFloat(1.0),
-            },
-        ),
-        (
-            "String".into(),
-            ScalarGenerator::String {
-                min_len: 1,
-                max_len: 10,
-            },
-        ),
-    ]
-    .into_iter()
-    .collect()
-}
-
-fn default_array_size() -> ArraySize {
-    ArraySize {
-        min_length: 0,
-        max_length: 10,
+impl StableIdConfig {
+    fn is_relational(&self) -> bool {
+        self.enabled && self.relational
     }
 }
 
-fn default_null_ratio() -> Option<Ratio> {
-    Some((1, 2))
+/// Filtering of the fields that are generated by their `@tag`s, to mimic a contract variant of the graph. A field's
+/// tags are the ones on its definition, on the type it belongs to and on the type it returns.
+#[derive(Debug, Default, Clone, Hash, Serialize, Deserialize)]
+pub struct ContractConfig {
+    /// When not empty, only fields with at least one of these tags are generated
+    #[serde(default)]
+    pub include_tags: BTreeSet<String>,
+    /// Fields with any of these tags are left out of responses, even if they also have an included tag
+    #[serde(default)]
+    pub exclude_tags: BTreeSet<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash)]
-#[serde(tag = "type", rename_all = "lowercase")]
-pub enum ScalarGenerator {
-    Bool,
-    Float {
-        min: OrderedFloat<f64>,
-        max: OrderedFloat<f64>,
-    },
-    Int {
-        min: i32,
-        max: i32,
-    },
-    String {
-        min_len: usize,
-        max_len: usize,
-    },
-}
+impl ContractConfig {
+    /// Whether `field`, selected on `parent_type`, is part of the contract. Introspection fields always are.
+    fn includes(&self, schema: &Schema, parent_type: &Name, field: &Field) -> bool {
+        if (self.include_tags.is_empty() && self.exclude_tags.is_empty())
+            || field.name.starts_with("__")
+        {
+            return true;
+        }
 
-impl Default for ScalarGenerator {
-    fn default() -> Self {
-        Self::DEFAULT
+        let type_directives = |name: &Name| {
+            schema.types.get(name).into_iter().flat_map(|ty| {
+                ty.directives()
+                    .get_all("tag")
+                    .map(|directive| &***directive)
+            })
+        };
+        let tags: Vec<&str> = field
+            .definition
+            .directives
+            .get_all("tag")
+            .map(|directive| &**directive)
+            .chain(type_directives(parent_type))
+            .chain(type_directives(field.ty().inner_named_type()))
+            .filter_map(|directive| directive.specified_argument_by_name("name")?.as_str())
+            .collect();
+
+        (self.include_tags.is_empty() || tags.iter().any(|tag| self.include_tags.contains(*tag)))
+            && !tags.iter().any(|tag| self.exclude_tags.contains(*tag))
     }
 }
 
-impl ScalarGenerator {
-    const DEFAULT: Self = Self::String {
-        min_len: 1,
-        max_len: 10,
-    };
+/// The response to an operation without any fields to generate
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptySelection {
+    /// `{"data": null}`
+    Null,
+    /// `{"data": {}}`
+    #[default]
+    EmptyObject,
+    /// `{"data": null}` with a GraphQL error explaining that nothing was selected
+    Error,
+}
 
-    fn generate(&self, rng: &mut ThreadRng) -> anyhow::Result<Value> {
-        let val = match *self {
-            Self::Bool => Value::Bool(rng.random_bool(0.5)),
-            Self::Int { min, max } => Value::Number(rng.random_range(min..=max).into()),
+impl EmptySelection {
+    fn response(self) -> Value {
+        match self {
+            Self::Null => json!({ "data": null }),
+            Self::EmptyObject => json!({ "data": {} }),
+            Self::Error => {
+                json!({ "data": null, "errors": [{ "message": "Operation selected no fields" }] })
+            }
+        }
+    }
+}
 
-            Self::Float { min, max } => Value::Number(
-                Number::from_f64(rng.random_range(*min..=*max)).expect("expected finite float"),
-            ),
+/// A way of corrupting a serialized response for `malformed_response_ratio`. Every style is deliberately invalid, and
+/// only exists to test how clients cope with subgraphs that send broken responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedResponseStyle {
+    /// The response cut off part way through, as if the connection dropped while it was being sent
+    Truncated,
+    /// `data` set to the whole response as a JSON string, rather than to an object
+    DataString,
+    /// An HTML error page, like one a misconfigured proxy might send
+    NotJson,
+    /// No body at all
+    Empty,
+}
 
-            // The default Arbitrary impl for String has a random length so we build based on
-            // characters instead
-            Self::String { min_len, max_len } => {
-                let len = rng.random_range(min_len..=max_len);
-                // Allow for some multibyte chars. May still need to realloc
-                let mut chars = Vec::with_capacity(len * 2);
-                for _ in 0..len {
-                    chars.push(rng.sample(rand::distr::Alphanumeric) as char);
-                }
+impl MalformedResponseStyle {
+    const ALL: [Self; 4] = [
+        Self::Truncated,
+        Self::DataString,
+        Self::NotJson,
+        Self::Empty,
+    ];
 
-                Value::String(ByteString::from(chars.into_iter().collect::<String>()))
+    fn apply<R: Rng + ?Sized>(self, rng: &mut R, bytes: &Bytes) -> Bytes {
+        match self {
+            // Cutting off at least one byte makes sure that the result doesn't parse
+            Self::Truncated => bytes.slice(..rng.random_range(0..bytes.len().max(1))),
+            Self::DataString => {
+                let data = Value::String(String::from_utf8_lossy(bytes).into_owned().into());
+                let mut response = Map::new();
+                response.insert("data", data);
+                serde_json::to_vec(&response)
+                    .map(Bytes::from)
+                    .unwrap_or_default()
             }
-        };
-
-        Ok(val)
+            Self::NotJson => Bytes::from_static(
+                b"<html><head><title>502 Bad Gateway</title></head><body><h1>502 Bad Gateway</h1></body></html>",
+            ),
+            Self::Empty => Bytes::new(),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash)]
-pub struct ArraySize {
-    pub min_length: usize,
-    pub max_length: usize,
+fn default_malformed_response_styles() -> BTreeSet<MalformedResponseStyle> {
+    MalformedResponseStyle::ALL.into()
 }
 
-impl ArraySize {
-    fn range(&self) -> RangeInclusive<usize> {
-        self.min_length..=self.max_length
+/// Corrupts `bytes` in one of the configured styles if `malformed_response_ratio` picks this response
+fn malformed_response<R: Rng + ?Sized>(
+    rng: &mut R,
+    rgen_cfg: &ResponseGenerationConfig,
+    bytes: &Bytes,
+) -> Option<Bytes> {
+    let (numerator, denominator) = rgen_cfg.malformed_response_ratio?;
+    if !rng.random_ratio(numerator, denominator) {
+        return None;
     }
+
+    let style = *rgen_cfg.malformed_response_styles.iter().choose(rng)?;
+    debug!(?style, "sending malformed response");
+    Some(style.apply(rng, bytes))
 }
 
-struct ResponseBuilder<'a, 'doc, 'schema> {
-    rng: &'a mut ThreadRng,
-    doc: &'doc Valid<ExecutableDocument>,
-    schema: &'schema FederatedSchema,
-    cfg: &'a ResponseGenerationConfig,
+/// Whether the `Accept` header of a request with `request_headers` lists `media_type`, with a quality above zero
+fn accepts(request_headers: &HeaderMap, media_type: &str) -> bool {
+    request_headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let is_media_type = params
+                .next()
+                .is_some_and(|accepted| accepted.eq_ignore_ascii_case(media_type));
+            // A quality of zero means that the client does not accept the media type at all
+            let is_refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .is_some_and(|quality| quality == 0.0)
+            });
+            is_media_type && !is_refused
+        })
 }
 
-impl<'a, 'doc, 'schema> ResponseBuilder<'a, 'doc, 'schema> {
-    fn new(
-        rng: &'a mut ThreadRng,
-        doc: &'doc Valid<ExecutableDocument>,
-        schema: &'schema FederatedSchema,
-        cfg: &'a ResponseGenerationConfig,
-    ) -> Self {
-        Self {
-            rng,
-            doc,
-            schema,
-            cfg,
+/// The media type that GraphQL responses are sent with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum ResponseContentType {
+    #[default]
+    #[serde(rename = "application/json")]
+    Json,
+    /// The media type introduced by the GraphQL-over-HTTP spec
+    #[serde(rename = "application/graphql-response+json")]
+    GraphQLResponseJson,
+    /// `application/graphql-response+json` when the request's `Accept` header lists it, and `application/json`
+    /// otherwise
+    #[serde(rename = "negotiate")]
+    Negotiate,
+}
+
+impl ResponseContentType {
+    const JSON: &str = "application/json";
+    const GRAPHQL_RESPONSE_JSON: &str = "application/graphql-response+json";
+
+    /// The media type to respond to a request with `request_headers` with
+    fn negotiate(self, request_headers: &HeaderMap) -> &'static str {
+        match self {
+            Self::Json => Self::JSON,
+            Self::GraphQLResponseJson => Self::GRAPHQL_RESPONSE_JSON,
+            Self::Negotiate if accepts(request_headers, Self::GRAPHQL_RESPONSE_JSON) => {
+                Self::GRAPHQL_RESPONSE_JSON
+            }
+            Self::Negotiate => Self::JSON,
         }
     }
 
-    fn selection_set(
-        &mut self,
-        selection_set: &SelectionSet,
-    ) -> anyhow::Result<Map<ByteString, Value>> {
-        let grouped_fields = self.collect_fields(selection_set)?;
-        let mut result = Map::new();
-
-        for (key, fields) in grouped_fields {
-            // The first occurrence of a field is representative for metadata that is defined by the schema
-            let meta_field = fields[0];
-
-            let val = if meta_field.name == "__typename" {
-                let selection_type = if let Some(union_schema_ty) = self
-                    .schema
-                    .types
-                    .get(&selection_set.ty)
-                    .and_then(|t| t.as_union())
-                {
-                    // pick a specific member of the union, rather than using the union name
-                    self.arbitrary_union_member(union_schema_ty)?.to_string()
-                } else {
-                    selection_set.ty.to_string()
-                };
-                Value::String(ByteString::from(selection_type))
-            } else if meta_field.name == "_service" {
-                let mut service_obj = Map::new();
-                service_obj.insert("sdl".to_string(), Value::String(self.schema.sdl().into()));
-                Value::Object(service_obj)
-            } else if !meta_field.ty().is_non_null() && self.should_be_null() {
-                Value::Null
-            } else {
-                let is_selection_set = !meta_field.selection_set.is_empty();
-                let is_array = meta_field.ty().is_list();
+    /// The HTTP status of responses to requests that fail before execution, like those that don't validate. The
+    /// GraphQL-over-HTTP spec has `application/json` responses use 200 for these, so that clients that predate it
+    /// still read the errors, and `application/graphql-response+json` responses use 400.
+    fn request_error_status(self, request_headers: &HeaderMap) -> StatusCode {
+        match self.negotiate(request_headers) {
+            Self::GRAPHQL_RESPONSE_JSON => StatusCode::BAD_REQUEST,
+            _ => StatusCode::OK,
+        }
+    }
+}
 
-                if is_selection_set {
-                    let mut selections = Vec::new();
-                    for field in fields {
-                        selections.extend_from_slice(&field.selection_set.selections);
-                    }
-                    let full_selection_set = SelectionSet {
-                        ty: meta_field.selection_set.ty.clone(),
-                        selections,
-                    };
-
-                    if is_array {
-                        Value::Array(self.array_selection_set(&full_selection_set)?)
-                    } else {
-                        Value::Object(self.selection_set(&full_selection_set)?)
-                    }
-                } else {
-                    match is_array {
-                        false => self.leaf_field(meta_field.ty().inner_named_type())?,
-                        true => self.array_leaf_field(meta_field.ty().inner_named_type())?,
-                    }
-                }
-            };
+/// A requirement on an incoming request header. Requests that do not satisfy the condition are
+/// short-circuited with the configured status before any response generation happens.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct HeaderCondition {
+    /// The exact value the header must have. If unset, the header only needs to be present.
+    #[serde(default)]
+    pub value: Option<String>,
+    /// The HTTP status to respond with when the condition is not met.
+    pub status: u16,
+}
 
-            result.insert(key, val);
+impl HeaderCondition {
+    fn is_satisfied_by(&self, header: Option<&HeaderValue>) -> bool {
+        match (header, &self.value) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(actual), Some(expected)) => actual.as_bytes() == expected.as_bytes(),
         }
-
-        Ok(result)
     }
+}
 
-    fn collect_fields(
-        &self,
-        selection_set: &'doc SelectionSet,
-    ) -> anyhow::Result<HashMap<String, Vec<&'doc Node<Field>>>> {
-        let mut collected_fields: HashMap<String, Vec<&Node<Field>>> = HashMap::new();
-
-        for selection in &selection_set.selections {
-            match selection {
-                Selection::Field(field) => {
-                    let key = field.alias.as_ref().unwrap_or(&field.name).to_string();
-                    collected_fields.entry(key).or_default().push(field);
-                }
-                Selection::FragmentSpread(fragment) => {
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct ResponseGenerationConfig {
+    #[serde(default = "default_scalar_config")]
+    pub scalars: BTreeMap<String, ScalarConfig>,
+    #[serde(default = "default_array_size")]
+    pub array: ArraySize,
+    /// Whether list fields with an integer argument named in `pagination_args` are capped at that many items, so
+    /// that paginated queries like `posts(first: 5)` get a sensible number of results.
+    ///
+    /// Defaults to false.
+    #[serde(default)]
+    pub respect_pagination_args: bool,
+    /// The names of the arguments that cap list lengths when `respect_pagination_args` is set.
+    ///
+    /// Defaults to `first`, `last` and `limit`.
+    #[serde(default = "default_pagination_args")]
+    pub pagination_args: BTreeSet<String>,
+    /// Whether enum values marked `@deprecated` are left out of generated responses, as a real server would
+    /// rarely return them. They are still used for enums whose values are all deprecated.
+    ///
+    /// Defaults to true.
+    #[serde(default = "default_exclude_deprecated_enum_values")]
+    pub exclude_deprecated_enum_values: bool,
+    /// Timestamp fields that are never earlier than a sibling field in the same object, keyed by the later field's
+    /// name. When both are selected and are RFC 3339 timestamps, such as those of the `datetime` scalar generator,
+    /// a later field that came out earlier is moved to between its sibling and now.
+    ///
+    /// Defaults to `updatedAt` following `createdAt`.
+    #[serde(default = "default_ordered_timestamps")]
+    pub ordered_timestamps: BTreeMap<String, String>,
+    /// Generation of Relay-style connections whose edges, cursors and page info are consistent with each other.
+    ///
+    /// Defaults to generating connections field by field, like any other type.
+    #[serde(default)]
+    pub connections: ConnectionConfig,
+    /// Reuse of `id`s within a response, so that the same entity can show up in several places.
+    ///
+    /// Defaults to generating every `id` independently.
+    #[serde(default)]
+    pub stable_ids: StableIdConfig,
+    /// Which fields are generated, by their `@tag`s, to mimic a contract variant of the graph.
+    ///
+    /// Defaults to generating every field that is selected.
+    #[serde(default)]
+    pub contract: ContractConfig,
+    #[serde(default = "default_null_ratio")]
+    pub null_ratio: Option<Ratio>,
+    #[serde(default)]
+    pub header_ratio: BTreeMap<String, (u32, u32)>,
+    /// Names of request headers to copy onto the response, prefixed with `x-echo-`.
+    #[serde(default)]
+    pub echo_request_headers: Vec<String>,
+    /// Response headers whose values are rendered from the request they respond to. Templates can refer to
+    /// `{operation_name}`, `{subgraph}` and `{request_id}`, which are empty when the request doesn't have one. Like
+    /// the configured `headers`, each is only sent some of the time if it has a `header_ratio`.
+    ///
+    /// Defaults to no templated headers.
+    #[serde(default)]
+    pub header_templates: BTreeMap<String, String>,
+    /// Request headers that must be present (and optionally match a value) for a request to be served.
+    #[serde(default)]
+    pub header_conditions: BTreeMap<String, HeaderCondition>,
+    /// The entity types that `_entities` is allowed to resolve. This is most useful as a subgraph override so
+    /// that each subgraph only returns the entities it owns.
+    ///
+    /// Defaults to allowing every member of the `_Entity` union.
+    #[serde(default)]
+    pub entity_types: Option<BTreeSet<String>>,
+    /// The ratio of requests that hang without a response, to exercise request timeouts. A hanging request is
+    /// responded to as usual once `hang_duration` has passed, or with a 503 if the server shuts down first.
+    ///
+    /// Defaults to never hanging.
+    #[serde(default)]
+    pub hang_ratio: Option<Ratio>,
+    /// How long requests picked by `hang_ratio` hang for.
+    ///
+    /// Defaults to hanging until the server shuts down.
+    #[serde(default, with = "humantime_serde")]
+    pub hang_duration: Option<Duration>,
+    #[serde(default)]
+    pub http_error_ratio: Option<Ratio>,
+    /// The ratio of generated responses that are deliberately corrupted after being serialized, and still sent with a
+    /// 200 status. **This is for fault injection testing only**: it makes the mock break the GraphQL-over-HTTP spec on
+    /// purpose, to check that clients such as the router handle non-conformant subgraphs gracefully. Only successfully
+    /// generated responses are corrupted: response fixtures, overrides and error responses are sent as they are, with
+    /// their own status.
+    ///
+    /// Defaults to never corrupting responses.
+    #[serde(default)]
+    pub malformed_response_ratio: Option<Ratio>,
+    /// The ways that `malformed_response_ratio` corrupts responses, one of which is picked at random each time.
+    ///
+    /// Defaults to every style.
+    #[serde(default = "default_malformed_response_styles")]
+    pub malformed_response_styles: BTreeSet<MalformedResponseStyle>,
+    /// The ratio of requests whose connection is aborted without a response, to simulate connections being reset.
+    /// HTTP/1 connections are closed, while only the request's stream is reset for HTTP/2.
+    ///
+    /// Defaults to never resetting connections.
+    #[serde(default)]
+    pub reset_ratio: Option<Ratio>,
+    /// The number of seconds to send in the `Retry-After` header of 503 responses injected by `http_error_ratio`, and
+    /// of 429 and 503 responses forced with the [FORCE_STATUS_HEADER].
+    ///
+    /// Defaults to omitting the header.
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+    #[serde(default)]
+    pub graphql_errors: GraphQLErrorConfig,
+    /// Whether queries may select `__schema` or `__type`. When disabled, they are responded to with a GraphQL error
+    /// instead, like a production server that has introspection turned off. `__typename` is always allowed.
+    ///
+    /// Defaults to true.
+    #[serde(default = "default_introspection")]
+    pub introspection: bool,
+    /// The maximum depth of nested selection sets that will be generated. Queries that go any deeper are
+    /// responded to with a "Query depth limit exceeded" GraphQL error instead.
+    ///
+    /// Defaults to no limit.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// The HTTP status to respond with when `max_depth` is exceeded.
+    ///
+    /// Defaults to 200.
+    #[serde(default = "default_depth_limit_status")]
+    pub depth_limit_status: u16,
+    /// The HTTP status to respond with when a query fails to parse or validate, either 200 or 400. The error is
+    /// the same either way.
+    ///
+    /// Defaults to the status that the GraphQL-over-HTTP spec gives the response's `content_type`, which is 200 for
+    /// `application/json`. Set it to 400 to keep the status that every validation failure used to get.
+    #[serde(default)]
+    pub validation_error_status: Option<u16>,
+    /// A budget for the total number of values (fields and array items) in a single response. Once it has been
+    /// spent, arrays are shrunk to `array.min_length` and the response notes that it was truncated.
+    ///
+    /// Defaults to no limit.
+    #[serde(default)]
+    pub max_response_nodes: Option<usize>,
+    /// The size in bytes that generated responses are padded to with an `extensions.__padding` string, to test how
+    /// large responses are handled. Responses that are already at least this large aren't padded.
+    ///
+    /// Defaults to no padding.
+    #[serde(default)]
+    pub response_padding_bytes: Option<usize>,
+    /// The number of top level fields a query needs before they are generated in parallel. This reduces the
+    /// latency of very wide queries, at the cost of using more threads per request. Responses are always generated
+    /// sequentially with a `max_response_nodes` budget, so that they don't depend on scheduling.
+    ///
+    /// Defaults to always generating sequentially.
+    #[serde(default)]
+    pub parallel_generation_threshold: Option<usize>,
+    /// How to respond to an operation that selects no fields at all, or that can't be found in the document.
+    ///
+    /// Defaults to an empty `data` object.
+    #[serde(default)]
+    pub empty_selection: EmptySelection,
+    /// Literal responses for the operations with these names, which are sent verbatim instead of generated ones.
+    /// Anonymous operations can be given one by the SHA-256 hash of their query text as `sha256:<hex>`, the same way
+    /// as in `allowed_operations`. The query still has to be valid against the schema.
+    #[serde(default)]
+    pub response_overrides: BTreeMap<String, Value>,
+    /// When set, only these operations are served and every other operation gets a GraphQL error. Operations are
+    /// listed by name, or by the SHA-256 hash of their query text as `sha256:<hex>`.
+    ///
+    /// Defaults to allowing every operation.
+    #[serde(default)]
+    pub allowed_operations: Option<BTreeSet<String>>,
+    /// Operations that get a GraphQL error instead of being served, listed the same way as `allowed_operations`.
+    /// An operation on both lists is denied.
+    #[serde(default)]
+    pub denied_operations: BTreeSet<String>,
+    /// The `Content-Type` of GraphQL responses.
+    ///
+    /// Defaults to `application/json`.
+    #[serde(default)]
+    pub content_type: ResponseContentType,
+}
+
+impl ResponseGenerationConfig {
+    /// Starts building a config from the defaults, for use when embedding the mock
+    pub fn builder() -> ResponseGenerationConfigBuilder {
+        ResponseGenerationConfigBuilder::default()
+    }
+
+    /// Checks for values that deserialize successfully but can't be used to generate responses.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        StatusCode::from_u16(self.depth_limit_status)
+            .map_err(|_| anyhow!("invalid depth_limit_status: {}", self.depth_limit_status))?;
+        if self.stable_ids.enabled && self.stable_ids.ids_per_type == 0 {
+            return Err(anyhow!("stable_ids.ids_per_type must be at least 1"));
+        }
+        if let Some(status) = self.validation_error_status
+            && status != 200
+            && status != 400
+        {
+            return Err(anyhow!(
+                "invalid validation_error_status: {status}, must be 200 or 400"
+            ));
+        }
+        for (name, scalar_cfg) in &self.scalars {
+            scalar_cfg.validate(name)?;
+        }
+        for (name, template) in &self.header_templates {
+            HeaderName::try_from(name)
+                .map_err(|err| anyhow!("invalid header_templates name {name}: {err}"))?;
+            render_header_template(template, &HeaderTemplateVariables::default())
+                .map_err(|err| anyhow!("invalid header_templates.{name}: {err}"))?;
+        }
+        if self.malformed_response_ratio.is_some() && self.malformed_response_styles.is_empty() {
+            return Err(anyhow!(
+                "malformed_response_styles needs at least one style when malformed_response_ratio is set"
+            ));
+        }
+        if self.array.min_length > self.array.max_length {
+            return Err(anyhow!(
+                "array.min_length ({}) is greater than array.max_length ({})",
+                self.array.min_length,
+                self.array.max_length
+            ));
+        }
+        Ok(())
+    }
+
+    /// Describes every configured scalar generator whose name isn't a scalar type in `schema`, since those
+    /// generators would never be used. Unchanged default generators are skipped, as schemas needn't use every
+    /// scalar that there is a default for.
+    pub fn scalar_mismatches(&self, schema: &Schema) -> Vec<String> {
+        let defaults = default_scalar_config();
+
+        self.scalars
+            .iter()
+            .filter(|(name, generator)| defaults.get(*name) != Some(generator))
+            .filter_map(|(name, _)| {
+                let kind = match schema.types.get(name.as_str()) {
+                    None => return Some(format!("`{name}` is not defined in the schema")),
+                    Some(ExtendedType::Scalar(_)) => return None,
+                    Some(ExtendedType::Object(_)) => "an object type",
+                    Some(ExtendedType::Interface(_)) => "an interface type",
+                    Some(ExtendedType::Union(_)) => "a union type",
+                    Some(ExtendedType::Enum(_)) => "an enum type",
+                    Some(ExtendedType::InputObject(_)) => "an input object type",
+                };
+                Some(format!("`{name}` is {kind}, not a scalar"))
+            })
+            .collect()
+    }
+
+    /// The response override for `op`, which is keyed either by the operation's name or by the [query_hash] of the
+    /// document it is in, the same way as `allowed_operations`
+    fn response_override(&self, doc: &ExecutableDocument, op: &Operation) -> Option<&Value> {
+        if self.response_overrides.is_empty() {
+            return None;
+        }
+
+        op.name
+            .as_ref()
+            .and_then(|name| self.response_overrides.get(name.as_str()))
+            .or_else(|| {
+                // Requests are parsed into a document of their own, so its only source is the query text
+                let query = doc.sources.values().next()?.source_text();
+                self.response_overrides.get(&query_hash(query))
+            })
+    }
+
+    /// Describes every field in the `data` of a response override that the schema doesn't define, so that
+    /// overrides which have drifted from the schema are noticed. Fields of abstract types are only checked when the
+    /// override includes their `__typename`.
+    pub fn response_override_mismatches(&self, schema: &Schema) -> Vec<String> {
+        response_mismatches(&self.response_overrides, schema)
+    }
+
+    /// Merges the default scalar config with the provided config, allowing users to specify a partial set of scalar
+    /// generators while inheriting the default configuration for those they do not specify.
+    pub(crate) fn merge_default_scalars(&mut self) {
+        let default = default_scalar_config();
+        let provided = mem::replace(&mut self.scalars, default);
+        self.scalars.extend(provided);
+    }
+}
+
+impl Default for ResponseGenerationConfig {
+    fn default() -> Self {
+        Self {
+            scalars: default_scalar_config(),
+            array: default_array_size(),
+            respect_pagination_args: false,
+            pagination_args: default_pagination_args(),
+            exclude_deprecated_enum_values: default_exclude_deprecated_enum_values(),
+            ordered_timestamps: default_ordered_timestamps(),
+            connections: ConnectionConfig::default(),
+            stable_ids: StableIdConfig::default(),
+            contract: ContractConfig::default(),
+            null_ratio: default_null_ratio(),
+            header_ratio: BTreeMap::new(),
+            echo_request_headers: Vec::new(),
+            header_templates: BTreeMap::new(),
+            header_conditions: BTreeMap::new(),
+            entity_types: None,
+            graphql_errors: GraphQLErrorConfig::default(),
+            hang_ratio: None,
+            hang_duration: None,
+            http_error_ratio: None,
+            reset_ratio: None,
+            malformed_response_ratio: None,
+            malformed_response_styles: default_malformed_response_styles(),
+            retry_after: None,
+            introspection: default_introspection(),
+            max_depth: None,
+            depth_limit_status: default_depth_limit_status(),
+            validation_error_status: None,
+            max_response_nodes: None,
+            response_padding_bytes: None,
+            parallel_generation_threshold: None,
+            empty_selection: EmptySelection::default(),
+            response_overrides: BTreeMap::new(),
+            allowed_operations: None,
+            denied_operations: BTreeSet::new(),
+            content_type: ResponseContentType::default(),
+        }
+    }
+}
+
+/// Describes every field in the `data` of the canned `responses`, keyed by operation name, that the schema doesn't
+/// define. Used for both [ResponseGenerationConfig::response_overrides] and response fixture files.
+pub fn response_mismatches(responses: &BTreeMap<String, Value>, schema: &Schema) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let Some(query_type) = schema.root_operation(OperationType::Query) else {
+        return mismatches;
+    };
+
+    for (op_name, response) in responses {
+        match response.get("data") {
+            Some(data) if response.is_object() => {
+                override_mismatches(schema, op_name, query_type, data, &mut mismatches)
+            }
+            _ => mismatches.push(format!("`{op_name}` must be an object with a `data` field")),
+        }
+    }
+
+    mismatches
+}
+
+/// Checks the fields of `value`, which is being returned for `type_name`, against the schema
+fn override_mismatches(
+    schema: &Schema,
+    op_name: &str,
+    type_name: &Name,
+    value: &Value,
+    mismatches: &mut Vec<String>,
+) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                override_mismatches(schema, op_name, type_name, item, mismatches);
+            }
+        }
+        Value::Object(fields) => {
+            // Only a concrete object type says which fields are allowed
+            let type_name = match (schema.types.get(type_name), fields.get("__typename")) {
+                (_, Some(Value::String(typename))) => match Name::new(typename.as_str()) {
+                    Ok(typename) => typename,
+                    Err(_) => return,
+                },
+                (Some(ExtendedType::Object(_) | ExtendedType::Interface(_)), _) => {
+                    type_name.clone()
+                }
+                _ => return,
+            };
+            for (key, value) in fields {
+                if key.as_str() == "__typename" {
+                    continue;
+                }
+                match schema.type_field(&type_name, key.as_str()) {
+                    Ok(field) => override_mismatches(
+                        schema,
+                        op_name,
+                        field.ty.inner_named_type(),
+                        value,
+                        mismatches,
+                    ),
+                    Err(_) => mismatches.push(format!(
+                        "`{op_name}` returns `{type_name}.{}`, which is not defined in the schema",
+                        key.as_str()
+                    )),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Builds a [ResponseGenerationConfig] in code, starting from its defaults. Every setter corresponds to the config
+/// field of the same name, and [ResponseGenerationConfigBuilder::build] applies the same validation as loading a
+/// config file does.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseGenerationConfigBuilder {
+    config: ResponseGenerationConfig,
+}
+
+impl ResponseGenerationConfigBuilder {
+    /// Sets the generator for the scalar type `name`, replacing the default one if there is one
+    pub fn scalar(mut self, name: impl Into<String>, generator: impl Into<ScalarConfig>) -> Self {
+        self.config.scalars.insert(name.into(), generator.into());
+        self
+    }
+
+    pub fn array_size(mut self, min_length: usize, max_length: usize) -> Self {
+        self.config.array = ArraySize {
+            min_length,
+            max_length,
+        };
+        self
+    }
+
+    pub fn respect_pagination_args(mut self, respect_pagination_args: bool) -> Self {
+        self.config.respect_pagination_args = respect_pagination_args;
+        self
+    }
+
+    pub fn exclude_deprecated_enum_values(mut self, exclude_deprecated_enum_values: bool) -> Self {
+        self.config.exclude_deprecated_enum_values = exclude_deprecated_enum_values;
+        self
+    }
+
+    /// Orders the `later` timestamp field of objects after their `earlier` one
+    pub fn ordered_timestamp(
+        mut self,
+        later: impl Into<String>,
+        earlier: impl Into<String>,
+    ) -> Self {
+        self.config
+            .ordered_timestamps
+            .insert(later.into(), earlier.into());
+        self
+    }
+
+    /// Replaces the names of the arguments that cap list lengths
+    pub fn pagination_args<S: Into<String>>(
+        mut self,
+        pagination_args: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.config.pagination_args = pagination_args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn connections(mut self, connections: ConnectionConfig) -> Self {
+        self.config.connections = connections;
+        self
+    }
+
+    pub fn stable_ids(mut self, stable_ids: StableIdConfig) -> Self {
+        self.config.stable_ids = stable_ids;
+        self
+    }
+
+    pub fn contract(mut self, contract: ContractConfig) -> Self {
+        self.config.contract = contract;
+        self
+    }
+
+    pub fn null_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.null_ratio = ratio.into();
+        self
+    }
+
+    pub fn header_ratio(mut self, header_name: impl Into<String>, ratio: Ratio) -> Self {
+        self.config.header_ratio.insert(header_name.into(), ratio);
+        self
+    }
+
+    pub fn echo_request_header(mut self, header_name: impl Into<String>) -> Self {
+        self.config.echo_request_headers.push(header_name.into());
+        self
+    }
+
+    pub fn header_template(
+        mut self,
+        header_name: impl Into<String>,
+        template: impl Into<String>,
+    ) -> Self {
+        self.config
+            .header_templates
+            .insert(header_name.into(), template.into());
+        self
+    }
+
+    pub fn header_condition(
+        mut self,
+        header_name: impl Into<String>,
+        condition: HeaderCondition,
+    ) -> Self {
+        self.config
+            .header_conditions
+            .insert(header_name.into(), condition);
+        self
+    }
+
+    pub fn entity_types<S: Into<String>>(
+        mut self,
+        entity_types: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.config.entity_types = Some(entity_types.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn hang_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.hang_ratio = ratio.into();
+        self
+    }
+
+    pub fn hang_duration(mut self, duration: impl Into<Option<Duration>>) -> Self {
+        self.config.hang_duration = duration.into();
+        self
+    }
+
+    pub fn http_error_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.http_error_ratio = ratio.into();
+        self
+    }
+
+    pub fn malformed_response_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.malformed_response_ratio = ratio.into();
+        self
+    }
+
+    /// Replaces the ways that malformed responses are corrupted
+    pub fn malformed_response_styles(
+        mut self,
+        styles: impl IntoIterator<Item = MalformedResponseStyle>,
+    ) -> Self {
+        self.config.malformed_response_styles = styles.into_iter().collect();
+        self
+    }
+
+    pub fn reset_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.reset_ratio = ratio.into();
+        self
+    }
+
+    pub fn retry_after(mut self, seconds: impl Into<Option<u64>>) -> Self {
+        self.config.retry_after = seconds.into();
+        self
+    }
+
+    pub fn request_error_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.graphql_errors.request_error_ratio = ratio.into();
+        self
+    }
+
+    pub fn field_error_ratio(mut self, ratio: impl Into<Option<Ratio>>) -> Self {
+        self.config.graphql_errors.field_error_ratio = ratio.into();
+        self
+    }
+
+    pub fn field_error_mode(mut self, mode: FieldErrorMode) -> Self {
+        self.config.graphql_errors.field_error_mode = mode;
+        self
+    }
+
+    pub fn introspection(mut self, introspection: bool) -> Self {
+        self.config.introspection = introspection;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: impl Into<Option<usize>>) -> Self {
+        self.config.max_depth = max_depth.into();
+        self
+    }
+
+    pub fn depth_limit_status(mut self, status: u16) -> Self {
+        self.config.depth_limit_status = status;
+        self
+    }
+
+    pub fn validation_error_status(mut self, status: impl Into<Option<u16>>) -> Self {
+        self.config.validation_error_status = status.into();
+        self
+    }
+
+    pub fn max_response_nodes(mut self, max_response_nodes: impl Into<Option<usize>>) -> Self {
+        self.config.max_response_nodes = max_response_nodes.into();
+        self
+    }
+
+    pub fn response_padding_bytes(mut self, padding_bytes: impl Into<Option<usize>>) -> Self {
+        self.config.response_padding_bytes = padding_bytes.into();
+        self
+    }
+
+    pub fn parallel_generation_threshold(mut self, threshold: impl Into<Option<usize>>) -> Self {
+        self.config.parallel_generation_threshold = threshold.into();
+        self
+    }
+
+    pub fn empty_selection(mut self, empty_selection: EmptySelection) -> Self {
+        self.config.empty_selection = empty_selection;
+        self
+    }
+
+    pub fn response_override(mut self, operation_name: impl Into<String>, response: Value) -> Self {
+        self.config
+            .response_overrides
+            .insert(operation_name.into(), response);
+        self
+    }
+
+    pub fn allowed_operation(mut self, operation: impl Into<String>) -> Self {
+        self.config
+            .allowed_operations
+            .get_or_insert_default()
+            .insert(operation.into());
+        self
+    }
+
+    pub fn denied_operation(mut self, operation: impl Into<String>) -> Self {
+        self.config.denied_operations.insert(operation.into());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: ResponseContentType) -> Self {
+        self.config.content_type = content_type;
+        self
+    }
+
+    /// Validates the config, failing on values that can't be used to generate responses
+    pub fn build(self) -> Result<ResponseGenerationConfig, SubgraphMockError> {
+        self.config.validate().map_err(SubgraphMockError::Config)?;
+        Ok(self.config)
+    }
+}
+
+fn default_depth_limit_status() -> u16 {
+    200
+}
+
+fn default_scalar_config() -> BTreeMap<String, ScalarConfig> {
+    [
+        ("Boolean".into(), ScalarGenerator::Bool),
+        ("Int".into(), ScalarGenerator::Int { min: 0, max: 100 }),
+        ("ID".into(), ScalarGenerator::Int { min: 0, max: 100 }),
+        (
+            "Float".into(),
+            ScalarGenerator::Float {
+                min: OrderedFloat(-1.0),
+                max: OrderedFloat(1.0),
+            },
+        ),
+        (
+            "String".into(),
+            ScalarGenerator::String {
+                min_len: 1,
+                max_len: 10,
+            },
+        ),
+        // Common custom scalars whose values have to match a format
+        ("UUID".into(), ScalarGenerator::Uuid),
+        ("EmailAddress".into(), ScalarGenerator::Email),
+        ("URL".into(), ScalarGenerator::Url),
+        ("JSON".into(), ScalarGenerator::JSON),
+        ("JSONObject".into(), ScalarGenerator::JSON),
+        (
+            "DateTime".into(),
+            ScalarGenerator::DateTime {
+                max_age: ScalarGenerator::DATE_TIME_MAX_AGE,
+            },
+        ),
+        ("Long".into(), ScalarGenerator::INT64),
+        ("BigInt".into(), ScalarGenerator::INT64),
+        ("Int64".into(), ScalarGenerator::INT64),
+    ]
+    .into_iter()
+    .map(|(name, generator): (String, ScalarGenerator)| (name, generator.into()))
+    .collect()
+}
+
+fn default_array_size() -> ArraySize {
+    ArraySize {
+        min_length: 0,
+        max_length: 10,
+    }
+}
+
+fn default_pagination_args() -> BTreeSet<String> {
+    ["first", "last", "limit"].map(String::from).into()
+}
+
+fn default_exclude_deprecated_enum_values() -> bool {
+    true
+}
+
+fn default_ordered_timestamps() -> BTreeMap<String, String> {
+    [("updatedAt".to_string(), "createdAt".to_string())].into()
+}
+
+fn default_introspection() -> bool {
+    true
+}
+
+fn default_null_ratio() -> Option<Ratio> {
+    Some((1, 2))
+}
+
+/// How the values of a scalar type are generated: either by a single generator, or by one of several that is picked
+/// for every value in proportion to its weight
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(untagged)]
+pub enum ScalarConfig {
+    Single(ScalarGenerator),
+    Weighted(Vec<WeightedScalarGenerator>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct WeightedScalarGenerator {
+    pub weight: u32,
+    #[serde(flatten)]
+    pub generator: ScalarGenerator,
+}
+
+impl From<ScalarGenerator> for ScalarConfig {
+    fn from(generator: ScalarGenerator) -> Self {
+        Self::Single(generator)
+    }
+}
+
+impl ScalarConfig {
+    /// Checks every generator's ranges. Weighted generators also need at least one non-zero weight for there to be
+    /// anything to pick.
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Single(generator) => generator.validate(name),
+            Self::Weighted(generators) => {
+                if generators.iter().all(|weighted| weighted.weight == 0) {
+                    return Err(anyhow!(
+                        "scalars.{name} needs at least one generator with a weight above zero"
+                    ));
+                }
+                generators
+                    .iter()
+                    .try_for_each(|weighted| weighted.generator.validate(name))
+            }
+        }
+    }
+
+    fn generate<R: Rng + ?Sized>(&self, rng: &mut R, now: SystemTime) -> anyhow::Result<Value> {
+        match self {
+            Self::Single(generator) => generator.generate(rng, now),
+            Self::Weighted(generators) => generators
+                .choose_weighted(rng, |weighted| weighted.weight)?
+                .generator
+                .generate(rng, now),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ScalarGenerator {
+    Bool,
+    Float {
+        min: OrderedFloat<f64>,
+        max: OrderedFloat<f64>,
+    },
+    Int {
+        min: i32,
+        max: i32,
+    },
+    /// A 64-bit integer, for scalars like `Long` or `BigInt`. Sent as a string when `as_string` is set, since
+    /// JavaScript clients can't represent every 64-bit integer as a number.
+    Int64 {
+        min: i64,
+        max: i64,
+        #[serde(default)]
+        as_string: bool,
+    },
+    String {
+        min_len: usize,
+        max_len: usize,
+    },
+    /// A random (version 4) UUID
+    Uuid,
+    /// An email address at `example.com`
+    Email,
+    /// An `https` URL at `example.com`
+    Url,
+    /// A JSON object with arbitrary values, for scalars like `JSON`. Objects and arrays are nested at most
+    /// `max_depth` levels below it, and each holds at most `max_keys` entries.
+    Json {
+        #[serde(default = "default_json_max_depth")]
+        max_depth: usize,
+        #[serde(default = "default_json_max_keys")]
+        max_keys: usize,
+    },
+    /// An RFC 3339 timestamp in UTC, like `2024-05-01T12:30:00Z`, at most `max_age` before the time it is generated,
+    /// or before a fixed time for seeded and deterministic requests
+    DateTime {
+        #[serde(default = "default_date_time_max_age", with = "humantime_serde")]
+        max_age: Duration,
+    },
+}
+
+fn default_json_max_depth() -> usize {
+    2
+}
+
+fn default_json_max_keys() -> usize {
+    3
+}
+
+fn default_date_time_max_age() -> Duration {
+    ScalarGenerator::DATE_TIME_MAX_AGE
+}
+
+impl Default for ScalarGenerator {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl ScalarGenerator {
+    const DEFAULT: Self = Self::String {
+        min_len: 1,
+        max_len: 10,
+    };
+
+    /// The default for common 64-bit integer scalars, which covers every non-negative value
+    const INT64: Self = Self::Int64 {
+        min: 0,
+        max: i64::MAX,
+        as_string: false,
+    };
+
+    const JSON: Self = Self::Json {
+        max_depth: 2,
+        max_keys: 3,
+    };
+
+    const DATE_TIME_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+    /// Ensures that the range of values to generate isn't empty, since generating from it would panic
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        match *self {
+            Self::Int { min, max } if min > max => Err(anyhow!(
+                "scalars.{name}: min ({min}) is greater than max ({max})"
+            )),
+            Self::Int64 { min, max, .. } if min > max => Err(anyhow!(
+                "scalars.{name}: min ({min}) is greater than max ({max})"
+            )),
+            Self::Float { min, max } if !min.is_finite() || !max.is_finite() => Err(anyhow!(
+                "scalars.{name}: min ({min}) and max ({max}) must be finite"
+            )),
+            Self::Float { min, max } if min > max => Err(anyhow!(
+                "scalars.{name}: min ({min}) is greater than max ({max})"
+            )),
+            Self::String { min_len, max_len } if min_len > max_len => Err(anyhow!(
+                "scalars.{name}: min_len ({min_len}) is greater than max_len ({max_len})"
+            )),
+            Self::Json { max_keys: 0, .. } => {
+                Err(anyhow!("scalars.{name}: max_keys must be at least 1"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn generate<R: Rng + ?Sized>(&self, rng: &mut R, now: SystemTime) -> anyhow::Result<Value> {
+        let val = match *self {
+            Self::Bool => Value::Bool(rng.random_bool(0.5)),
+            Self::Int { min, max } => Value::Number(rng.random_range(min..=max).into()),
+            Self::Int64 {
+                min,
+                max,
+                as_string,
+            } => {
+                let int = rng.random_range(min..=max);
+                if as_string {
+                    Value::String(int.to_string().into())
+                } else {
+                    Value::Number(int.into())
+                }
+            }
+
+            Self::Float { min, max } => Value::Number(
+                Number::from_f64(rng.random_range(*min..=*max)).expect("expected finite float"),
+            ),
+
+            // The default Arbitrary impl for String has a random length so we build based on
+            // characters instead
+            Self::String { min_len, max_len } => {
+                let len = rng.random_range(min_len..=max_len);
+                // Allow for some multibyte chars. May still need to realloc
+                let mut chars = Vec::with_capacity(len * 2);
+                for _ in 0..len {
+                    chars.push(rng.sample(rand::distr::Alphanumeric) as char);
+                }
+
+                Value::String(ByteString::from(chars.into_iter().collect::<String>()))
+            }
+
+            Self::Uuid => {
+                let mut bytes: [u8; 16] = rng.random();
+                // Set the version (4, random) and the variant (RFC 9562)
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+                let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                Value::String(ByteString::from(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[0..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..32]
+                )))
+            }
+
+            Self::Email => {
+                let len = rng.random_range(3..=10);
+                Value::String(ByteString::from(format!(
+                    "{}@example.com",
+                    lowercase_alphanumeric(rng, len)
+                )))
+            }
+
+            Self::Url => {
+                let len = rng.random_range(1..=10);
+                Value::String(ByteString::from(format!(
+                    "https://example.com/{}",
+                    lowercase_alphanumeric(rng, len)
+                )))
+            }
+
+            Self::Json {
+                max_depth,
+                max_keys,
+            } => random_json_object(rng, max_depth, max_keys),
+
+            Self::DateTime { max_age } => {
+                let age = Duration::from_secs(rng.random_range(0..=max_age.as_secs()));
+                let timestamp = now.checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+                Value::String(
+                    humantime::format_rfc3339_seconds(timestamp)
+                        .to_string()
+                        .into(),
+                )
+            }
+        };
+
+        Ok(val)
+    }
+}
+
+fn lowercase_alphanumeric<R: Rng + ?Sized>(rng: &mut R, len: usize) -> String {
+    (0..len)
+        .map(|_| (rng.sample(rand::distr::Alphanumeric) as char).to_ascii_lowercase())
+        .collect()
+}
+
+/// An object with between 1 and `max_keys` keys, where each value may be nested up to `depth` more levels
+fn random_json_object<R: Rng + ?Sized>(rng: &mut R, depth: usize, max_keys: usize) -> Value {
+    let len = rng.random_range(1..=max_keys);
+    let object = (0..len)
+        .map(|_| {
+            let key_len = rng.random_range(1..=8);
+            (
+                ByteString::from(lowercase_alphanumeric(rng, key_len)),
+                random_json_value(rng, depth, max_keys),
+            )
+        })
+        .collect();
+
+    Value::Object(object)
+}
+
+/// Any JSON value, which is only an object or an array while there are levels of `depth` left to nest it in
+fn random_json_value<R: Rng + ?Sized>(rng: &mut R, depth: usize, max_keys: usize) -> Value {
+    match rng.random_range(0..if depth > 0 { 6 } else { 4 }) {
+        0 => Value::Null,
+        1 => Value::Bool(rng.random_bool(0.5)),
+        2 => Value::Number(rng.random_range(0..1000).into()),
+        3 => {
+            let len = rng.random_range(1..=10);
+            Value::String(lowercase_alphanumeric(rng, len).into())
+        }
+        4 => random_json_object(rng, depth - 1, max_keys),
+        _ => {
+            let len = rng.random_range(0..=max_keys);
+            Value::Array(
+                (0..len)
+                    .map(|_| random_json_value(rng, depth - 1, max_keys))
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash)]
+pub struct ArraySize {
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+impl ArraySize {
+    fn range(&self) -> RangeInclusive<usize> {
+        self.min_length..=self.max_length
+    }
+}
+
+/// Combines the selection sets of every field that contributes to the same response key
+fn merged_selection_set(fields: &[&Node<Field>]) -> SelectionSet {
+    let mut selections = Vec::new();
+    for field in fields {
+        selections.extend_from_slice(&field.selection_set.selections);
+    }
+    SelectionSet {
+        ty: fields[0].selection_set.ty.clone(),
+        selections,
+    }
+}
+
+/// The cursor for the item at `offset` in a connection, in the same format as the reference `graphql-relay`
+/// implementation: `arrayconnection:<offset>` encoded as base64
+fn encode_cursor(offset: usize) -> String {
+    STANDARD.encode(format!("arrayconnection:{offset}"))
+}
+
+/// The offset that a cursor made by [encode_cursor] points at
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    String::from_utf8(STANDARD.decode(cursor).ok()?)
+        .ok()?
+        .strip_prefix("arrayconnection:")?
+        .parse()
+        .ok()
+}
+
+/// Resolves an argument value into JSON, substituting the values of any variables it references.
+fn resolve_value(value: &ast::Value, variables: &JsonMap) -> Value {
+    match value {
+        ast::Value::Null => Value::Null,
+        ast::Value::Enum(name) => Value::String(name.as_str().into()),
+        ast::Value::Variable(name) => variables.get(name.as_str()).cloned().unwrap_or_default(),
+        ast::Value::String(string) => Value::String(string.as_str().into()),
+        ast::Value::Float(float) => float
+            .try_to_f64()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_default(),
+        ast::Value::Int(int) => int
+            .as_str()
+            .parse::<i64>()
+            .map(|int| Value::Number(int.into()))
+            .unwrap_or_default(),
+        ast::Value::Boolean(bool) => Value::Bool(*bool),
+        ast::Value::List(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| resolve_value(item, variables))
+                .collect(),
+        ),
+        ast::Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str().into(), resolve_value(value, variables)))
+                .collect(),
+        ),
+    }
+}
+
+/// Returned instead of a response when `reset_ratio` picks a request to simulate a connection reset for. The
+/// connection is aborted without a response rather than this being turned into one.
+#[derive(Debug)]
+pub struct ConnectionReset;
+
+impl std::fmt::Display for ConnectionReset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Simulated connection reset")
+    }
+}
+
+impl std::error::Error for ConnectionReset {}
+
+/// Returned instead of a response if the query nests selection sets deeper than the configured `max_depth`.
+#[derive(Debug)]
+struct DepthLimitExceeded;
+
+impl std::fmt::Display for DepthLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Query depth limit exceeded")
+    }
+}
+
+impl std::error::Error for DepthLimitExceeded {}
+
+/// Returned instead of a response if the query selects `__schema` or `__type` while `introspection` is disabled.
+#[derive(Debug)]
+struct IntrospectionDisabled;
+
+impl std::fmt::Display for IntrospectionDisabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "GraphQL introspection is not allowed, but the query contained __schema or __type",
+        )
+    }
+}
+
+impl std::error::Error for IntrospectionDisabled {}
+
+/// Returned instead of a response when a request error is injected by `request_error_ratio`.
+#[derive(Debug)]
+struct SimulatedRequestError;
+
+impl std::fmt::Display for SimulatedRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Request error simulated")
+    }
+}
+
+impl std::error::Error for SimulatedRequestError {}
+
+/// Whether `selection_set` selects `__schema` or `__type` anywhere, including through fragments.
+fn selects_introspection(doc: &ExecutableDocument, selection_set: &SelectionSet) -> bool {
+    selection_set
+        .selections
+        .iter()
+        .any(|selection| match selection {
+            Selection::Field(field) => {
+                matches!(field.name.as_str(), "__schema" | "__type")
+                    || selects_introspection(doc, &field.selection_set)
+            }
+            Selection::FragmentSpread(spread) => doc
+                .fragments
+                .get(&spread.fragment_name)
+                .is_some_and(|fragment| selects_introspection(doc, &fragment.selection_set)),
+            Selection::InlineFragment(inline) => selects_introspection(doc, &inline.selection_set),
+        })
+}
+
+/// Whether any field in `selection_set`, which is nested `depth` selection sets deep, leads to a selection set
+/// deeper than `max_depth`. Fragments don't count towards the depth since they don't nest the response.
+fn exceeds_depth(
+    doc: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    depth: usize,
+    max_depth: usize,
+) -> bool {
+    if depth > max_depth {
+        return true;
+    }
+
+    selection_set
+        .selections
+        .iter()
+        .any(|selection| match selection {
+            Selection::Field(field) => {
+                !field.selection_set.is_empty()
+                    && exceeds_depth(doc, &field.selection_set, depth + 1, max_depth)
+            }
+            Selection::FragmentSpread(spread) => doc
+                .fragments
+                .get(&spread.fragment_name)
+                .is_some_and(|fragment| {
+                    exceeds_depth(doc, &fragment.selection_set, depth, max_depth)
+                }),
+            Selection::InlineFragment(inline) => {
+                exceeds_depth(doc, &inline.selection_set, depth, max_depth)
+            }
+        })
+}
+
+/// Tracks the `max_response_nodes` budget across every builder that contributes to a response. Builders may be
+/// running in parallel when there is no budget to share.
+#[derive(Debug, Default)]
+struct NodeBudget {
+    /// The number of values generated so far
+    nodes: AtomicUsize,
+    /// Whether any array was shrunk because the budget was spent
+    truncated: AtomicBool,
+}
+
+struct ResponseBuilder<'a, 'doc, 'schema, R: Rng + ?Sized> {
+    rng: &'a mut R,
+    /// The time that generated timestamps are relative to, from [reference_time]
+    now: SystemTime,
+    doc: &'doc Valid<ExecutableDocument>,
+    schema: &'schema FederatedSchema,
+    cfg: &'a ResponseGenerationConfig,
+    variables: &'a JsonMap,
+    budget: &'a NodeBudget,
+    /// The innermost connection that is being generated
+    page: Option<Page>,
+    /// Seeds the `id`s that each type's objects share when `stable_ids` is enabled. The same for every builder
+    /// that contributes to a response.
+    id_seed: Option<u64>,
+    /// The type and index of the entity whose fields are being generated, in relational mode
+    entity: Option<(Name, usize)>,
+    /// The indexes of the entities of each type that have been generated so far, in relational mode
+    entity_pools: BTreeMap<Name, BTreeSet<usize>>,
+    /// The entity that the next selection set is if it is an item of a list, in relational mode
+    list_entity: Option<usize>,
+}
+
+/// The page of items that a connection holds, which its fields are all generated from
+#[derive(Debug)]
+struct Page {
+    connection_type: Name,
+    edge_type: Name,
+    page_info_type: Option<Name>,
+    /// The offset of the first item in the page
+    start: usize,
+    len: usize,
+    has_next_page: bool,
+    total_count: usize,
+    /// The index of the edge that is being generated, for its cursor
+    edge: Option<usize>,
+}
+
+impl<'a, 'doc, 'schema, R: Rng + ?Sized> ResponseBuilder<'a, 'doc, 'schema, R> {
+    fn new(
+        rng: &'a mut R,
+        now: SystemTime,
+        doc: &'doc Valid<ExecutableDocument>,
+        schema: &'schema FederatedSchema,
+        cfg: &'a ResponseGenerationConfig,
+        variables: &'a JsonMap,
+        budget: &'a NodeBudget,
+    ) -> Self {
+        let id_seed = cfg.stable_ids.enabled.then(|| rng.random());
+        Self {
+            rng,
+            now,
+            doc,
+            schema,
+            cfg,
+            variables,
+            budget,
+            page: None,
+            id_seed,
+            entity: None,
+            entity_pools: BTreeMap::new(),
+            list_entity: None,
+        }
+    }
+
+    /// Generates the top level selection set of an operation. If there are at least `parallel_generation_threshold`
+    /// top level fields, each is generated concurrently using its own RNG. Those RNGs are seeded from this builder's
+    /// RNG and the field's response key, so the result doesn't depend on how the fields are scheduled. That doesn't
+    /// hold for a `max_response_nodes` budget, which would go to whichever fields get to it first, or for relational
+    /// `stable_ids`, which share entities across fields, so responses with either are generated sequentially.
+    fn root_selection_set(
+        &mut self,
+        selection_set: &SelectionSet,
+    ) -> anyhow::Result<Map<ByteString, Value>> {
+        let grouped_fields = self.collect_fields(selection_set)?;
+        if self
+            .cfg
+            .parallel_generation_threshold
+            .is_none_or(|threshold| grouped_fields.len() < threshold)
+            || self.cfg.max_response_nodes.is_some()
+            || self.cfg.stable_ids.is_relational()
+        {
+            return self.grouped_fields(selection_set, grouped_fields);
+        }
+
+        let seed: u64 = self.rng.random();
+        let (now, doc, schema, cfg, variables, budget, id_seed) = (
+            self.now,
+            self.doc,
+            self.schema,
+            self.cfg,
+            self.variables,
+            self.budget,
+            self.id_seed,
+        );
+
+        let values: Vec<(ByteString, Value)> = grouped_fields
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(key, fields)| {
+                let mut hasher = DefaultHasher::new();
+                seed.hash(&mut hasher);
+                key.hash(&mut hasher);
+                let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+                let mut builder =
+                    ResponseBuilder::new(&mut rng, now, doc, schema, cfg, variables, budget);
+                builder.id_seed = id_seed;
+                let value = builder.field(selection_set, &fields)?;
+                budget.nodes.fetch_add(1, Ordering::Relaxed);
+                Ok((ByteString::from(key), value))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(values.into_iter().collect())
+    }
+
+    fn selection_set(
+        &mut self,
+        selection_set: &SelectionSet,
+    ) -> anyhow::Result<Map<ByteString, Value>> {
+        let grouped_fields = self.collect_fields(selection_set)?;
+        let list_entity = self.list_entity.take();
+        let entity = self.pick_entity(&selection_set.ty, list_entity);
+        let outer = mem::replace(&mut self.entity, entity);
+        let result = self.grouped_fields(selection_set, grouped_fields);
+        self.entity = outer;
+        result
+    }
+
+    /// Picks the entity that an object of type `ty` is in relational mode, if its type has an `id` field. Items of
+    /// lists are given their entity, so that lists don't repeat themselves, while other objects link to an existing
+    /// entity three times out of four.
+    fn pick_entity(&mut self, ty: &Name, list_entity: Option<usize>) -> Option<(Name, usize)> {
+        if !self.cfg.stable_ids.is_relational()
+            || !self
+                .schema
+                .types
+                .get(ty)
+                .and_then(|ty| ty.as_object())
+                .is_some_and(|object| object.fields.contains_key("id"))
+        {
+            return None;
+        }
+
+        let ids_per_type = self.cfg.stable_ids.ids_per_type;
+        let pool = self.entity_pools.entry(ty.clone()).or_default();
+        let index = match list_entity {
+            Some(index) => index,
+            None if pool.len() < ids_per_type
+                && (pool.is_empty() || self.rng.random_ratio(1, 4)) =>
+            {
+                (0..ids_per_type)
+                    .find(|index| !pool.contains(index))
+                    .unwrap_or_default()
+            }
+            None => *pool
+                .iter()
+                .nth(self.rng.random_range(0..pool.len()))
+                .unwrap_or(&0),
+        };
+        pool.insert(index);
+
+        Some((ty.clone(), index))
+    }
+
+    fn grouped_fields(
+        &mut self,
+        selection_set: &SelectionSet,
+        grouped_fields: IndexMap<String, Vec<&Node<Field>>>,
+    ) -> anyhow::Result<Map<ByteString, Value>> {
+        let mut result = Map::new();
+        let mut field_keys = Vec::new();
+
+        for (key, fields) in grouped_fields {
+            let val = self.field(selection_set, &fields)?;
+            self.budget.nodes.fetch_add(1, Ordering::Relaxed);
+            field_keys.push((fields[0].name.clone(), key.clone()));
+            result.insert(key, val);
+        }
+        self.order_timestamps(&field_keys, &mut result);
+
+        Ok(result)
+    }
+
+    /// Moves each timestamp in `result` that [ResponseGenerationConfig::ordered_timestamps] orders after a sibling,
+    /// but which came out before it, to between that sibling and `now`. The new timestamp only depends on the two
+    /// generated ones, so that an entity that shows up more than once is ordered the same way each time. Orders
+    /// can be chained, so moving one timestamp can put another out of order again; there are at most as many passes
+    /// as there are orders, which is enough for any chain.
+    fn order_timestamps(&self, field_keys: &[(Name, String)], result: &mut Map<ByteString, Value>) {
+        let key = |field_name: &str| {
+            field_keys
+                .iter()
+                .find(|(name, _)| name == field_name)
+                .map(|(_, key)| key.as_str())
+        };
+        let timestamp = |result: &Map<ByteString, Value>, key: &str| {
+            result
+                .get(key)
+                .and_then(|value| value.as_str())
+                .and_then(|value| humantime::parse_rfc3339(value).ok())
+        };
+
+        for _ in 0..self.cfg.ordered_timestamps.len() {
+            let mut moved = false;
+            for (later, earlier) in &self.cfg.ordered_timestamps {
+                let (Some(later_key), Some(earlier_key)) = (key(later), key(earlier)) else {
+                    continue;
+                };
+                let (Some(later), Some(earlier)) =
+                    (timestamp(result, later_key), timestamp(result, earlier_key))
+                else {
+                    continue;
+                };
+                let Ok(early_by) = earlier.duration_since(later) else {
+                    continue;
+                };
+                if early_by.is_zero() {
+                    continue;
+                }
+
+                let since = self
+                    .now
+                    .duration_since(earlier)
+                    .unwrap_or_default()
+                    .as_secs();
+                let ordered = earlier + Duration::from_secs(early_by.as_secs() % (since + 1));
+                result.insert(
+                    later_key,
+                    Value::String(
+                        humantime::format_rfc3339_seconds(ordered)
+                            .to_string()
+                            .into(),
+                    ),
+                );
+                moved = true;
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    /// Generates the value of a response key in `selection_set`, which `fields` all contribute to
+    fn field(
+        &mut self,
+        selection_set: &SelectionSet,
+        fields: &[&Node<Field>],
+    ) -> anyhow::Result<Value> {
+        // The first occurrence of a field is representative for metadata that is defined by the schema
+        let meta_field = fields[0];
+
+        let val = if meta_field.name == "__typename" {
+            let selection_type = if let Some(union_schema_ty) = self
+                .schema
+                .types
+                .get(&selection_set.ty)
+                .and_then(|t| t.as_union())
+            {
+                // pick a specific member of the union, rather than using the union name
+                self.arbitrary_union_member(union_schema_ty)?.to_string()
+            } else {
+                selection_set.ty.to_string()
+            };
+            Value::String(ByteString::from(selection_type))
+        } else if meta_field.name == "_service" {
+            let mut service_obj = Map::new();
+            service_obj.insert("sdl".to_string(), Value::String(self.schema.sdl().into()));
+            Value::Object(service_obj)
+        } else if meta_field.name == "_entities"
+            && let Some(Value::Array(representations)) = meta_field
+                .specified_argument_by_name("representations")
+                .map(|value| resolve_value(value, self.variables))
+        {
+            Value::Array(self.entities(&representations, fields)?)
+        } else if let Some(value) = self.page_field(selection_set, fields)? {
+            value
+        } else if let Some((ty, index)) = &self.entity
+            && meta_field.selection_set.is_empty()
+        {
+            self.entity_field(&ty.clone(), *index, selection_set, fields)?
+        } else if !meta_field.ty().is_non_null() && self.should_be_null() {
+            Value::Null
+        } else {
+            let is_selection_set = !meta_field.selection_set.is_empty();
+            let is_array = meta_field.ty().is_list();
+
+            if is_selection_set {
+                let full_selection_set = merged_selection_set(fields);
+
+                if is_array {
+                    Value::Array(self.array_selection_set(meta_field, &full_selection_set)?)
+                } else if let Some(page) = self.page(meta_field)? {
+                    let outer = self.page.replace(page);
+                    let connection = self.selection_set(&full_selection_set);
+                    self.page = outer;
+                    Value::Object(connection?)
+                } else {
+                    Value::Object(self.selection_set(&full_selection_set)?)
+                }
+            } else {
+                match is_array {
+                    false if meta_field.name == "id" && self.id_seed.is_some() => {
+                        let entity = self.rng.random_range(0..self.cfg.stable_ids.ids_per_type);
+                        self.stable_id(&selection_set.ty, entity, meta_field)?
+                    }
+                    false => self.leaf_field(meta_field.ty().inner_named_type())?,
+                    true => self.array_leaf_field(meta_field)?,
+                }
+            }
+        };
+
+        Ok(val)
+    }
+
+    /// Picks the page of items for `field` if it returns a connection. The page continues from the field's `after`
+    /// cursor, and only has a next page if it is as long as its pagination argument allows.
+    fn page(&mut self, field: &Field) -> anyhow::Result<Option<Page>> {
+        let connections = &self.cfg.connections;
+        let connection_type = field.ty().inner_named_type();
+        if !connections.enabled
+            || !(connection_type.ends_with("Connection")
+                || connections.types.contains(connection_type.as_str()))
+        {
+            return Ok(None);
+        }
+        let Some(ExtendedType::Object(connection)) = self.schema.types.get(connection_type) else {
+            return Ok(None);
+        };
+        let Some(edges) = connection.fields.get("edges") else {
+            return Ok(None);
+        };
+
+        let mut len = self.arbitrary_array_len(field)?;
+        let min_length = self.cfg.array.min_length;
+        if len > min_length && self.should_truncate_array(min_length) {
+            len = min_length;
+        }
+        let start = field
+            .specified_argument_by_name("after")
+            .map(|after| resolve_value(after, self.variables))
+            .and_then(|after| decode_cursor(after.as_str()?))
+            .map_or(0, |after| after.saturating_add(1));
+        let has_next_page = self
+            .pagination_limit(field)
+            .is_none_or(|limit| len >= limit)
+            && self.rng.random_bool(0.5);
+        let remaining = if has_next_page {
+            self.rng.random_range(1..=self.cfg.array.max_length.max(1))
+        } else {
+            0
+        };
+
+        Ok(Some(Page {
+            connection_type: connection_type.clone(),
+            edge_type: edges.ty.inner_named_type().clone(),
+            page_info_type: connection
+                .fields
+                .get("pageInfo")
+                .map(|page_info| page_info.ty.inner_named_type().clone()),
+            start,
+            len,
+            has_next_page,
+            total_count: start.saturating_add(len).saturating_add(remaining),
+            edge: None,
+        }))
+    }
+
+    /// Generates a field of the connection that is being generated, or of its edges or page info, from its page.
+    /// Returns `None` for every other field.
+    fn page_field(
+        &mut self,
+        selection_set: &SelectionSet,
+        fields: &[&Node<Field>],
+    ) -> anyhow::Result<Option<Value>> {
+        let Some(page) = &self.page else {
+            return Ok(None);
+        };
+        let meta_field = fields[0];
+        let parent_type = &selection_set.ty;
+        let cursor = |offset: usize| Value::String(encode_cursor(offset).into());
+        let boundary_cursor = |offset: Option<usize>| match offset {
+            Some(offset) => cursor(offset),
+            None if meta_field.ty().is_non_null() => cursor(page.start),
+            None => Value::Null,
+        };
+        let last = page.len.checked_sub(1).map(|last| page.start + last);
+
+        let value = if *parent_type == page.connection_type {
+            match meta_field.name.as_str() {
+                "edges" | "nodes" if meta_field.ty().is_list() => {
+                    let is_edges = meta_field.name == "edges";
+                    let full_selection_set = merged_selection_set(fields);
+                    let mut items = Vec::with_capacity(page.len);
+                    for index in 0..page.len {
+                        self.budget.nodes.fetch_add(1, Ordering::Relaxed);
+                        if is_edges && let Some(page) = &mut self.page {
+                            page.edge = Some(index);
+                        }
+                        items.push(Value::Object(self.selection_set(&full_selection_set)?));
+                    }
+                    if let Some(page) = &mut self.page {
+                        page.edge = None;
+                    }
+                    Value::Array(items)
+                }
+                "totalCount" => Value::Number(page.total_count.into()),
+                _ => return Ok(None),
+            }
+        } else if *parent_type == page.edge_type
+            && let Some(edge) = page.edge
+            && meta_field.name == "cursor"
+        {
+            cursor(page.start + edge)
+        } else if page.page_info_type.as_ref() == Some(parent_type) {
+            match meta_field.name.as_str() {
+                "hasNextPage" => Value::Bool(page.has_next_page),
+                "hasPreviousPage" => Value::Bool(page.start > 0),
+                "startCursor" => boundary_cursor(last.map(|_| page.start)),
+                "endCursor" => boundary_cursor(last),
+                _ => return Ok(None),
+            }
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(value))
+    }
+
+    /// Groups the fields of `selection_set` by their response key, in the order that each key is first selected so
+    /// that the response matches the order of the query
+    fn collect_fields(
+        &self,
+        selection_set: &'doc SelectionSet,
+    ) -> anyhow::Result<IndexMap<String, Vec<&'doc Node<Field>>>> {
+        let mut collected_fields: IndexMap<String, Vec<&Node<Field>>> = IndexMap::default();
+
+        for selection in &selection_set.selections {
+            match selection {
+                Selection::Field(field) => {
+                    if !self
+                        .cfg
+                        .contract
+                        .includes(self.schema, &selection_set.ty, field)
+                    {
+                        continue;
+                    }
+                    let key = field.alias.as_ref().unwrap_or(&field.name).to_string();
+                    collected_fields.entry(key).or_default().push(field);
+                }
+                Selection::FragmentSpread(fragment) => {
                     if let Some(fragment_def) = self.doc.fragments.get(&fragment.fragment_name) {
                         for (key, mut fields) in self.collect_fields(&fragment_def.selection_set)? {
                             collected_fields.entry(key).or_default().append(&mut fields);
                         }
                     }
                 }
-                Selection::InlineFragment(inline_fragment) => {
-                    // NB: ignore inline fragment type conditions; if we add extra fields, the router
-                    // can filter them out for us
-                    for (key, mut fields) in self.collect_fields(&inline_fragment.selection_set)? {
-                        collected_fields.entry(key).or_default().append(&mut fields);
+                Selection::InlineFragment(inline_fragment) => {
+                    // NB: ignore inline fragment type conditions; if we add extra fields, the router
+                    // can filter them out for us
+                    for (key, mut fields) in self.collect_fields(&inline_fragment.selection_set)? {
+                        collected_fields.entry(key).or_default().append(&mut fields);
+                    }
+                }
+            }
+        }
+
+        Ok(collected_fields)
+    }
+
+    fn leaf_field(&mut self, type_name: &Name) -> anyhow::Result<Value> {
+        match self.schema.types.get(type_name).unwrap() {
+            ExtendedType::Enum(enum_ty) => {
+                let current = enum_ty
+                    .values
+                    .values()
+                    .filter(|value| !value.directives.has("deprecated"));
+                let enum_value = if self.cfg.exclude_deprecated_enum_values
+                    && current.clone().next().is_some()
+                {
+                    current.choose(self.rng)
+                } else {
+                    enum_ty.values.values().choose(self.rng)
+                }
+                .ok_or(anyhow!("empty enum: {type_name}"))?;
+
+                Ok(Value::String(ByteString::from(
+                    enum_value.value.to_string(),
+                )))
+            }
+
+            ExtendedType::Scalar(scalar) => match self.cfg.scalars.get(scalar.name.as_str()) {
+                Some(scalar_cfg) => scalar_cfg.generate(self.rng, self.now),
+                None => ScalarGenerator::DEFAULT.generate(self.rng, self.now),
+            },
+
+            _ => unreachable!("A field with an empty selection set must be a scalar or enum type"),
+        }
+    }
+
+    /// Generates the id of one of the `ids_per_type` entities of `parent_type` for its `field`. Each of them is
+    /// generated from the response's id seed, so that builders running in parallel agree on them.
+    fn stable_id(
+        &mut self,
+        parent_type: &Name,
+        entity: usize,
+        field: &Field,
+    ) -> anyhow::Result<Value> {
+        let mut hasher = DefaultHasher::new();
+        self.id_seed.hash(&mut hasher);
+        parent_type.hash(&mut hasher);
+        entity.hash(&mut hasher);
+        let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+        ResponseBuilder::new(
+            &mut rng,
+            self.now,
+            self.doc,
+            self.schema,
+            self.cfg,
+            self.variables,
+            self.budget,
+        )
+        .leaf_field(field.ty().inner_named_type())
+    }
+
+    /// Generates a scalar field of the `index`th entity of `ty` in relational mode, from an RNG seeded by the entity
+    /// and the field's name so that every occurrence of the entity agrees on it. Its `id` is never null, so that the
+    /// entity can always be told apart.
+    fn entity_field(
+        &mut self,
+        ty: &Name,
+        index: usize,
+        selection_set: &SelectionSet,
+        fields: &[&Node<Field>],
+    ) -> anyhow::Result<Value> {
+        let field = fields[0];
+        if field.name == "id" && !field.ty().is_list() {
+            return self.stable_id(ty, index, field);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.id_seed.hash(&mut hasher);
+        ty.hash(&mut hasher);
+        index.hash(&mut hasher);
+        field.name.hash(&mut hasher);
+        let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+        ResponseBuilder::new(
+            &mut rng,
+            self.now,
+            self.doc,
+            self.schema,
+            self.cfg,
+            self.variables,
+            self.budget,
+        )
+        .field(selection_set, fields)
+    }
+
+    /// Resolves one entity per representation, in order. Representations for types that this subgraph doesn't
+    /// own are resolved as null.
+    fn entities(
+        &mut self,
+        representations: &[Value],
+        fields: &[&Node<Field>],
+    ) -> anyhow::Result<Vec<Value>> {
+        let mut selections = Vec::new();
+        for field in fields {
+            selections.extend_from_slice(&field.selection_set.selections);
+        }
+
+        let mut entities = Vec::with_capacity(representations.len());
+        for representation in representations {
+            let typename = representation
+                .get("__typename")
+                .and_then(|typename| typename.as_str())
+                .and_then(|typename| Name::new(typename).ok());
+
+            let entity = match typename {
+                Some(ty) if self.owns_entity(&ty) => {
+                    // Using the concrete type for the selection set means that `__typename` will be resolved
+                    // to the type of the representation rather than a random member of the `_Entity` union
+                    Value::Object(self.selection_set(&SelectionSet {
+                        ty,
+                        selections: selections.clone(),
+                    })?)
+                }
+                _ => Value::Null,
+            };
+            entities.push(entity);
+        }
+
+        Ok(entities)
+    }
+
+    fn owns_entity(&self, type_name: &Name) -> bool {
+        let is_entity = self
+            .schema
+            .types
+            .get("_Entity")
+            .and_then(|ty| ty.as_union())
+            .is_some_and(|entity| entity.members.contains(type_name));
+
+        is_entity
+            && self
+                .cfg
+                .entity_types
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(type_name.as_str()))
+    }
+
+    fn arbitrary_union_member(&mut self, union_type: &UnionType) -> anyhow::Result<Name> {
+        if union_type.name == "_Entity" && self.cfg.entity_types.is_some() {
+            let owned: Vec<&Name> = union_type
+                .members
+                .iter()
+                .map(|member| &member.name)
+                .filter(|name| self.owns_entity(name))
+                .collect();
+
+            return owned
+                .into_iter()
+                .choose(self.rng)
+                .cloned()
+                .ok_or(anyhow!("no entity types are owned by this subgraph"));
+        }
+
+        let num_values = union_type.members.len();
+        let index = self.rng.random_range(0..num_values);
+        Ok(union_type
+            .members
+            .get_index(index)
+            .ok_or(anyhow!("Missing value"))?
+            .name
+            .clone())
+    }
+
+    /// A random length for the list `field`, capped by its pagination argument if there is one
+    fn arbitrary_array_len(&mut self, field: &Field) -> anyhow::Result<usize> {
+        let len = self.rng.random_range(self.cfg.array.range());
+        Ok(self
+            .pagination_limit(field)
+            .map_or(len, |limit| len.min(limit)))
+    }
+
+    /// The smallest non-negative integer passed to any of the field's pagination arguments, when they are respected
+    fn pagination_limit(&self, field: &Field) -> Option<usize> {
+        if !self.cfg.respect_pagination_args {
+            return None;
+        }
+
+        field
+            .arguments
+            .iter()
+            .filter(|argument| self.cfg.pagination_args.contains(argument.name.as_str()))
+            .filter_map(|argument| resolve_value(&argument.value, self.variables).as_u64())
+            .map(|limit| usize::try_from(limit).unwrap_or(usize::MAX))
+            .min()
+    }
+
+    /// Whether generating another array item would go over the `max_response_nodes` budget. Arrays always get at
+    /// least `array.min_length` items, so the budget is a soft limit.
+    fn should_truncate_array(&self, len: usize) -> bool {
+        let over_budget = len >= self.cfg.array.min_length
+            && self
+                .cfg
+                .max_response_nodes
+                .is_some_and(|max_nodes| self.budget.nodes.load(Ordering::Relaxed) >= max_nodes);
+        if over_budget {
+            self.budget.truncated.store(true, Ordering::Relaxed);
+        }
+        over_budget
+    }
+
+    fn array_selection_set(
+        &mut self,
+        field: &Field,
+        selection_set: &SelectionSet,
+    ) -> anyhow::Result<Vec<Value>> {
+        let num_values = self.arbitrary_array_len(field)?;
+        let ids_per_type = self.cfg.stable_ids.ids_per_type;
+        // In relational mode, the items are consecutive entities of their type, starting from any of them
+        let first_entity = self
+            .cfg
+            .stable_ids
+            .is_relational()
+            .then(|| self.rng.random_range(0..ids_per_type));
+        let mut values = Vec::with_capacity(num_values);
+        while values.len() < num_values && !self.should_truncate_array(values.len()) {
+            self.budget.nodes.fetch_add(1, Ordering::Relaxed);
+            self.list_entity = first_entity.map(|first| (first + values.len()) % ids_per_type);
+            values.push(Value::Object(self.selection_set(selection_set)?));
+        }
+
+        Ok(values)
+    }
+
+    fn array_leaf_field(&mut self, field: &Field) -> anyhow::Result<Value> {
+        let type_name = field.ty().inner_named_type();
+        let num_values = self.arbitrary_array_len(field)?;
+        let mut values = Vec::with_capacity(num_values);
+        while values.len() < num_values && !self.should_truncate_array(values.len()) {
+            self.budget.nodes.fetch_add(1, Ordering::Relaxed);
+            values.push(self.leaf_field(type_name)?);
+        }
+
+        Ok(Value::Array(values))
+    }
+
+    fn should_be_null(&mut self) -> bool {
+        if let Some((numerator, denominator)) = self.cfg.null_ratio {
+            self.rng.random_ratio(numerator, denominator)
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn introspection_short_circuits() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+
+        let query = r#"
+            query {
+                __schema {
+                    queryType {
+                        name
+                    }
+                    types {
+                        name
+                        kind
+                    }
+                }
+            }
+        "#;
+
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let cfg = ResponseGenerationConfig::default();
+        let result = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+
+        assert!(result.get("data").is_some());
+        let data = result.get("data").unwrap();
+        assert!(data.get("__schema").is_some());
+        // No other random data is included
+        assert!(data.as_object().unwrap().len() == 1);
+
+        let schema_obj = data.get("__schema").unwrap();
+        assert!(schema_obj.get("queryType").is_some());
+
+        let query_type = schema_obj.get("queryType").unwrap();
+        assert_eq!(query_type.get("name").unwrap().as_str().unwrap(), "Query");
+
+        let types = schema_obj.get("types").unwrap().as_array().unwrap();
+        assert!(!types.is_empty());
+
+        let type_names: Vec<&str> = types
+            .iter()
+            .filter_map(|t| t.get("name")?.as_str())
+            .collect();
+        assert!(type_names.contains(&"Query"));
+        assert!(type_names.contains(&"User"));
+        assert!(type_names.contains(&"Post"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn introspection_can_be_disabled() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let cfg = ResponseGenerationConfig::builder()
+            .introspection(false)
+            .build()?;
+        let generate = |query: &str| {
+            let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql")
+                .map_err(|err| anyhow!("{}", err.errors))?;
+            generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )
+        };
+
+        for query in [
+            "{ __schema { queryType { name } } }",
+            "{ posts { id } __type(name: \"Post\") { name } }",
+            "{ ...Introspection } fragment Introspection on Query { __schema { types { name } } }",
+        ] {
+            let err = generate(query).unwrap_err();
+            assert!(err.is::<IntrospectionDisabled>(), "{query}");
+        }
+        // `__typename` isn't introspection in the sense that servers disable
+        let response = generate("{ __typename posts { __typename id } }")?;
+        assert_eq!(response["data"]["__typename"], "Query");
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_templates_are_validated() {
+        let validate = |name: &str, template: &str| {
+            ResponseGenerationConfig::builder()
+                .header_template(name, template)
+                .build()
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(validate("x-op", "{operation_name} via {subgraph}"), Ok(()));
+        assert!(
+            validate("x-op", "{operation}")
+                .unwrap_err()
+                .contains("unknown variable {operation}")
+        );
+        assert!(
+            validate("x-op", "{subgraph")
+                .unwrap_err()
+                .contains("unclosed {")
+        );
+        assert!(
+            validate("not a header", "static")
+                .unwrap_err()
+                .contains("invalid header_templates name")
+        );
+    }
+
+    #[test]
+    fn service_introspection_uses_raw_schema() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+
+        let query = r#"
+            query {
+                _service {
+                    sdl
+                }
+            }
+        "#;
+
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let cfg = ResponseGenerationConfig::default();
+        let result = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+
+        assert!(result.get("data").is_some());
+        let data = result.get("data").unwrap();
+        assert!(data.get("_service").is_some());
+
+        let schema_obj = data.get("_service").unwrap();
+        assert!(schema_obj.get("sdl").is_some());
+
+        let sdl = schema_obj.get("sdl").unwrap().as_str().unwrap();
+        assert_eq!(supergraph, sdl);
+
+        Ok(())
+    }
+
+    #[test]
+    fn entities_resolve_only_owned_types() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+
+        let query = r#"
+            query($representations: [_Any!]!) {
+                _entities(representations: $representations) {
+                    __typename
+                    ... on User {
+                        id
                     }
                 }
             }
+        "#;
+        let variables: JsonMap = serde_json_bytes::from_value(json!({
+            "representations": [
+                { "__typename": "User", "id": "1" },
+                { "__typename": "Post", "id": "2" },
+                { "__typename": "User", "id": "3" },
+            ]
+        }))?;
+
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let cfg = ResponseGenerationConfig {
+            entity_types: Some(BTreeSet::from(["User".to_string()])),
+            ..Default::default()
+        };
+        let result = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &variables,
+        )?;
+
+        let entities = result
+            .get("data")
+            .and_then(|data| data.get("_entities"))
+            .and_then(|entities| entities.as_array())
+            .unwrap();
+        assert_eq!(entities.len(), 3);
+
+        let typenames: Vec<Option<&str>> = entities
+            .iter()
+            .map(|entity| entity.get("__typename").and_then(|name| name.as_str()))
+            .collect();
+        assert_eq!(typenames, vec![Some("User"), None, Some("User")]);
+        assert!(entities[1].is_null());
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_depth_rejects_deep_queries() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let cfg = ResponseGenerationConfig {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let variables = JsonMap::new();
+
+        let too_deep = "{ posts { id author { id } } }";
+        let doc =
+            ExecutableDocument::parse_and_validate(&schema, too_deep, "query.graphql").unwrap();
+        let err = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &variables,
+        )
+        .unwrap_err();
+        assert!(err.is::<DepthLimitExceeded>());
+
+        // Fragments don't add to the depth of the fields they contain
+        let within_limit = "{ posts { ...PostFields } } fragment PostFields on Post { id title }";
+        let doc =
+            ExecutableDocument::parse_and_validate(&schema, within_limit, "query.graphql").unwrap();
+        assert!(
+            generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &variables
+            )
+            .is_ok()
+        );
+
+        let through_fragment =
+            "{ posts { ...PostFields } } fragment PostFields on Post { author { id } }";
+        let doc =
+            ExecutableDocument::parse_and_validate(&schema, through_fragment, "query.graphql")
+                .unwrap();
+        let err = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &variables,
+        )
+        .unwrap_err();
+        assert!(err.is::<DepthLimitExceeded>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_response_nodes_truncates_arrays() -> anyhow::Result<()> {
+        fn count_nodes(value: &Value) -> usize {
+            match value {
+                Value::Object(fields) => fields.values().map(|field| 1 + count_nodes(field)).sum(),
+                Value::Array(items) => items.iter().map(|item| 1 + count_nodes(item)).sum(),
+                _ => 0,
+            }
+        }
+
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let query = "{ users { id posts { id } } }";
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let cfg = ResponseGenerationConfig {
+            array: ArraySize {
+                min_length: 1,
+                max_length: 10,
+            },
+            max_response_nodes: Some(30),
+            ..Default::default()
+        };
+
+        let mut saw_truncation = false;
+        for _ in 0..20 {
+            let result = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let data = &result["data"];
+
+            // The budget is soft: the array item that spends it is finished, along with the fields enclosing it
+            assert!(count_nodes(data) <= 35, "{data}");
+            let users = data["users"].as_array().unwrap();
+            assert!(!users.is_empty());
+            for user in users {
+                assert!(!user["posts"].as_array().unwrap().is_empty());
+            }
+
+            saw_truncation |= result["extensions"]["truncated"] == Value::Bool(true);
+        }
+        assert!(saw_truncation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn well_known_scalars_match_their_formats() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let scalars = default_scalar_config();
+        let generate =
+            |rng: &mut StdRng, name: &str| scalars[name].generate(rng, SystemTime::now());
+
+        for _ in 0..50 {
+            let uuid = generate(&mut rng, "UUID")?;
+            let groups: Vec<_> = uuid.as_str().unwrap().split('-').collect();
+            assert_eq!(
+                groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+                [8, 4, 4, 4, 12]
+            );
+            assert!(
+                groups
+                    .iter()
+                    .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+            );
+            assert!(groups[2].starts_with('4'));
+            assert!(groups[3].starts_with(['8', '9', 'a', 'b']));
+
+            let email = generate(&mut rng, "EmailAddress")?;
+            let (local, domain) = email.as_str().unwrap().split_once('@').unwrap();
+            assert!(!local.is_empty() && local.chars().all(|c| c.is_ascii_alphanumeric()));
+            assert_eq!(domain, "example.com");
+
+            let url = generate(&mut rng, "URL")?;
+            assert!(url.as_str().unwrap().starts_with("https://example.com/"));
+
+            let json = generate(&mut rng, "JSON")?;
+            assert!(!json.as_object().unwrap().is_empty());
+
+            let date_time = generate(&mut rng, "DateTime")?;
+            let timestamp = humantime::parse_rfc3339(date_time.as_str().unwrap())?;
+            let age = SystemTime::now().duration_since(timestamp)?;
+            assert!(age <= ScalarGenerator::DATE_TIME_MAX_AGE, "{date_time}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps_are_ordered() -> anyhow::Result<()> {
+        let schema = FederatedSchema::parse_string(
+            r#"
+            scalar DateTime
+            type Query {
+              audits: [Audit!]!
+            }
+            type Audit {
+              createdAt: DateTime!
+              updatedAt: DateTime!
+              deletedAt: DateTime!
+            }
+            "#,
+            "audit.graphql",
+        )?;
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ audits { modified: updatedAt createdAt deletedAt } }",
+            "query.graphql",
+        )
+        .map_err(|err| anyhow!("{}", err.errors))?;
+        let timestamps = |cfg: &ResponseGenerationConfig| {
+            let response = generate_response(
+                &mut StdRng::seed_from_u64(0),
+                SystemTime::now(),
+                cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let audits = response["data"]["audits"].as_array().unwrap().clone();
+            audits
+                .iter()
+                .map(|audit| {
+                    let timestamp =
+                        |key: &str| humantime::parse_rfc3339(audit[key].as_str().unwrap());
+                    Ok((
+                        timestamp("createdAt")?,
+                        timestamp("modified")?,
+                        timestamp("deletedAt")?,
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(50, 50)
+            .build()?;
+        let now = SystemTime::now();
+        let audits = timestamps(&cfg)?;
+        // Aliases don't matter, since fields are matched by name
+        assert!(
+            audits
+                .iter()
+                .all(|(created, updated, _)| created <= updated && *updated <= now)
+        );
+        // Fields without an order are left alone
+        assert!(audits.iter().any(|(created, _, deleted)| deleted < created));
+
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(50, 50)
+            .ordered_timestamp("deletedAt", "updatedAt")
+            .build()?;
+        assert!(
+            timestamps(&cfg)?
+                .iter()
+                .all(|(created, updated, deleted)| created <= updated && updated <= deleted)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn int64_scalars() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let default = default_scalar_config()["Long"].clone();
+        let as_string = ScalarConfig::from(ScalarGenerator::Int64 {
+            min: i64::MIN,
+            max: i64::MIN + 10,
+            as_string: true,
+        });
+
+        for _ in 0..50 {
+            let long = default.generate(&mut rng, SystemTime::now())?;
+            assert!(long.as_i64().is_some_and(|long| long >= 0));
+
+            let string = as_string.generate(&mut rng, SystemTime::now())?;
+            let long: i64 = string.as_str().unwrap().parse()?;
+            assert!((i64::MIN..=i64::MIN + 10).contains(&long));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_scalars_stay_within_bounds() -> anyhow::Result<()> {
+        fn depth_and_width(value: &Value) -> (usize, usize) {
+            let children: Vec<&Value> = match value {
+                Value::Object(object) => object.values().collect(),
+                Value::Array(array) => array.iter().collect(),
+                _ => return (0, 0),
+            };
+            children.iter().map(|child| depth_and_width(child)).fold(
+                (1, children.len()),
+                |(depth, width), (child_depth, child_width)| {
+                    (depth.max(child_depth + 1), width.max(child_width))
+                },
+            )
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = ScalarGenerator::Json {
+            max_depth: 3,
+            max_keys: 4,
+        };
+        let (mut saw_array, mut deepest) = (false, 0);
+        for _ in 0..200 {
+            let json = generator.generate(&mut rng, SystemTime::now())?;
+            assert!(json.is_object());
+            let (depth, width) = depth_and_width(&json);
+            assert!(depth <= 4 && width <= 4, "{json} is out of bounds");
+            deepest = deepest.max(depth);
+            saw_array |= json.to_string().contains('[');
+        }
+        assert!(saw_array);
+        assert_eq!(deepest, 4);
+
+        let flat = ScalarGenerator::Json {
+            max_depth: 0,
+            max_keys: 1,
+        };
+        assert_eq!(
+            depth_and_width(&flat.generate(&mut rng, SystemTime::now())?),
+            (1, 1)
+        );
+        assert!(
+            ScalarGenerator::Json {
+                max_depth: 1,
+                max_keys: 0
+            }
+            .validate("JSON")
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pagination_args_cap_list_lengths() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/paginated.graphql");
+        let schema = FederatedSchema::parse_string(source, "paginated.graphql")?;
+        let query = r#"
+            query($first: Int) {
+                posts(first: $first) { id comments(last: 3, first: 1) }
+                tags(limit: 2)
+                unlimited: tags
+            }
+        "#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let variables = json!({ "first": 4 });
+        let variables = variables.as_object().unwrap();
+
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(10, 10)
+            .respect_pagination_args(true)
+            .build()?;
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            variables,
+        )?;
+        let data = &response["data"];
+        let posts = data["posts"].as_array().unwrap();
+        assert_eq!(posts.len(), 4);
+        // The smallest of several pagination arguments wins
+        assert!(
+            posts
+                .iter()
+                .all(|post| post["comments"].as_array().unwrap().len() == 1)
+        );
+        assert_eq!(data["tags"].as_array().unwrap().len(), 2);
+        assert_eq!(data["unlimited"].as_array().unwrap().len(), 10);
+
+        // Only the configured argument names are respected
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(10, 10)
+            .respect_pagination_args(true)
+            .pagination_args(["limit"])
+            .build()?;
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            variables,
+        )?;
+        assert_eq!(response["data"]["posts"].as_array().unwrap().len(), 10);
+        assert_eq!(response["data"]["tags"].as_array().unwrap().len(), 2);
+
+        // Arguments are ignored unless the option is enabled
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(10, 10)
+            .build()?;
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            variables,
+        )?;
+        assert_eq!(response["data"]["tags"].as_array().unwrap().len(), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn connections_are_consistent() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/connections.graphql");
+        let schema = FederatedSchema::parse_string(source, "connections.graphql")?;
+        let query = r#"
+            query($after: String) {
+                posts(first: 3, after: $after) {
+                    totalCount
+                    edges { cursor node { id comments { edges { cursor } pageInfo { endCursor } } } }
+                    nodes { id }
+                    pageInfo { hasNextPage hasPreviousPage startCursor endCursor }
+                }
+                feed { edges { cursor } pageInfo { hasNextPage } }
+            }
+        "#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let variables = json!({ "after": encode_cursor(9) });
+        let variables = variables.as_object().unwrap();
+        let cfg = ResponseGenerationConfig::builder()
+            .respect_pagination_args(true)
+            .null_ratio(None)
+            .connections(ConnectionConfig {
+                enabled: true,
+                types: BTreeSet::from(["Feed".to_string()]),
+            })
+            .build()?;
+
+        for _ in 0..50 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                variables,
+            )?;
+            let posts = &response["data"]["posts"];
+            let edges = posts["edges"].as_array().unwrap();
+            let page_info = &posts["pageInfo"];
+            assert!(edges.len() <= 3);
+            assert_eq!(posts["nodes"].as_array().unwrap().len(), edges.len());
+
+            // The page continues from the `after` cursor
+            let cursors: Vec<_> = edges.iter().map(|edge| edge["cursor"].clone()).collect();
+            let expected: Vec<_> = (10..10 + edges.len())
+                .map(|offset| Value::from(encode_cursor(offset)))
+                .collect();
+            assert_eq!(cursors, expected);
+            assert_eq!(page_info["hasPreviousPage"], json!(true));
+            assert_eq!(
+                page_info["startCursor"],
+                cursors.first().cloned().unwrap_or_default()
+            );
+            assert_eq!(
+                page_info["endCursor"],
+                cursors.last().cloned().unwrap_or_default()
+            );
+
+            // There's only a next page if this one is full
+            let total_count = posts["totalCount"].as_u64().unwrap() as usize;
+            if page_info["hasNextPage"] == json!(true) {
+                assert_eq!(edges.len(), 3);
+                assert!(total_count > 10 + edges.len());
+            } else {
+                assert_eq!(total_count, 10 + edges.len());
+            }
+
+            // Nested connections have pages of their own
+            for edge in edges {
+                let comments = &edge["node"]["comments"];
+                let comment_cursors: Vec<_> = comments["edges"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|edge| edge["cursor"].clone())
+                    .collect();
+                assert_eq!(
+                    comments["pageInfo"]["endCursor"],
+                    comment_cursors.last().cloned().unwrap_or_default()
+                );
+            }
+
+            let feed = &response["data"]["feed"];
+            let feed_cursors: Vec<_> = feed["edges"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|edge| edge["cursor"].clone())
+                .collect();
+            let expected: Vec<_> = (0..feed_cursors.len())
+                .map(|offset| Value::from(encode_cursor(offset)))
+                .collect();
+            assert_eq!(feed_cursors, expected);
         }
 
-        Ok(collected_fields)
+        Ok(())
+    }
+
+    #[test]
+    fn cursors_match_graphql_relay() {
+        assert_eq!(encode_cursor(0), "YXJyYXljb25uZWN0aW9uOjA=");
+        assert_eq!(encode_cursor(12), "YXJyYXljb25uZWN0aW9uOjEy");
+        for offset in [0, 1, 9, 10, 123, 4567, usize::MAX] {
+            assert_eq!(decode_cursor(&encode_cursor(offset)), Some(offset));
+        }
+        assert_eq!(decode_cursor("not a cursor"), None);
     }
 
-    fn leaf_field(&mut self, type_name: &Name) -> anyhow::Result<Value> {
-        match self.schema.types.get(type_name).unwrap() {
-            ExtendedType::Enum(enum_ty) => {
-                let enum_value = enum_ty
-                    .values
-                    .values()
-                    .choose(self.rng)
-                    .ok_or(anyhow!("empty enum: {type_name}"))?;
+    #[test]
+    fn stable_ids_are_shared_within_a_response() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let query = r#"{ user(id: "1") { id } users { id } posts { id author { id } } }"#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query")
+            .map_err(|err| anyhow!("{}", err.errors))?;
+        let ids = |values: Vec<&Value>| -> BTreeSet<String> {
+            values
+                .into_iter()
+                .map(|value| value["id"].to_string())
+                .collect()
+        };
 
-                Ok(Value::String(ByteString::from(
-                    enum_value.value.to_string(),
-                )))
+        // Top level fields are generated in parallel, which mustn't stop them from sharing ids
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(5, 5)
+            .null_ratio(None)
+            .parallel_generation_threshold(1)
+            .stable_ids(StableIdConfig {
+                enabled: true,
+                ids_per_type: 2,
+                relational: false,
+            })
+            .build()?;
+        let (mut user_ids, mut post_ids) = (BTreeSet::new(), BTreeSet::new());
+        for _ in 0..20 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let data = &response["data"];
+            let posts: Vec<_> = data["posts"].as_array().unwrap().iter().collect();
+            let mut users: Vec<_> = data["users"].as_array().unwrap().iter().collect();
+            users.push(&data["user"]);
+            users.extend(posts.iter().map(|post| &post["author"]));
+
+            let (response_user_ids, response_post_ids) = (ids(users), ids(posts));
+            assert!(response_user_ids.len() <= 2, "{response_user_ids:?}");
+            assert!(response_post_ids.len() <= 2, "{response_post_ids:?}");
+            user_ids.extend(response_user_ids);
+            post_ids.extend(response_post_ids);
+        }
+        // While each response has its own ids
+        assert!(user_ids.len() > 2, "{user_ids:?}");
+        assert!(post_ids.len() > 2, "{post_ids:?}");
+
+        assert!(
+            ResponseGenerationConfig::builder()
+                .stable_ids(StableIdConfig {
+                    enabled: true,
+                    ids_per_type: 0,
+                    relational: false,
+                })
+                .build()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validation_errors_on_deprecated_fields_have_their_reason() -> anyhow::Result<()> {
+        let schema = FederatedSchema::parse_string(
+            r#"
+            type Query {
+              old(id: ID!): Item @deprecated(reason: "Use `item` instead")
+              older: Int @deprecated
+              item(id: ID!): Item
+            }
+            type Item {
+              name: String
             }
+            "#,
+            "deprecated.graphql",
+        )?;
+        let query = "{ old { name } older(bogus: 1) item { name } }";
+        let err = ExecutableDocument::parse_and_validate(&schema, query, "query").unwrap_err();
 
-            ExtendedType::Scalar(scalar) => self
-                .cfg
-                .scalars
-                .get(scalar.name.as_str())
-                .unwrap_or(&ScalarGenerator::DEFAULT)
-                .generate(self.rng),
+        let reasons: Vec<_> = validation_errors(&err, &schema)
+            .into_iter()
+            .map(|error| error.extensions.get("deprecationReason").cloned())
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                Some(json!("Use `item` instead")),
+                Some(json!("No longer supported")),
+                None,
+            ]
+        );
 
-            _ => unreachable!("A field with an empty selection set must be a scalar or enum type"),
-        }
+        Ok(())
     }
 
-    fn arbitrary_union_member(&mut self, union_type: &UnionType) -> anyhow::Result<Name> {
-        let num_values = union_type.members.len();
-        let index = self.rng.random_range(0..num_values);
-        Ok(union_type
-            .members
-            .get_index(index)
-            .ok_or(anyhow!("Missing value"))?
-            .name
-            .clone())
+    #[test]
+    fn relational_entities_are_linked_within_a_response() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let query = r#"{ posts { id title author { id name } } users { id name email } }"#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query")
+            .map_err(|err| anyhow!("{}", err.errors))?;
+        let cfg = ResponseGenerationConfig::builder()
+            .array_size(3, 3)
+            .parallel_generation_threshold(1)
+            .stable_ids(StableIdConfig {
+                enabled: true,
+                ids_per_type: 3,
+                relational: true,
+            })
+            .build()?;
+
+        for _ in 0..20 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let data = &response["data"];
+            let users = data["users"].as_array().unwrap();
+            let user = |user: &Value| format!("{} {}", user["id"], user["name"]);
+            let user_set: BTreeSet<String> = users.iter().map(user).collect();
+            // The list holds every user in the pool, which each author is one of, down to their name
+            assert_eq!(user_set.len(), 3, "{users:?}");
+            for post in data["posts"].as_array().unwrap() {
+                assert!(user_set.contains(&user(&post["author"])), "{data:?}");
+            }
+        }
+
+        Ok(())
     }
 
-    fn arbitrary_array_len(&mut self) -> anyhow::Result<usize> {
-        Ok(self.rng.random_range(self.cfg.array.range()))
+    #[test]
+    fn contracts_filter_fields_by_tag() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/tagged.graphql");
+        let schema = FederatedSchema::parse_string(source, "tagged.graphql")?;
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ products { id name cost } stats { count } }",
+            "query.graphql",
+        )
+        .map_err(|err| anyhow!("{}", err.errors))?;
+        let keys = |include_tags: &[&str], exclude_tags: &[&str]| {
+            let cfg = ResponseGenerationConfig::builder()
+                .array_size(1, 1)
+                .null_ratio(None)
+                .contract(ContractConfig {
+                    include_tags: include_tags.iter().map(|tag| tag.to_string()).collect(),
+                    exclude_tags: exclude_tags.iter().map(|tag| tag.to_string()).collect(),
+                })
+                .build()?;
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let data = response["data"].as_object().unwrap();
+            let mut keys: Vec<String> = data.keys().map(|key| key.as_str().to_string()).collect();
+            if let Some(product) = data.get("products").and_then(|products| products.get(0)) {
+                let product = product.as_object().unwrap();
+                keys.extend(
+                    product
+                        .keys()
+                        .map(|key| format!("products.{}", key.as_str())),
+                );
+            }
+            anyhow::Ok(keys)
+        };
+
+        assert_eq!(
+            keys(&[], &[])?,
+            [
+                "products",
+                "stats",
+                "products.id",
+                "products.name",
+                "products.cost"
+            ]
+        );
+        assert_eq!(
+            keys(&[], &["internal"])?,
+            ["products", "products.id", "products.name"]
+        );
+        // Fields are included by the tags of the types they belong to and return as well as their own
+        assert_eq!(
+            keys(&["public"], &[])?,
+            ["products", "products.id", "products.name", "products.cost"]
+        );
+        assert_eq!(
+            keys(&["public"], &["internal"])?,
+            ["products", "products.id", "products.name"]
+        );
+
+        Ok(())
     }
 
-    fn array_selection_set(&mut self, selection_set: &SelectionSet) -> anyhow::Result<Vec<Value>> {
-        let num_values = self.arbitrary_array_len()?;
-        let mut values = Vec::with_capacity(num_values);
-        for _ in 0..num_values {
-            values.push(Value::Object(self.selection_set(selection_set)?));
-        }
+    #[test]
+    fn deprecated_enum_values_are_excluded() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/deprecated-enum.graphql");
+        let schema = FederatedSchema::parse_string(source, "deprecated-enum.graphql")?;
+        let doc =
+            ExecutableDocument::parse_and_validate(&schema, "{ status legacy }", "query.graphql")
+                .map_err(|err| anyhow!("{}", err.errors))?;
+        let values = |exclude_deprecated_enum_values: bool| {
+            let cfg = ResponseGenerationConfig::builder()
+                .null_ratio(None)
+                .exclude_deprecated_enum_values(exclude_deprecated_enum_values)
+                .build()?;
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut statuses = BTreeSet::new();
+            let mut legacies = BTreeSet::new();
+            for _ in 0..100 {
+                let response = generate_response(
+                    &mut rng,
+                    SystemTime::now(),
+                    &cfg,
+                    None,
+                    &doc,
+                    &schema,
+                    &JsonMap::new(),
+                )?;
+                statuses.insert(response["data"]["status"].as_str().unwrap().to_string());
+                legacies.insert(response["data"]["legacy"].as_str().unwrap().to_string());
+            }
+            anyhow::Ok((statuses, legacies))
+        };
 
-        Ok(values)
+        // Enums whose values are all deprecated still have to be generated
+        let (statuses, legacies) = values(true)?;
+        assert_eq!(statuses, ["ACTIVE", "ARCHIVED"].map(String::from).into());
+        assert_eq!(legacies, ["OLD", "OLDER"].map(String::from).into());
+
+        let (statuses, _) = values(false)?;
+        assert_eq!(
+            statuses,
+            ["ACTIVE", "ARCHIVED", "PENDING"].map(String::from).into()
+        );
+
+        Ok(())
     }
 
-    fn array_leaf_field(&mut self, type_name: &Name) -> anyhow::Result<Value> {
-        let num_values = self.arbitrary_array_len()?;
-        let mut values = Vec::with_capacity(num_values);
-        for _ in 0..num_values {
-            values.push(self.leaf_field(type_name)?);
+    #[test]
+    fn field_errors_only_fail_nullable_fields() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/non-null-root.graphql");
+        let schema = FederatedSchema::parse_string(source, "non-null-root.graphql")?;
+        let cfg = ResponseGenerationConfig::builder()
+            .field_error_ratio((1, 1))
+            .null_ratio(None)
+            .build()?;
+
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ required alsoRequired }",
+            "query.graphql",
+        )
+        .unwrap();
+        for _ in 0..20 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            assert!(response.get("errors").is_none());
+            assert!(response["data"]["required"].is_string());
+            assert!(response["data"]["alsoRequired"].is_i64());
         }
 
-        Ok(Value::Array(values))
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ required optional }",
+            "query.graphql",
+        )
+        .unwrap();
+        for _ in 0..20 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            assert!(response["data"]["required"].is_string());
+            // Failed fields are null rather than missing
+            assert_eq!(response["data"]["optional"], Value::Null);
+            assert_eq!(response["errors"][0]["path"], json!(["optional"]));
+            assert_eq!(
+                response["errors"][0]["locations"],
+                json!([{ "line": 1, "column": 12 }])
+            );
+        }
+
+        Ok(())
     }
 
-    fn should_be_null(&mut self) -> bool {
-        if let Some((numerator, denominator)) = self.cfg.null_ratio {
-            self.rng.random_ratio(numerator, denominator)
-        } else {
-            false
+    #[test]
+    fn field_errors_per_field() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/non-null-root.graphql");
+        let schema = FederatedSchema::parse_string(source, "non-null-root.graphql")?;
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ a: optional b: optional c: optional d: optional }",
+            "query.graphql",
+        )
+        .unwrap();
+        let cfg = ResponseGenerationConfig::builder()
+            .field_error_ratio((1, 2))
+            .field_error_mode(FieldErrorMode::Field)
+            .null_ratio(None)
+            .build()?;
+
+        // Fields fail independently, so how many fail in a request follows a binomial distribution rather than
+        // being picked uniformly
+        let mut failure_counts = [0; 5];
+        for _ in 0..2000 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let errors = response.get("errors").and_then(|errors| errors.as_array());
+            let failed = errors.map_or(0, |errors| errors.len());
+            assert_eq!(
+                response["data"]
+                    .as_object()
+                    .unwrap()
+                    .values()
+                    .filter(|value| value.is_null())
+                    .count(),
+                failed
+            );
+            failure_counts[failed] += 1;
         }
+        // 1 in 16 requests have no failures, and 6 in 16 have two of them
+        let ratio = |count: usize| count as f64 / 2000.0;
+        assert!((0.03..0.1).contains(&ratio(failure_counts[0])));
+        assert!((0.32..0.43).contains(&ratio(failure_counts[2])));
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn one_of_inputs_are_preserved() -> anyhow::Result<()> {
+        let source = include_str!("../state/schema/test-data/one-of-input.graphql");
+        let schema = FederatedSchema::parse_string(source, "one-of-input.graphql")?;
+        let query = r#"
+            query($by: UserBy!) {
+                literal: user(by: { email: "a@example.com" }) { id }
+                variable: user(by: $by) { email }
+            }
+        "#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let variables = json!({ "by": { "id": "1" } });
+        let variables = variables.as_object().unwrap();
+
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &ResponseGenerationConfig::default(),
+            None,
+            &doc,
+            &schema,
+            variables,
+        )?;
+        assert!(response.get("errors").is_none());
+
+        // Only the one field that was set is read from the input
+        let op = doc.operations.get(None).unwrap();
+        let argument = |index: usize| {
+            let Selection::Field(field) = &op.selection_set.selections[index] else {
+                unreachable!()
+            };
+            resolve_value(field.specified_argument_by_name("by").unwrap(), variables)
+        };
+        assert_eq!(argument(0), json!({ "email": "a@example.com" }));
+        assert_eq!(argument(1), json!({ "id": "1" }));
+
+        Ok(())
+    }
 
     #[test]
-    fn introspection_short_circuits() -> anyhow::Result<()> {
+    fn scalar_mismatches_are_reported() -> anyhow::Result<()> {
         let supergraph = include_str!("../../tests/data/schema.graphql");
         let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let mut cfg = ResponseGenerationConfig::default();
+        assert!(cfg.scalar_mismatches(&schema).is_empty());
+
+        cfg.scalars.extend([
+            ("Post".to_string(), ScalarGenerator::Bool.into()),
+            ("Strnig".to_string(), ScalarGenerator::Bool.into()),
+            ("String".to_string(), ScalarGenerator::Bool.into()),
+        ]);
+        assert_eq!(
+            cfg.scalar_mismatches(&schema),
+            [
+                "`Post` is an object type, not a scalar",
+                "`Strnig` is not defined in the schema",
+            ]
+        );
+
+        Ok(())
+    }
 
+    #[test]
+    fn fields_are_generated_in_query_order() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
         let query = r#"
             query {
-                __schema {
-                    queryType {
-                        name
-                    }
-                    types {
-                        name
-                        kind
-                    }
+                post(id: 1) {
+                    views
+                    ...PostFields
+                    ... on Post { content }
+                    id
+                    alias: title
                 }
             }
+
+            fragment PostFields on Post { title featuredImage views }
         "#;
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let cfg = ResponseGenerationConfig {
+            null_ratio: None,
+            ..Default::default()
+        };
+
+        for _ in 0..10 {
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                None,
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            let keys: Vec<_> = response["data"]["post"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|key| key.as_str())
+                .collect();
+            assert_eq!(
+                keys,
+                ["views", "title", "featuredImage", "content", "id", "alias"]
+            );
+        }
+
+        Ok(())
+    }
 
+    #[test]
+    fn parallel_generation_is_deterministic() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let query = "{ a: posts { id title } b: users { name email } c: post(id: 1) { title views } d: user(id: 2) { email name } }";
         let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let op = doc.operations.get(None).unwrap();
+        let cfg = ResponseGenerationConfig {
+            parallel_generation_threshold: Some(2),
+            ..Default::default()
+        };
+        let variables = JsonMap::new();
+
+        let generate = |cfg: &ResponseGenerationConfig, seed: u64| {
+            ResponseBuilder::new(
+                &mut StdRng::seed_from_u64(seed),
+                SystemTime::now(),
+                &doc,
+                &schema,
+                cfg,
+                &variables,
+                &NodeBudget::default(),
+            )
+            .root_selection_set(&op.selection_set)
+        };
+
+        let first = generate(&cfg, 7)?;
+        assert_eq!(first.len(), 4);
+        for _ in 0..10 {
+            assert_eq!(generate(&cfg, 7)?, first);
+        }
+        assert_ne!(generate(&cfg, 8)?, first);
+
+        // Including which fields spend a shared budget
+        let budgeted = ResponseGenerationConfig {
+            max_response_nodes: Some(20),
+            array: ArraySize {
+                min_length: 0,
+                max_length: 20,
+            },
+            ..cfg.clone()
+        };
+        let first = generate(&budgeted, 7)?;
+        for _ in 0..10 {
+            assert_eq!(generate(&budgeted, 7)?, first);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn uncached_responses_reuse_validated_documents() -> anyhow::Result<()> {
+        use cached::Cached;
+
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let request = || GraphQLRequest {
+            query: "{ posts { id title } }".to_string(),
+            operation_name: None,
+            variables: JsonMap::new(),
+        };
+        // Pick a hash that can't collide with documents cached by other tests
+        let doc_hash = rand::rng().random();
+        let cached_doc = || PARSE_AND_VALIDATE.lock().cache_get(&doc_hash).cloned();
+
         let cfg = ResponseGenerationConfig::default();
-        let result = generate_response(&cfg, None, &doc, &schema, &JsonMap::new())?;
+        let (_, status, _, _) = into_response_bytes_and_status_code_no_cache(
+            &mut rand::make_rng(),
+            &cfg,
+            request(),
+            &schema,
+            StatusCode::OK,
+            doc_hash,
+            1,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let doc = cached_doc().expect("validated document is cached");
 
-        assert!(result.get("data").is_some());
-        let data = result.get("data").unwrap();
-        assert!(data.get("__schema").is_some());
-        // No other random data is included
-        assert!(data.as_object().unwrap().len() == 1);
+        // A different response generation config only changes the response cache key
+        let cfg = ResponseGenerationConfig {
+            null_ratio: Some((1, 1)),
+            ..Default::default()
+        };
+        let (_, status, _, _) = into_response_bytes_and_status_code_no_cache(
+            &mut rand::make_rng(),
+            &cfg,
+            request(),
+            &schema,
+            StatusCode::OK,
+            doc_hash,
+            2,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // The cached document is only replaced if the query had to be parsed again
+        let anonymous_op =
+            |doc: Valid<ExecutableDocument>| doc.operations.anonymous.clone().unwrap();
+        assert!(anonymous_op(doc).ptr_eq(&anonymous_op(cached_doc().unwrap())));
 
-        let schema_obj = data.get("__schema").unwrap();
-        assert!(schema_obj.get("queryType").is_some());
+        Ok(())
+    }
 
-        let query_type = schema_obj.get("queryType").unwrap();
-        assert_eq!(query_type.get("name").unwrap().as_str().unwrap(), "Query");
+    #[tokio::test]
+    async fn operations_are_selected_by_name() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let cfg = ResponseGenerationConfig::default();
+        let request = |operation_name: Option<&str>| GraphQLRequest {
+            query: "query Posts { posts { id } } query Users { users { id } }".to_string(),
+            operation_name: operation_name.map(str::to_string),
+            variables: JsonMap::new(),
+        };
+        let doc_hash = rand::rng().random();
 
-        let types = schema_obj.get("types").unwrap().as_array().unwrap();
-        assert!(!types.is_empty());
+        let (bytes, status, _, _) = into_response_bytes_and_status_code_no_cache(
+            &mut rand::make_rng(),
+            &cfg,
+            request(Some("Users")),
+            &schema,
+            StatusCode::OK,
+            doc_hash,
+            1,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_slice(&bytes)?;
+        assert!(response["data"]["users"].is_array());
+        assert!(response["data"].get("posts").is_none());
 
-        let type_names: Vec<&str> = types
-            .iter()
-            .filter_map(|t| t.get("name")?.as_str())
-            .collect();
-        assert!(type_names.contains(&"Query"));
-        assert!(type_names.contains(&"User"));
-        assert!(type_names.contains(&"Post"));
+        for (operation_name, message) in [
+            (
+                None,
+                "Ambiguous request: multiple operations but no specified `operationName`",
+            ),
+            (Some("Comments"), "No operation named 'Comments'"),
+        ] {
+            let (bytes, status, _, _) = into_response_bytes_and_status_code_no_cache(
+                &mut rand::make_rng(),
+                &cfg,
+                request(operation_name),
+                &schema,
+                StatusCode::BAD_REQUEST,
+                doc_hash,
+                1,
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let response: Value = serde_json::from_slice(&bytes)?;
+            assert_eq!(response["errors"][0]["message"], message);
+        }
 
         Ok(())
     }
 
     #[test]
-    fn service_introspection_uses_raw_schema() -> anyhow::Result<()> {
+    fn empty_selection_is_configurable() -> anyhow::Result<()> {
         let supergraph = include_str!("../../tests/data/schema.graphql");
         let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let doc = ExecutableDocument::parse_and_validate(&schema, "{ posts { id } }", "query")
+            .map_err(|err| anyhow!("{}", err.errors))?;
 
-        let query = r#"
-            query {
-                _service {
-                    sdl
-                }
-            }
-        "#;
+        for (empty_selection, expected) in [
+            (EmptySelection::default(), json!({ "data": {} })),
+            (EmptySelection::Null, json!({ "data": null })),
+            (
+                EmptySelection::Error,
+                json!({ "data": null, "errors": [{ "message": "Operation selected no fields" }] }),
+            ),
+        ] {
+            let cfg = ResponseGenerationConfig {
+                empty_selection,
+                ..Default::default()
+            };
+            // There is no operation to select anything with this name
+            let response = generate_response(
+                &mut rand::rng(),
+                SystemTime::now(),
+                &cfg,
+                Some("Missing"),
+                &doc,
+                &schema,
+                &JsonMap::new(),
+            )?;
+            assert_eq!(response, expected);
+        }
 
-        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
-        let cfg = ResponseGenerationConfig::default();
-        let result = generate_response(&cfg, None, &doc, &schema, &JsonMap::new())?;
+        Ok(())
+    }
 
-        assert!(result.get("data").is_some());
-        let data = result.get("data").unwrap();
-        assert!(data.get("_service").is_some());
+    #[test]
+    fn response_overrides_are_returned_verbatim() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let doc = ExecutableDocument::parse_and_validate(
+            &schema,
+            "query Canned { posts { id } } query Random { posts { id } }",
+            "query",
+        )
+        .map_err(|err| anyhow!("{}", err.errors))?;
 
-        let schema_obj = data.get("_service").unwrap();
-        assert!(schema_obj.get("sdl").is_some());
+        let canned = json!({ "data": { "posts": [{ "id": "1", "title": "Not selected" }] } });
+        let cfg = ResponseGenerationConfig::builder()
+            .response_override("Canned", canned.clone())
+            .build()?;
+        assert!(cfg.response_override_mismatches(&schema).is_empty());
 
-        let sdl = schema_obj.get("sdl").unwrap().as_str().unwrap();
-        assert_eq!(supergraph, sdl);
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            Some("Canned"),
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+        assert_eq!(response, canned);
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            Some("Random"),
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+        assert_ne!(response, canned);
+
+        // Anonymous operations are overridden by the hash of their query
+        let query = "{ posts { id } }";
+        let doc = ExecutableDocument::parse_and_validate(&schema, query, "query")
+            .map_err(|err| anyhow!("{}", err.errors))?;
+        let cfg = ResponseGenerationConfig::builder()
+            .response_override(query_hash(query), canned.clone())
+            .build()?;
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+        assert_eq!(response, canned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn response_override_mismatches() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+
+        let cfg = ResponseGenerationConfig::builder()
+            .response_override(
+                "Drifted",
+                json!({ "data": { "posts": [{ "id": "1", "author": { "nickname": "x" } }], "feed": [] } }),
+            )
+            .response_override("NoData", json!([]))
+            .build()?;
+        assert_eq!(
+            cfg.response_override_mismatches(&schema),
+            vec![
+                "`Drifted` returns `Query.feed`, which is not defined in the schema",
+                "`Drifted` returns `User.nickname`, which is not defined in the schema",
+                "`NoData` must be an object with a `data` field",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn weighted_scalar_generators() -> anyhow::Result<()> {
+        let cfg: ResponseGenerationConfig = serde_yaml::from_str(
+            "
+            scalars:
+              Boolean:
+                type: bool
+              String:
+                - weight: 7
+                  type: string
+                  min_len: 1
+                  max_len: 1
+                - weight: 3
+                  type: string
+                  min_len: 20
+                  max_len: 20
+            ",
+        )?;
+        cfg.validate()?;
+        // Single generators are still parsed as they were
+        assert_eq!(cfg.scalars["Boolean"], ScalarGenerator::Bool.into());
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let n_long = (0..10_000)
+            .filter(|_| {
+                let value = cfg.scalars["String"]
+                    .generate(&mut rng, SystemTime::now())
+                    .unwrap();
+                value.as_str().unwrap().len() == 20
+            })
+            .count();
+        assert_eq!(format!("{:.1}", n_long as f64 / 10_000.0), "0.3");
+
+        let unweighted = ResponseGenerationConfig::builder().scalar(
+            "String",
+            ScalarConfig::Weighted(vec![WeightedScalarGenerator {
+                weight: 0,
+                generator: ScalarGenerator::Bool,
+            }]),
+        );
+        assert!(unweighted.build().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder_config_generates_responses() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let doc =
+            ExecutableDocument::parse_and_validate(&schema, "{ posts { id title } }", "query")
+                .map_err(|err| anyhow!("{}", err.errors))?;
+
+        let cfg = ResponseGenerationConfig::builder()
+            .scalar("String", ScalarGenerator::Uuid)
+            .array_size(2, 2)
+            .null_ratio(None)
+            .build()?;
+        let response = generate_response(
+            &mut rand::rng(),
+            SystemTime::now(),
+            &cfg,
+            None,
+            &doc,
+            &schema,
+            &JsonMap::new(),
+        )?;
+
+        let posts = response["data"]["posts"].as_array().unwrap();
+        assert_eq!(posts.len(), 2);
+        for post in posts {
+            assert_eq!(post["title"].as_str().unwrap().len(), 36);
+        }
+
+        // Configs that can't be used to generate responses are rejected
+        assert!(
+            ResponseGenerationConfig::builder()
+                .array_size(3, 1)
+                .build()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn warm_up_seeds_response_cache() -> anyhow::Result<()> {
+        use cached::Cached;
+
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = FederatedSchema::parse_string(supergraph, "../../tests/data/schema.graphql")?;
+        let warmup_dir = format!("{}/tests/data/warmup", env!("CARGO_MANIFEST_DIR"));
+        // A unique config keeps the cache keys from colliding with other tests
+        let response_generation = ResponseGenerationConfig {
+            header_ratio: BTreeMap::from([(rand::rng().random::<u64>().to_string(), (1, 2))]),
+            ..Default::default()
+        };
+        let config = Config {
+            response_generation: response_generation.clone(),
+            warmup: vec![
+                format!("{warmup_dir}/invalid.graphql").into(),
+                format!("{warmup_dir}/missing.graphql").into(),
+                format!("{warmup_dir}/posts.graphql").into(),
+            ],
+            ..Default::default()
+        };
+        let req = GraphQLRequest {
+            query: include_str!("../../tests/data/warmup/posts.graphql").to_string(),
+            operation_name: None,
+            variables: JsonMap::new(),
+        };
+        let (_, cache_hash) = cache_hashes(&req, &response_generation, &schema, StatusCode::OK);
+        let state = State::from_schema(config, schema)?;
+        let cached_response = || async {
+            INTO_RESPONSE_BYTES_AND_STATUS_CODE
+                .lock()
+                .await
+                .cache_get(&cache_hash)
+                .cloned()
+        };
+        assert!(cached_response().await.is_none());
+
+        // Invalid and unreadable operations are skipped without affecting the rest
+        warm_up(&state).await;
+        let (bytes, status, _, _) = cached_response().await.expect("response is cached");
+        assert_eq!(status, StatusCode::OK);
+
+        // Real requests are served the pre-generated response
+        let resp = handle(
+            serde_json::to_vec(&json!({ "query": req.query }))?,
+            &HeaderMap::new(),
+            None,
+            Arc::new(state),
+        )
+        .await?;
+        assert_eq!(resp.into_body().collect().await?.to_bytes(), bytes);
 
         Ok(())
     }
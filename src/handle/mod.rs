@@ -1,19 +1,157 @@
-use crate::state::State;
+use crate::{
+    handle::graphql::{FieldLatency, JitterSeed},
+    latency::LatencyGenerator,
+    state::{Config, State},
+};
 use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use hyper::{
-    Method, Request, Response, StatusCode,
+    HeaderMap, Method, Request, Response, StatusCode,
     body::{Body, Bytes},
+    header::{ALLOW, CONTENT_TYPE, HeaderValue},
 };
+use rand::{SeedableRng, rngs::StdRng};
+use serde_json_bytes::{json, serde_json};
+use sha2::{Digest, Sha256};
 use std::{error::Error, sync::Arc};
-use tokio::time::{Instant, sleep};
-use tracing::{trace, warn};
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{Instrument, Span, info_span, trace, warn};
+use uuid::Uuid;
 
+pub mod cors;
 pub mod graphql;
 
 pub type ByteResponse = Response<BoxBody<Bytes, hyper::Error>>;
 
+/// Request header that sets the latency of a single response in milliseconds, bypassing the latency generator. Only
+/// honored when [Config::allow_latency_override] is enabled.
+pub const LATENCY_OVERRIDE_HEADER: &str = "x-mock-latency-ms";
+
+/// Response header naming the subgraph that handled a GraphQL request, or the [Config::instance_name] for requests
+/// to `/`. Left out when [Config::subgraph_name_header] is disabled.
+pub const SUBGRAPH_NAME_HEADER: &str = "x-subgraph-name";
+
+/// The W3C Trace Context request header, whose trace id is attached to everything logged while handling a request
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Header that identifies a request in the mock's logs. Requests that don't send one are given a random UUID, and
+/// the id is echoed back on the response either way.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
 /// Top level handler function that is called for every incoming request from Hyper.
+///
+/// Requests to `/{subgraph_name}` are handled as the named subgraph, while requests to `/` use the base config.
+/// `GET /status` reports whether the schema being served is up to date, see [status], and `GET /health` whether
+/// the [Config::startup_delay] has passed. `GET /debug/cache` reports how effective response caching is, and
+/// `POST /debug/flush-cache` empties the caches. With [Config::supergraph_endpoint] enabled, the schema is served at
+/// `GET /supergraph`, see [supergraph], and `POST /uplink`, see [uplink]. Requests with a method that their route
+/// doesn't accept are rejected with a `405 Method Not Allowed`, and unknown `/debug/` paths with a `404 Not Found`.
+/// Every response carries the request's [REQUEST_ID_HEADER].
 pub async fn handle_request<B>(req: Request<B>, state: Arc<State>) -> anyhow::Result<ByteResponse>
+where
+    B: Body,
+    B::Error: Error + Send + Sync + 'static,
+{
+    handle_in_span(req, None, state).await
+}
+
+/// Handler for requests received on a listener that is dedicated to a single subgraph. Every request is handled
+/// as `subgraph_name`, regardless of its path.
+pub async fn handle_subgraph_request<B>(
+    req: Request<B>,
+    subgraph_name: &str,
+    state: Arc<State>,
+) -> anyhow::Result<ByteResponse>
+where
+    B: Body,
+    B::Error: Error + Send + Sync + 'static,
+{
+    handle_in_span(req, Some(subgraph_name), state).await
+}
+
+/// Routes a request within its [request_span], and echoes its [REQUEST_ID_HEADER] back on the response. The id is
+/// set on the request as well, so that it is the same everywhere the request is handled.
+async fn handle_in_span<B>(
+    mut req: Request<B>,
+    listener_subgraph: Option<&str>,
+    state: Arc<State>,
+) -> anyhow::Result<ByteResponse>
+where
+    B: Body,
+    B::Error: Error + Send + Sync + 'static,
+{
+    let request_id = request_id(req.headers());
+    let span = request_span(req.headers(), &request_id);
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.clone());
+    let mut resp = route_request(req, listener_subgraph, state)
+        .instrument(span)
+        .await?;
+    resp.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+
+    Ok(resp)
+}
+
+/// The id that a request is logged and responded to with: its own [REQUEST_ID_HEADER] if it sent a usable one, or
+/// a new random UUID otherwise
+fn request_id(headers: &HeaderMap) -> HeaderValue {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .filter(|value| !value.is_empty() && value.to_str().is_ok())
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::try_from(Uuid::new_v4().to_string())
+                .expect("UUIDs are valid header values")
+        })
+}
+
+/// The span that a request is handled in, which records its `request_id`. When the request is part of a distributed
+/// trace, the span records its trace id as well so that the mock's logs can be correlated with the rest of the trace.
+fn request_span(headers: &HeaderMap, request_id: &HeaderValue) -> Span {
+    let trace_id = headers
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(trace_id);
+    // Only request ids that are valid strings are kept
+    let request_id = request_id.to_str().unwrap_or_default();
+    info_span!("request", request_id, trace_id)
+}
+
+/// The trace id of a W3C `traceparent` header, which looks like `00-<trace id>-<parent id>-<flags>`. Invalid headers
+/// have no trace id, as the spec says that they should be ignored.
+fn trace_id(traceparent: &str) -> Option<&str> {
+    let is_hex = |field: &str, len: usize| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let is_zero = |field: &str| field.bytes().all(|byte| byte == b'0');
+
+    let mut fields = traceparent.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    // Later versions may add fields, but version 00 has exactly four
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && !is_zero(trace_id)
+        && is_hex(parent_id, 16)
+        && !is_zero(parent_id)
+        && is_hex(flags, 2);
+
+    valid.then_some(trace_id)
+}
+
+async fn route_request<B>(
+    req: Request<B>,
+    listener_subgraph: Option<&str>,
+    state: Arc<State>,
+) -> anyhow::Result<ByteResponse>
 where
     B: Body,
     B::Error: Error + Send + Sync + 'static,
@@ -24,48 +162,410 @@ where
 
     let config = state.config.read().await;
 
-    let (res, generator_override) = match (&method, path) {
-        // matches routes in the form of `/{subgraph_name}`
+    // CORS preflight requests are answered immediately, without injecting any latency
+    if method == Method::OPTIONS
+        && let Some(cors) = &config.cors
+    {
+        return cors.preflight(&parts.headers);
+    }
+
+    // Held until the response is sent, so that latency counts towards the concurrency limit
+    let mut _permit = None;
+
+    let (mut res, subgraph_name) = match (&method, path) {
+        // Status checks are answered immediately, without injecting any latency
+        (&Method::GET, "/status") => {
+            let mut res = status(&state).await;
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        // As are cache statistics, see [graphql::cache_stats]
+        (&Method::GET, "/debug/cache") => {
+            let mut res = json_response(StatusCode::OK, &graphql::cache_stats().await);
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        (&Method::POST, "/debug/flush-cache") => {
+            let flushed = graphql::flush_caches().await;
+            let mut res = json_response(StatusCode::OK, &json!({ "flushed": flushed }));
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        // Health checks are answered immediately too, so that they can be polled until the server is ready
+        (&Method::GET, "/health") => {
+            let mut res = health(is_ready(&state, &config));
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        // Until the startup delay has passed, everything else is unavailable
+        _ if !is_ready(&state, &config) => {
+            trace!("responding before the startup delay has passed");
+            let mut res = Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(
+                    Full::new("Not ready\n".into())
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .map_err(|err| err.into());
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        (&Method::GET, "/supergraph") if config.supergraph_endpoint => {
+            let mut res = supergraph(&state).await;
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        (&Method::POST, "/uplink") if config.supergraph_endpoint => {
+            let mut res = uplink(&state, &body_bytes).await;
+            add_cors_headers(&config, &parts.headers, &mut res);
+            return res;
+        }
+
+        // matches routes in the form of `/{subgraph_name}`, or `/` for the base config
         // all further path elements will be ignored for the sake of not spending too much
         // compute time on this condition
-        (&Method::POST, route) if route.len() > 1 && route.starts_with('/') => {
-            let subgraph_name = route
-                .split('/')
-                .nth(1)
-                .expect("split will yield at least 2 elements based on the match condition");
-
-            (
-                graphql::handle(body_bytes, Some(subgraph_name), state.clone()).await,
-                config
-                    .subgraph_overrides
-                    .latency_generator
-                    .get(subgraph_name),
-            )
-        }
-        (&Method::POST, "/") => (graphql::handle(body_bytes, None, state.clone()).await, None),
-
-        // default to 404
-        (method, path) => {
-            warn!(%method, %path, "received unexpected request");
-            let mut resp = Response::new(
-                Full::new("Not found\n".into())
-                    .map_err(|never| match never {})
-                    .boxed(),
-            );
-            *resp.status_mut() = StatusCode::NOT_FOUND;
-
-            (Ok(resp), None)
+        (&Method::POST, route)
+            if allowed_methods(&config, listener_subgraph, route)
+                .is_some_and(|allow| allow.ends_with("POST")) =>
+        {
+            let subgraph_name = listener_subgraph.or_else(|| {
+                route
+                    .split('/')
+                    .nth(1)
+                    .filter(|subgraph_name| !subgraph_name.is_empty())
+            });
+
+            match state.request_permit(config.reject_excess_requests).await {
+                Ok(permit) => _permit = permit,
+                Err(_) => {
+                    trace!("rejecting request over the concurrency limit");
+                    let mut res = Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body(
+                            Full::new("Too many concurrent requests\n".into())
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        )
+                        .map_err(|err| err.into());
+                    add_cors_headers(&config, &parts.headers, &mut res);
+                    return res;
+                }
+            }
+
+            let mut resp = match &state.recorder {
+                Some(recorder) => {
+                    let request = body_bytes.clone();
+                    match graphql::handle(body_bytes, &parts.headers, subgraph_name, state.clone())
+                        .await
+                    {
+                        Ok(resp) => recorder.record(subgraph_name, &request, resp).await,
+                        Err(err) => Err(err),
+                    }
+                }
+                None => {
+                    graphql::handle(body_bytes, &parts.headers, subgraph_name, state.clone()).await
+                }
+            };
+
+            add_subgraph_name_header(&config, subgraph_name, &mut resp);
+            (resp, subgraph_name)
         }
+
+        // every other request is either for a known route with a method that it doesn't accept, or a 404
+        (method, path) => match allowed_methods(&config, listener_subgraph, path) {
+            Some(allow) => {
+                warn!(%method, %path, "received request with an unsupported method");
+                let resp = Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(ALLOW, allow)
+                    .body(
+                        Full::new("Method not allowed\n".into())
+                            .map_err(|never| match never {})
+                            .boxed(),
+                    )
+                    .map_err(|err| err.into());
+
+                (resp, None)
+            }
+            None => {
+                warn!(%method, %path, "received unexpected request");
+                let resp = json_response(
+                    StatusCode::NOT_FOUND,
+                    &json!({ "errors": [{ "message": "Not found" }] }),
+                );
+
+                (resp, None)
+            }
+        },
     };
 
-    // Skip latency injection when we have a non-2xx response
-    if res.is_ok() {
-        let latency = generator_override
-            .unwrap_or_else(|| &config.latency_generator)
-            .generate(Instant::now());
-        trace!(latency_ms = latency.as_millis(), "injecting latency");
-        sleep(latency).await;
+    // Skip latency injection when the request couldn't be handled at all
+    if let Ok(resp) = &res {
+        // Slow fields add to the generated latency, but a requested latency is exact
+        let latency = match latency_override(&config, &parts.headers) {
+            Some(latency) => Some(latency),
+            None => {
+                // Jitter comes from the request's own RNG when it has one, so that seeded requests are reproducible
+                let latency =
+                    latency_generator(&config, subgraph_name, resp.status()).map(|generator| {
+                        match resp.extensions().get::<JitterSeed>() {
+                            Some(JitterSeed(seed)) => generator
+                                .generate(Instant::now(), &mut StdRng::seed_from_u64(*seed)),
+                            None => generator.generate(Instant::now(), &mut rand::rng()),
+                        }
+                    });
+                match resp.extensions().get::<FieldLatency>() {
+                    Some(FieldLatency(field_latency)) if !field_latency.is_zero() => {
+                        Some(latency.unwrap_or_default() + *field_latency)
+                    }
+                    _ => latency,
+                }
+            }
+        };
+        if let Some(latency) = latency {
+            trace!(latency_ms = latency.as_millis(), status = %resp.status(), "injecting latency");
+            sleep(latency).await;
+        }
     }
 
+    add_cors_headers(&config, &parts.headers, &mut res);
     res
 }
+
+fn add_cors_headers(
+    config: &Config,
+    request_headers: &HeaderMap,
+    res: &mut anyhow::Result<ByteResponse>,
+) {
+    if let Some(cors) = &config.cors
+        && let Ok(resp) = res
+    {
+        cors.add_headers(request_headers, resp.headers_mut());
+    }
+}
+
+fn add_subgraph_name_header(
+    config: &Config,
+    subgraph_name: Option<&str>,
+    res: &mut anyhow::Result<ByteResponse>,
+) {
+    if !config.subgraph_name_header {
+        return;
+    }
+    if let Some(name) = subgraph_name.or(config.instance_name.as_deref())
+        && let Ok(value) = HeaderValue::try_from(name)
+        && let Ok(resp) = res
+    {
+        resp.headers_mut().insert(SUBGRAPH_NAME_HEADER, value);
+    }
+}
+
+/// The exact latency requested with the [LATENCY_OVERRIDE_HEADER], if overrides are allowed by the config. Invalid
+/// values are logged and ignored.
+fn latency_override(config: &Config, headers: &HeaderMap) -> Option<Duration> {
+    if !config.allow_latency_override {
+        return None;
+    }
+
+    let value = headers.get(LATENCY_OVERRIDE_HEADER)?;
+    match value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+    {
+        Some(latency_ms) => Some(Duration::from_millis(latency_ms)),
+        None => {
+            warn!(?value, "ignoring invalid {LATENCY_OVERRIDE_HEADER} header");
+            None
+        }
+    }
+}
+
+/// Selects the latency generator for a response based on its status. Every 2xx response is delayed, including
+/// those that only contain GraphQL errors. Other responses use the error latency when one is configured, or are
+/// returned immediately if `inject_latency_on_errors` is disabled.
+fn latency_generator<'a>(
+    config: &'a Config,
+    subgraph_name: Option<&str>,
+    status: StatusCode,
+) -> Option<&'a LatencyGenerator> {
+    let overrides = &config.subgraph_overrides;
+    // Only subgraphs with overrides have their own settings, any other subgraph name is handled with the base config
+    let subgraph_name =
+        subgraph_name.filter(|name| overrides.latency_generator.contains_key(*name));
+    let latency_generator = subgraph_name
+        .and_then(|name| overrides.latency_generator.get(name))
+        .unwrap_or(&config.latency_generator);
+
+    if status.is_success() {
+        return Some(latency_generator);
+    }
+
+    let (inject_on_errors, error_latency_generator) = match subgraph_name {
+        Some(name) => (
+            overrides
+                .inject_latency_on_errors
+                .get(name)
+                .copied()
+                .unwrap_or(config.inject_latency_on_errors),
+            overrides.error_latency_generator.get(name),
+        ),
+        None => (
+            config.inject_latency_on_errors,
+            config.error_latency_generator.as_ref(),
+        ),
+    };
+
+    inject_on_errors.then(|| error_latency_generator.unwrap_or(latency_generator))
+}
+
+/// The methods that `path` can be requested with, which are also sent in the `Allow` header of a `405 Method Not
+/// Allowed`. Status, health and schema routes are only read with `GET`, and every other path is a GraphQL endpoint
+/// that only accepts `POST`, apart from the reserved `/debug/` paths. Paths that can't be routed at all have no
+/// allowed methods.
+fn allowed_methods(
+    config: &Config,
+    listener_subgraph: Option<&str>,
+    path: &str,
+) -> Option<&'static str> {
+    let is_get_route = matches!(path, "/status" | "/health" | "/debug/cache")
+        || (path == "/supergraph" && config.supergraph_endpoint);
+
+    match (is_get_route, listener_subgraph.is_some()) {
+        // Dedicated listeners handle every other POST request as their subgraph, whatever its path
+        (true, true) => Some("GET, POST"),
+        (true, false) => Some("GET"),
+        (false, true) => Some("POST"),
+        (false, false) if path.starts_with("/debug/") && path != "/debug/flush-cache" => None,
+        (false, false) if path.starts_with('/') => Some("POST"),
+        (false, false) => None,
+    }
+}
+
+/// Whether the [Config::startup_delay] has passed
+fn is_ready(state: &State, config: &Config) -> bool {
+    state.started.elapsed() >= config.startup_delay
+}
+
+/// Respond with whether the server is ready to serve requests, as a 200 or a 503
+fn health(ready: bool) -> anyhow::Result<ByteResponse> {
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(status, &json!({ "ready": ready }))
+}
+
+/// Respond with the server's [SchemaStatus](crate::state::SchemaStatus) as JSON, so that failed schema reloads
+/// can be detected without scraping the logs
+async fn status(state: &State) -> anyhow::Result<ByteResponse> {
+    json_response(
+        StatusCode::OK,
+        &json!({ "schema": *state.schema_status.read().await }),
+    )
+}
+
+/// Respond with the SDL of the schema being served, so that it can be fetched as a router's schema
+async fn supergraph(state: &State) -> anyhow::Result<ByteResponse> {
+    let sdl = state.schema.read().await.sdl().to_string();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(
+            Full::new(sdl.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .map_err(|err| err.into())
+}
+
+/// How long routers are asked to wait between polls of [uplink]
+const UPLINK_MIN_DELAY_SECONDS: u64 = 10;
+
+/// Respond to a router's Uplink `SupergraphSdl` query with the schema being served, or with `Unchanged` when its
+/// `ifAfterId` variable shows that the router already has it. The id of a schema is the SHA-256 of its SDL, so that
+/// a router picks up a reloaded schema on its next poll.
+async fn uplink(state: &State, body: &[u8]) -> anyhow::Result<ByteResponse> {
+    let schema = state.schema.read().await;
+    let id = format!("{:x}", Sha256::digest(schema.sdl().as_bytes()));
+    let if_after_id = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|req| req["variables"]["ifAfterId"].as_str().map(str::to_string));
+
+    let router_config = if if_after_id.as_deref() == Some(id.as_str()) {
+        json!({
+            "__typename": "Unchanged",
+            "id": id,
+            "minDelaySeconds": UPLINK_MIN_DELAY_SECONDS,
+        })
+    } else {
+        json!({
+            "__typename": "RouterConfigResult",
+            "id": id,
+            "supergraphSdl": schema.sdl(),
+            "minDelaySeconds": UPLINK_MIN_DELAY_SECONDS,
+        })
+    };
+
+    json_response(
+        StatusCode::OK,
+        &json!({ "data": { "routerConfig": router_config } }),
+    )
+}
+
+fn json_response(
+    status: StatusCode,
+    body: &serde_json_bytes::Value,
+) -> anyhow::Result<ByteResponse> {
+    let body = serde_json::to_vec(body)?;
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(
+            Full::new(body.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .map_err(|err| err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_trace_ids() {
+        assert_eq!(
+            trace_id("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        // Future versions may add more fields
+        assert_eq!(
+            trace_id("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(trace_id(invalid), None, "{invalid} should be ignored");
+        }
+    }
+}
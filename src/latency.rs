@@ -1,4 +1,6 @@
 //! Simple latency generation
+use anyhow::anyhow;
+use rand::{Rng, RngExt};
 use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 use tokio::time::{Duration, Instant};
@@ -8,10 +10,27 @@ use tracing::trace;
 pub struct LatencyConfig {
     #[serde(deserialize_with = "humantime_serde::deserialize")]
     pub base: Duration,
-    pub saw: Option<Shape>,
+    pub saw: Option<Saw>,
     pub sine: Option<Shape>,
     pub square: Option<Shape>,
     pub triangle: Option<Shape>,
+    /// How far each request's latency randomly strays from the generated one, as a fraction of it: `0.2` scales
+    /// latencies by anywhere from 0.8 to 1.2. Fractions above 1 can take latencies down to zero, but not below.
+    #[serde(default)]
+    pub jitter_pct: f64,
+}
+
+impl LatencyConfig {
+    /// Checks for jitter that can't scale a latency
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.jitter_pct.is_finite() || self.jitter_pct < 0.0 {
+            return Err(anyhow!(
+                "latency.jitter_pct ({}) must be a non-negative number",
+                self.jitter_pct
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Default for LatencyConfig {
@@ -22,9 +41,11 @@ impl Default for LatencyConfig {
             sine: Some(Shape {
                 amplitude: Duration::from_millis(2),
                 period: Duration::from_secs(10),
+                phase: Duration::ZERO,
             }),
             square: None,
             triangle: None,
+            jitter_pct: 0.0,
         }
     }
 }
@@ -35,6 +56,38 @@ pub struct Shape {
     pub amplitude: Duration,
     #[serde(deserialize_with = "humantime_serde::deserialize")]
     pub period: Duration,
+    /// How far into its period the wave already is when the server starts, so that waves with the same period can
+    /// be offset from each other
+    #[serde(default, deserialize_with = "humantime_serde::deserialize")]
+    pub phase: Duration,
+}
+
+/// A saw wave, which ramps linearly across each period before jumping back to where it started
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Saw {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default)]
+    pub direction: SawDirection,
+}
+
+impl From<Shape> for Saw {
+    fn from(shape: Shape) -> Self {
+        Self {
+            shape,
+            direction: SawDirection::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SawDirection {
+    /// Rises from 0 towards the amplitude, then drops back to 0
+    #[default]
+    Rising,
+    /// Spikes to the amplitude, then decays towards 0
+    Falling,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -51,9 +104,30 @@ impl LatencyGenerator {
         }
     }
 
-    pub fn generate(&self, when: Instant) -> Duration {
+    /// The latency for a request made at `when`, based on the time elapsed since this generator was created and
+    /// varied by the configured jitter, which is drawn from `rng`
+    pub fn generate<R: Rng + ?Sized>(&self, when: Instant, rng: &mut R) -> Duration {
+        let latency = self.generate_at_elapsed(when.duration_since(self.start));
+        self.jitter(latency, rng)
+    }
+
+    /// Scales `latency` by a random factor within [LatencyConfig::jitter_pct] of 1
+    fn jitter<R: Rng + ?Sized>(&self, latency: Duration, rng: &mut R) -> Duration {
+        let jitter_pct = self.cfg.jitter_pct;
+        if jitter_pct == 0.0 {
+            return latency;
+        }
+
+        let factor = rng.random_range((1.0 - jitter_pct).max(0.0)..=1.0 + jitter_pct);
+        trace!("Jitter factor: {factor}");
+        latency.mul_f64(factor)
+    }
+
+    /// The latency for a request made `elapsed` after this generator was created. Useful for computing the
+    /// waveforms at an exact point in time, without depending on a clock.
+    pub fn generate_at_elapsed(&self, elapsed: Duration) -> Duration {
         let mut latency_ms = self.cfg.base.as_millis() as u64;
-        let elapsed_ms = when.duration_since(self.start).as_millis() as u64;
+        let elapsed_ms = elapsed.as_millis() as u64;
 
         trace!("Base latency: {latency_ms}");
         trace!("Elapsed: {elapsed_ms}");
@@ -77,28 +151,62 @@ impl LatencyGenerator {
 }
 
 #[inline(always)]
-fn saw_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
+fn saw_ms(
+    Saw {
+        shape: Shape {
+            amplitude,
+            period,
+            phase,
+        },
+        direction,
+    }: Saw,
+    elapsed: u64,
+) -> u64 {
     let amplitude = amplitude.as_millis() as u64;
     let period = period.as_millis() as u64;
+    let elapsed = elapsed + phase.as_millis() as u64;
 
     trace!(
         amplitude = amplitude,
         period = period,
         elapsed = elapsed,
+        ?direction,
         "Computing saw value",
     );
 
-    let result = ((elapsed % period) * amplitude) / period;
+    // A zero period has no ramp to climb
+    if period == 0 {
+        return 0;
+    }
 
-    trace!(result = result, "Saw value computed");
+    // Floating point keeps large amplitudes and periods from overflowing. The ramp is floored so that it rises from
+    // 0 towards, but never reaches, amplitude before dropping back to 0 at the start of the next period.
+    let phase = (elapsed % period) as f64 / period as f64; // 0.0 to 1.0 (exclusive)
+    let rising = (phase * amplitude as f64).floor() as u64; // 0 to amplitude (exclusive)
+
+    // A falling saw mirrors the rising one, so it starts each period at amplitude and never quite reaches 0
+    let result = match direction {
+        SawDirection::Rising => rising,
+        SawDirection::Falling => amplitude - rising,
+    };
+
+    trace!(phase = phase, result = result, "Saw value computed");
 
     result
 }
 
 #[inline(always)]
-fn sine_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
+fn sine_ms(
+    Shape {
+        amplitude,
+        period,
+        phase,
+    }: Shape,
+    elapsed: u64,
+) -> u64 {
     let amplitude = amplitude.as_millis() as u64;
     let period = period.as_millis() as u64;
+    let elapsed = elapsed + phase.as_millis() as u64;
 
     trace!(
         amplitude = amplitude,
@@ -122,9 +230,17 @@ fn sine_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
 }
 
 #[inline(always)]
-fn square_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
+fn square_ms(
+    Shape {
+        amplitude,
+        period,
+        phase,
+    }: Shape,
+    elapsed: u64,
+) -> u64 {
     let amplitude = amplitude.as_millis() as u64;
     let period = period.as_millis() as u64;
+    let elapsed = elapsed + phase.as_millis() as u64;
 
     trace!(
         amplitude = amplitude,
@@ -145,9 +261,17 @@ fn square_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
 }
 
 #[inline(always)]
-fn triangle_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
+fn triangle_ms(
+    Shape {
+        amplitude,
+        period,
+        phase,
+    }: Shape,
+    elapsed: u64,
+) -> u64 {
     let amplitude = amplitude.as_millis() as u64;
     let period = period.as_millis() as u64;
+    let elapsed = elapsed + phase.as_millis() as u64;
 
     trace!(
         amplitude = amplitude,
@@ -156,16 +280,236 @@ fn triangle_ms(Shape { amplitude, period }: Shape, elapsed: u64) -> u64 {
         "Computing triangle value",
     );
 
-    let position_in_period = elapsed % period;
-    let half_period = period / 2;
+    // A zero period has no slope to climb, and would otherwise divide by zero
+    if period == 0 {
+        return 0;
+    }
 
-    let result = if position_in_period < half_period {
-        (position_in_period * amplitude) / half_period
-    } else {
-        ((period - position_in_period) * amplitude) / half_period
-    };
+    // Floating point avoids dividing by a half period that truncates to zero for a 1ms period, as well as
+    // overflowing for large amplitudes
+    let phase = (elapsed % period) as f64 / period as f64; // 0.0 to 1.0 (exclusive)
+    let height = 1.0 - (2.0 * phase - 1.0).abs(); // 0.0 rising to 1.0 at half a period, then falling back
+    let result = (height * amplitude as f64)
+        .floor()
+        .clamp(0.0, amplitude as f64) as u64; // 0 to amplitude
 
     trace!(result = result, "Triangle value computed");
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    fn generator(base_ms: u64, shapes: impl FnOnce(&mut LatencyConfig)) -> LatencyGenerator {
+        let mut cfg = LatencyConfig {
+            base: Duration::from_millis(base_ms),
+            saw: None,
+            sine: None,
+            square: None,
+            triangle: None,
+            jitter_pct: 0.0,
+        };
+        shapes(&mut cfg);
+        LatencyGenerator::new(cfg)
+    }
+
+    fn shape(amplitude_ms: u64, period_ms: u64) -> Option<Shape> {
+        Some(Shape {
+            amplitude: Duration::from_millis(amplitude_ms),
+            period: Duration::from_millis(period_ms),
+            phase: Duration::ZERO,
+        })
+    }
+
+    /// Asserts the latency in milliseconds at each of the elapsed milliseconds in `expected`
+    fn assert_latencies(generator: LatencyGenerator, expected: &[(u64, u64)]) {
+        for &(elapsed_ms, latency_ms) in expected {
+            assert_eq!(
+                generator.generate_at_elapsed(Duration::from_millis(elapsed_ms)),
+                Duration::from_millis(latency_ms),
+                "latency at {elapsed_ms}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn base_only() {
+        assert_latencies(generator(7, |_| {}), &[(0, 7), (1234, 7)]);
+    }
+
+    #[test]
+    fn sine() {
+        let generator = generator(10, |cfg| cfg.sine = shape(20, 1000));
+        assert_latencies(
+            generator,
+            &[(0, 20), (250, 30), (500, 20), (750, 10), (1000, 20)],
+        );
+    }
+
+    #[test]
+    fn square() {
+        let generator = generator(10, |cfg| cfg.square = shape(5, 1000));
+        assert_latencies(
+            generator,
+            &[(0, 15), (499, 15), (500, 10), (999, 10), (1000, 15)],
+        );
+    }
+
+    #[test]
+    fn saw() {
+        let generator = generator(10, |cfg| cfg.saw = shape(20, 1000).map(Into::into));
+        assert_latencies(
+            generator,
+            &[
+                (0, 10),
+                (250, 15),
+                (500, 20),
+                (750, 25),
+                (999, 29),
+                (1000, 10),
+                (1500, 20),
+            ],
+        );
+    }
+
+    #[test]
+    fn falling_saw() {
+        let generator = generator(10, |cfg| {
+            cfg.saw = shape(20, 1000).map(|shape| Saw {
+                shape,
+                direction: SawDirection::Falling,
+            })
+        });
+        assert_latencies(
+            generator,
+            &[(0, 30), (250, 25), (500, 20), (999, 11), (1000, 30)],
+        );
+    }
+
+    #[test]
+    fn saw_does_not_overflow() {
+        // Multiplying a year long position by a year long amplitude would overflow integer math
+        let year_ms = 365 * 24 * 60 * 60 * 1000;
+        let generator = generator(0, |cfg| cfg.saw = shape(year_ms, year_ms).map(Into::into));
+        assert_latencies(generator, &[(year_ms / 2, year_ms / 2), (year_ms, 0)]);
+    }
+
+    #[test]
+    fn saw_with_zero_period() {
+        assert_latencies(
+            generator(10, |cfg| cfg.saw = shape(20, 0).map(Into::into)),
+            &[(0, 10), (5, 10)],
+        );
+    }
+
+    #[test]
+    fn triangle() {
+        let generator = generator(10, |cfg| cfg.triangle = shape(20, 1000));
+        assert_latencies(
+            generator,
+            &[
+                (0, 10),
+                (250, 20),
+                (500, 30),
+                (750, 20),
+                (1000, 10),
+                (1500, 30),
+            ],
+        );
+    }
+
+    #[test]
+    fn triangle_with_tiny_periods() {
+        // Half of these periods truncates to 0ms, and the phase regularly lands exactly on it
+        for period_ms in [0, 1, 2, 3] {
+            let generator = generator(0, |cfg| cfg.triangle = shape(20, period_ms));
+            for elapsed_ms in 0..10 {
+                let latency = generator.generate_at_elapsed(Duration::from_millis(elapsed_ms));
+                assert!(latency <= Duration::from_millis(20));
+            }
+        }
+        assert_latencies(
+            generator(0, |cfg| cfg.triangle = shape(20, 2)),
+            &[(0, 0), (1, 20)],
+        );
+    }
+
+    #[test]
+    fn phase_offsets() {
+        // Offset by a quarter period, the sine starts at its peak
+        let sine = generator(0, |cfg| {
+            cfg.sine = shape(20, 1000).map(|sine| Shape {
+                phase: Duration::from_millis(250),
+                ..sine
+            })
+        });
+        assert_latencies(sine, &[(0, 20), (250, 10), (500, 0)]);
+
+        // Offsets longer than a period wrap around
+        let saw = generator(0, |cfg| {
+            cfg.saw = shape(20, 1000).map(|saw| {
+                Shape {
+                    phase: Duration::from_millis(1250),
+                    ..saw
+                }
+                .into()
+            })
+        });
+        assert_latencies(saw, &[(0, 5), (750, 0)]);
+    }
+
+    #[test]
+    fn shapes_are_summed() {
+        let generator = generator(1, |cfg| {
+            cfg.sine = shape(20, 1000);
+            cfg.square = shape(5, 1000);
+        });
+        assert_latencies(generator, &[(250, 26), (750, 1)]);
+    }
+
+    #[test]
+    fn generate_uses_elapsed_time() {
+        let generator = generator(10, |cfg| cfg.square = shape(5, 1000));
+        let when = generator.start + Duration::from_millis(600);
+        assert_eq!(
+            generator.generate(when, &mut rand::rng()),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn jitter_scales_latency() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let base = Duration::from_millis(100);
+
+        let jittery = generator(100, |cfg| cfg.jitter_pct = 0.2);
+        let latencies: Vec<_> = (0..100).map(|_| jittery.jitter(base, &mut rng)).collect();
+        assert!(
+            latencies
+                .iter()
+                .all(|latency| (80..=120).contains(&latency.as_millis()))
+        );
+        assert!(latencies.iter().any(|latency| *latency < base));
+        assert!(latencies.iter().any(|latency| *latency > base));
+
+        // Latencies can be scaled down to zero, but no further
+        let very_jittery = generator(100, |cfg| cfg.jitter_pct = 1.5);
+        assert!(
+            (0..100).all(|_| very_jittery.jitter(base, &mut rng) <= Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn invalid_jitter_is_rejected() {
+        for jitter_pct in [-0.1, f64::NAN, f64::INFINITY] {
+            let cfg = LatencyConfig {
+                jitter_pct,
+                ..Default::default()
+            };
+            assert!(cfg.validate().is_err(), "{jitter_pct}");
+        }
+    }
+}
@@ -1,67 +1,611 @@
-use handle::handle_request;
-use hyper::service::service_fn;
+use anyhow::Context;
+use handle::{
+    graphql::{self, GraphQLRequest, Ratio},
+    handle_request, handle_subgraph_request,
+};
+use hyper::{
+    Version,
+    header::{CONNECTION, HeaderValue},
+    service::service_fn,
+};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto::Builder,
 };
-use state::{Config, State, default_port};
-use std::{fs, net::SocketAddr, path::PathBuf, sync::Arc};
-use tokio::net::TcpListener;
-use tracing::{error, info};
+use serde_json_bytes::{Value, serde_json};
+use state::{Config, FederatedSchema, HttpConfig, ServerConfig, State};
+use std::{
+    fs, future, io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    signal,
+    sync::{Notify, watch},
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
 
+mod error;
 pub mod handle;
 pub mod latency;
+pub mod outage;
+pub mod record;
 pub mod state;
+pub mod tls;
+
+pub use error::SubgraphMockError;
 
 /// A general purpose subgraph mock.
 #[derive(Debug, clap::Parser)]
+#[clap(
+    about,
+    name = "subgraph-mock",
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Serving is the default, so the arguments of `serve` are also accepted without naming it
+    #[command(flatten)]
+    pub serve: Args,
+}
+
+impl Cli {
+    /// The command to run, which is `serve` when none was given
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Serve(self.serve))
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Serve mock responses for a schema
+    Serve(Args),
+    /// Check that a schema loads and is patched for federation without errors, without serving it
+    Validate(ValidateArgs),
+    /// Print a response generated for a query, without serving it
+    Gen(GenArgs),
+}
+
+/// The arguments for serving mock responses
+#[derive(Debug, clap::Parser)]
 #[clap(about, name = "subgraph-mock", long_about = None)]
 pub struct Args {
     /// Path to the config file that should be used to configure the server
     #[arg(short, long)]
     pub config: Option<PathBuf>,
 
-    /// Path to the supergraph SDL that the server should mock
-    #[arg(short, long)]
-    pub schema: PathBuf,
+    /// Path to the supergraph SDL that the server should mock. May be passed multiple times, or point to a
+    /// directory, in order to merge a schema that is split across several files
+    #[arg(short, long, required = true)]
+    pub schema: Vec<PathBuf>,
+
+    /// IP address to listen on, overriding the `bind_address` from the config file
+    #[arg(long)]
+    pub bind: Option<IpAddr>,
+
+    /// Watch the schema files for changes and reload them, overriding `schema_watch.enabled` from the config file.
+    /// Pass `--watch=false` to only parse the schema once at startup.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub watch: Option<bool>,
+
+    /// Print the schema after all federation patching has been applied, then exit without starting the server
+    #[arg(long)]
+    pub print_schema: bool,
+
+    /// Port to listen on, overriding the `port` from the config file
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Whether to cache responses, overriding `cache_responses` from the config file and its subgraph overrides
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub cache_responses: Option<bool>,
+
+    /// The ratio of nullable fields that are null, like `1/3`, overriding `null_ratio` from the config file and its
+    /// subgraph overrides
+    #[arg(long, value_parser = parse_ratio)]
+    pub null_ratio: Option<Ratio>,
+}
+
+fn parse_ratio(ratio: &str) -> Result<Ratio, String> {
+    let invalid = || format!("`{ratio}` is not a ratio like `1/3`");
+    let (numerator, denominator) = ratio.split_once('/').ok_or_else(invalid)?;
+    let numerator = numerator.trim().parse().map_err(|_| invalid())?;
+    let denominator = denominator.trim().parse().map_err(|_| invalid())?;
+    if denominator == 0 || numerator > denominator {
+        return Err(format!("`{ratio}` must be between 0 and 1"));
+    }
+    Ok((numerator, denominator))
 }
 
 impl Args {
-    /// Load and initialise the configuration based on command line args
-    pub fn init(self) -> anyhow::Result<(u16, State)> {
-        let (port, config) = match self.config {
-            Some(path) => {
-                info!(path=%path.display(), "loading and parsing config file");
-                Config::parse_yaml(serde_yaml::from_slice(&fs::read(path)?)?)?
+    /// Parse and patch the schema exactly as the server would, returning the SDL of the resulting valid schema
+    pub fn patched_schema_sdl(&self) -> Result<String, SubgraphMockError> {
+        let (_, config) = load_config(self.config.as_ref())?;
+        Ok(FederatedSchema::parse_with_federation(&self.schema, &config.federation)?.to_string())
+    }
+
+    /// Load and initialise the configuration based on command line args. Flags take precedence over the config
+    /// file, which takes precedence over the defaults.
+    pub fn init(self) -> anyhow::Result<(ServerConfig, State)> {
+        let (mut server, mut config) = load_config(self.config.as_ref())?;
+
+        if let Some(bind) = self.bind {
+            server.listen_address.set_ip(bind);
+        }
+        if let Some(port) = self.port {
+            server.listen_address.set_port(port);
+        }
+        if let Some(watch) = self.watch {
+            config.schema_watch.enabled = watch;
+        }
+        if let Some(cache_responses) = self.cache_responses {
+            config.cache_responses = cache_responses;
+            for subgraph_cache_responses in config.subgraph_overrides.cache_responses.values_mut() {
+                *subgraph_cache_responses = cache_responses;
             }
-            None => {
-                info!("using default config");
-                (default_port(), Config::default())
+        }
+        if let Some(null_ratio) = self.null_ratio {
+            config.response_generation.null_ratio = Some(null_ratio);
+            for rgen_cfg in config.subgraph_overrides.response_generation.values_mut() {
+                rgen_cfg.null_ratio = Some(null_ratio);
             }
+        }
+
+        Ok((server, State::new(config, self.schema)?))
+    }
+}
+
+/// The arguments for validating a schema
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    /// Path to the config file whose `federation` settings the schema is patched with
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Path to the supergraph SDL to validate. May be passed multiple times, or point to a directory, in order to
+    /// merge a schema that is split across several files
+    #[arg(short, long, required = true)]
+    pub schema: Vec<PathBuf>,
+}
+
+impl ValidateArgs {
+    /// Parse and patch the schema exactly as the server would, returning the patched schema if it is valid
+    pub fn validate(&self) -> Result<FederatedSchema, SubgraphMockError> {
+        let (_, config) = load_config(self.config.as_ref())?;
+        FederatedSchema::parse_with_federation(&self.schema, &config.federation)
+    }
+}
+
+/// The arguments for generating a sample response
+#[derive(Debug, clap::Args)]
+pub struct GenArgs {
+    /// Path to the config file whose response generation settings are used
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Path to the supergraph SDL to generate a response for. May be passed multiple times, or point to a directory,
+    /// in order to merge a schema that is split across several files
+    #[arg(short, long, required = true)]
+    pub schema: Vec<PathBuf>,
+
+    /// Path to a file containing the query. The query is read from stdin if this isn't given, or is `-`
+    #[arg(short, long)]
+    pub query: Option<PathBuf>,
+
+    /// The name of the operation in the query to generate a response for
+    #[arg(long)]
+    pub operation_name: Option<String>,
+
+    /// The variables of the operation, as a JSON object
+    #[arg(long)]
+    pub variables: Option<String>,
+
+    /// Generate the response with this subgraph's overrides of the response generation settings
+    #[arg(long)]
+    pub subgraph: Option<String>,
+
+    /// Seed the response's randomness, so that the same seed always generates the same response. Without a seed,
+    /// the response is only reproducible when the config is `deterministic`.
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl GenArgs {
+    /// Generate a response for the query, just as the server would for a request at the given subgraph. Its
+    /// randomness is seeded the same way as a request's would be, from the seed or with `deterministic` set.
+    pub fn generate(&self) -> anyhow::Result<Value> {
+        let (_, config) = load_config(self.config.as_ref())?;
+        let schema = FederatedSchema::parse_with_federation(&self.schema, &config.federation)?;
+        let query = match &self.query {
+            Some(path) if path.as_os_str() != "-" => fs::read_to_string(path)
+                .with_context(|| format!("unable to read {}", path.display()))?,
+            _ => io::read_to_string(io::stdin()).context("unable to read the query from stdin")?,
+        };
+        let req = GraphQLRequest {
+            query,
+            operation_name: self.operation_name.clone(),
+            variables: match &self.variables {
+                Some(variables) => serde_json::from_str(variables).context("invalid variables")?,
+                None => Default::default(),
+            },
         };
+        let rgen_cfg = self
+            .subgraph
+            .as_ref()
+            .and_then(|name| config.subgraph_overrides.response_generation.get(name))
+            .unwrap_or(&config.response_generation);
+        let mut rng = graphql::request_rng(
+            &config,
+            self.subgraph.as_deref(),
+            self.seed,
+            &serde_json::to_vec(&req)?,
+        );
 
-        Ok((port, State::new(config, self.schema)?))
+        let now = graphql::reference_time(&config, self.seed);
+
+        graphql::generate_response_value(
+            &mut rng,
+            now,
+            rgen_cfg,
+            &schema,
+            &req.query,
+            req.operation_name.as_deref(),
+            &req.variables,
+        )
     }
 }
 
-/// Run the server loop with the provided [State]
-pub async fn mock_server_loop(port: u16, state: State) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
-    info!(%port, "subgraph mock server now listening");
+fn load_config(path: Option<&PathBuf>) -> Result<(ServerConfig, Config), SubgraphMockError> {
+    match path {
+        Some(path) => {
+            info!(path=%path.display(), "loading and parsing config file");
+            let source = fs::read_to_string(path).map_err(SubgraphMockError::io(path))?;
+            let yaml = serde_yaml::from_str(&Config::interpolate_env(&source)?)
+                .map_err(|err| SubgraphMockError::Config(err.into()))?;
+            Config::parse_yaml(yaml)
+        }
+        None => {
+            info!("using default config");
+            Ok((ServerConfig::default(), Config::default()))
+        }
+    }
+}
+
+/// How long in-flight connections are given to finish once a shutdown signal has been received
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run the server loop with the provided [State] until the process receives SIGINT or SIGTERM
+///
+/// Responses for any [Config::warmup] operations are generated and cached before listening. Along with the main
+/// listener, every subgraph in [ServerConfig::subgraph_ports] gets a listener of its own that serves all requests
+/// as that subgraph. All listeners share the same [State].
+pub async fn mock_server_loop(server: ServerConfig, state: State) -> anyhow::Result<()> {
+    let options = ConnectionOptions::new(&server)?;
+    graphql::warm_up(&state).await;
+
+    let listener = TcpListener::bind(server.listen_address)
+        .await
+        .with_context(|| format!("failed to listen on {}", server.listen_address))?;
+    info!(
+        listen_address = %server.listen_address,
+        tls = options.tls.is_some(),
+        http2_only = options.http2_only,
+        "subgraph mock server now listening"
+    );
+    let mut listeners = vec![(listener, options.clone())];
+
+    for (subgraph_name, port) in &server.subgraph_ports {
+        let listen_address = SocketAddr::new(server.listen_address.ip(), *port);
+        let listener = TcpListener::bind(listen_address).await.with_context(|| {
+            format!("failed to listen on {listen_address} for subgraph {subgraph_name}")
+        })?;
+        info!(%listen_address, subgraph = %subgraph_name, "subgraph now listening on its own port");
+
+        let options = ConnectionOptions {
+            subgraph: Some(subgraph_name.as_str().into()),
+            ..options.clone()
+        };
+        listeners.push((listener, options));
+    }
 
     let state = Arc::new(state);
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut servers = JoinSet::new();
+    for (listener, options) in listeners {
+        let mut shutdown_rx = shutdown_rx.clone();
+        servers.spawn(serve_connections(
+            listener,
+            options,
+            state.clone(),
+            async move {
+                let _ = shutdown_rx.changed().await;
+            },
+        ));
+    }
+
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        // Listeners only stop on their own if accepting connections fails
+        Some(result) = servers.join_next() => result??,
+    }
+
+    // Receivers only fail to be notified if their listener has already stopped
+    let _ = shutdown_tx.send(());
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+/// Serve `schema` on `addr` using `config` until the process receives SIGINT or SIGTERM.
+///
+/// This is the entrypoint for running the mock server programmatically, without any config or schema files. Fails
+/// without serving if the response fixtures, subgraph schemas or recording file that `config` points to can't be
+/// loaded.
+pub async fn serve(
+    config: Config,
+    schema: FederatedSchema,
+    addr: SocketAddr,
+) -> anyhow::Result<()> {
+    let server = ServerConfig {
+        listen_address: addr,
+        ..Default::default()
+    };
+    mock_server_loop(server, State::from_schema(config, schema)?).await
+}
+
+/// How each accepted connection should be served
+#[derive(Clone, Default)]
+pub struct ConnectionOptions {
+    /// If set, every accepted connection must complete a TLS handshake before it is served
+    pub tls: Option<TlsAcceptor>,
+    /// Only speak HTTP/2. Without TLS this means clients must use h2c with prior knowledge.
+    pub http2_only: bool,
+    /// How long connections are kept open, and for how many requests
+    pub http: HttpConfig,
+    /// If set, every request is handled as this subgraph instead of being routed by its path
+    pub subgraph: Option<Arc<str>>,
+}
+
+impl ConnectionOptions {
+    /// Resolve the options for a [ServerConfig], loading any TLS certificates from disk
+    pub fn new(server: &ServerConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            tls: server
+                .tls
+                .as_ref()
+                .map(|tls| tls.acceptor(server.http2_only))
+                .transpose()?,
+            http2_only: server.http2_only,
+            http: server.http,
+            subgraph: None,
+        })
+    }
+}
+
+/// Accept and serve connections on `listener` until `shutdown` resolves. Once it does, no new connections are
+/// accepted and open connections are gracefully closed, waiting up to [SHUTDOWN_TIMEOUT] for in-flight requests
+/// to complete before they are aborted.
+pub async fn serve_connections(
+    listener: TcpListener,
+    options: ConnectionOptions,
+    state: Arc<State>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
     loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
-
-        let state = state.clone();
-        tokio::spawn(async move {
-            if let Err(err) = Builder::new(TokioExecutor::new())
-                .serve_connection(io, service_fn(|req| handle_request(req, state.clone())))
-                .await
-            {
-                error!(%err, "server error");
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+
+                let state = state.clone();
+                let shutdown_rx = shutdown_rx.clone();
+                let options = options.clone();
+                connections.spawn(async move {
+                    match &options.tls {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(stream) => serve_connection(stream, &options, state, shutdown_rx).await,
+                            Err(err) => warn!(%err, %peer, "TLS handshake failed"),
+                        },
+                        None => serve_connection(stream, &options, state, shutdown_rx).await,
+                    }
+                });
             }
-        });
+            // Reap finished connections so that the set doesn't grow for the lifetime of the server
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = &mut shutdown => break,
+        }
+    }
+
+    info!(
+        connections = connections.len(),
+        "shutting down, waiting for in-flight connections to close"
+    );
+    drop(listener);
+    state.shut_down();
+    // Receivers only fail to be notified if their connection has already finished
+    let _ = shutdown_tx.send(());
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if timeout(SHUTDOWN_TIMEOUT, drain).await.is_err() {
+        warn!(
+            connections = connections.len(),
+            "timed out waiting for connections to close, aborting them"
+        );
+        connections.shutdown().await;
+    }
+
+    info!("subgraph mock server shut down");
+    Ok(())
+}
+
+/// Serve a single connection until it closes, or gracefully shut it down once `shutdown_rx` is notified. Connections
+/// are also gracefully shut down once they've been idle for [HttpConfig::keep_alive_timeout], or have been used for
+/// [HttpConfig::max_requests_per_connection].
+async fn serve_connection<I>(
+    io: I,
+    options: &ConnectionOptions,
+    state: Arc<State>,
+    mut shutdown_rx: watch::Receiver<()>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut builder = Builder::new(TokioExecutor::new());
+    if options.http2_only {
+        builder = builder.http2_only();
+    }
+    // Failing a request makes hyper abort the connection (or the stream, for HTTP/2) without a response, which is
+    // how a simulated reset is done. Those failures are expected, so they aren't logged as errors.
+    let reset = Arc::new(AtomicBool::new(false));
+    let served = Arc::new(AtomicUsize::new(0));
+    let used_up = Arc::new(Notify::new());
+    let (in_flight_tx, in_flight_rx) = watch::channel(0usize);
+    let in_flight_tx = Arc::new(in_flight_tx);
+    let max_requests = options.http.max_requests_per_connection;
+    let conn = builder.serve_connection(
+        TokioIo::new(io),
+        service_fn(|req| {
+            let state = state.clone();
+            let subgraph = options.subgraph.clone();
+            let reset = reset.clone();
+            let used_up = used_up.clone();
+            let in_flight_tx = in_flight_tx.clone();
+            let last = max_requests.is_some_and(|max_requests| {
+                served.fetch_add(1, Ordering::Relaxed) + 1 >= max_requests
+            });
+            async move {
+                let _in_flight = InFlight::new(in_flight_tx);
+                let version = req.version();
+                let mut result = match subgraph {
+                    Some(subgraph_name) => {
+                        handle_subgraph_request(req, &subgraph_name, state).await
+                    }
+                    None => handle_request(req, state).await,
+                };
+                if let Err(err) = &result
+                    && err.is::<graphql::ConnectionReset>()
+                {
+                    reset.store(true, Ordering::Relaxed);
+                }
+                if last {
+                    // HTTP/2 has no connection header, its clients are told to stop with a GOAWAY instead
+                    if version < Version::HTTP_2
+                        && let Ok(resp) = &mut result
+                    {
+                        resp.headers_mut()
+                            .insert(CONNECTION, HeaderValue::from_static("close"));
+                    }
+                    used_up.notify_one();
+                }
+                result
+            }
+        }),
+    );
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown_rx.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+        _ = used_up.notified() => {
+            debug!("closing connection after its last request");
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+        _ = idle(in_flight_rx, options.http.keep_alive_timeout) => {
+            debug!("closing idle connection");
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+
+    match result {
+        Err(err) if reset.load(Ordering::Relaxed) => debug!(%err, "connection reset"),
+        Err(err) => error!(%err, "server error"),
+        Ok(()) => {}
+    }
+}
+
+/// Counts a request as in flight on its connection for as long as it is held, including when hyper drops the request
+/// before it finishes
+struct InFlight(Arc<watch::Sender<usize>>);
+
+impl InFlight {
+    fn new(in_flight: Arc<watch::Sender<usize>>) -> Self {
+        in_flight.send_modify(|in_flight| *in_flight += 1);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+/// Resolves once a connection has had no requests in flight for `keep_alive_timeout`, counting from when it was
+/// opened, so that connections that never send a request are closed as well. Never resolves without a timeout.
+async fn idle(mut in_flight: watch::Receiver<usize>, keep_alive_timeout: Option<Duration>) {
+    let Some(keep_alive_timeout) = keep_alive_timeout else {
+        return future::pending().await;
+    };
+    // The sender lives as long as the connection, so waiting can't fail
+    loop {
+        let _ = in_flight.wait_for(|in_flight| *in_flight == 0).await;
+        if timeout(keep_alive_timeout, in_flight.changed())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Resolves once the process receives either SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            error!(%err, "unable to listen for SIGINT");
+            future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => _ = sigterm.recv().await,
+            Err(err) => {
+                error!(%err, "unable to listen for SIGTERM");
+                future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
     }
 }
@@ -1,17 +1,29 @@
 use clap::Parser;
-use std::panic::set_hook;
-use subgraph_mock::{Args, mock_server_loop};
+use serde_json_bytes::serde_json;
+use std::{io, panic::set_hook, process::ExitCode};
+use subgraph_mock::{Cli, Command, mock_server_loop};
 use tracing::error;
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
-    fmt,
+    fmt::{self, writer::BoxMakeWriter},
     prelude::*,
 };
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
+    let command = Cli::parse().command();
+    // Generated responses are printed to stdout, which logs mustn't get mixed into
+    let log_writer = match command {
+        Command::Gen(_) => BoxMakeWriter::new(io::stderr),
+        _ => BoxMakeWriter::new(io::stdout),
+    };
     tracing_subscriber::registry()
-        .with(fmt::layer().compact().with_target(false))
+        .with(
+            fmt::layer()
+                .compact()
+                .with_target(false)
+                .with_writer(log_writer),
+        )
         .with(
             EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
@@ -33,6 +45,31 @@ async fn main() -> anyhow::Result<()> {
         }
     }));
 
-    let (port, state) = Args::parse().init()?;
-    mock_server_loop(port, state).await
+    let args = match command {
+        Command::Serve(args) => args,
+        Command::Validate(args) => {
+            return Ok(match args.validate() {
+                Ok(_) => {
+                    println!("schema is valid");
+                    ExitCode::SUCCESS
+                }
+                Err(err) => {
+                    eprintln!("{:#}", anyhow::Error::from(err));
+                    ExitCode::FAILURE
+                }
+            });
+        }
+        Command::Gen(args) => {
+            println!("{}", serde_json::to_string_pretty(&args.generate()?)?);
+            return Ok(ExitCode::SUCCESS);
+        }
+    };
+    if args.print_schema {
+        print!("{}", args.patched_schema_sdl()?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let (server, state) = args.init()?;
+    mock_server_loop(server, state).await?;
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,102 @@
+//! Scheduled outages, during which every request fails
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// A subgraph that is down for `duration` at the end of every `every`, like a restart on a fixed schedule
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct OutageConfig {
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    pub every: Duration,
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    pub duration: Duration,
+}
+
+impl OutageConfig {
+    /// Checks for schedules that can't be followed
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // Schedules are followed to the millisecond
+        if self.every < Duration::from_millis(1) {
+            return Err(anyhow!("outage.every must be at least 1ms"));
+        }
+        if self.duration > self.every {
+            return Err(anyhow!(
+                "outage.duration ({:?}) is longer than outage.every ({:?})",
+                self.duration,
+                self.every
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Tracks an [OutageConfig] against the time the server started, the same way the
+/// [LatencyGenerator](crate::latency::LatencyGenerator) does for latency waveforms
+#[derive(Debug, Clone, Copy)]
+pub struct OutageSchedule {
+    start: Instant,
+    cfg: OutageConfig,
+}
+
+impl OutageSchedule {
+    pub fn new(cfg: OutageConfig) -> Self {
+        Self {
+            start: Instant::now(),
+            cfg,
+        }
+    }
+
+    /// How long the outage that is ongoing at `when` will last for, or [None] if the subgraph is up
+    pub fn remaining(&self, when: Instant) -> Option<Duration> {
+        self.remaining_at_elapsed(when.duration_since(self.start))
+    }
+
+    /// The same as [OutageSchedule::remaining], for a request made `elapsed` after the schedule was created
+    pub fn remaining_at_elapsed(&self, elapsed: Duration) -> Option<Duration> {
+        let every = self.cfg.every.as_millis();
+        let position = elapsed.as_millis() % every;
+        let outage_start = every - self.cfg.duration.as_millis();
+
+        (position >= outage_start).then(|| Duration::from_millis((every - position) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outages_end_every_cycle() {
+        let schedule = OutageSchedule::new(OutageConfig {
+            every: Duration::from_secs(10),
+            duration: Duration::from_secs(2),
+        });
+
+        for (elapsed_ms, remaining_ms) in [
+            (0, None),
+            (7999, None),
+            (8000, Some(2000)),
+            (9999, Some(1)),
+            (10000, None),
+            (18500, Some(1500)),
+        ] {
+            assert_eq!(
+                schedule.remaining_at_elapsed(Duration::from_millis(elapsed_ms)),
+                remaining_ms.map(Duration::from_millis),
+                "remaining outage at {elapsed_ms}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn schedules_shorter_than_a_millisecond_are_rejected() {
+        let outage = |every| OutageConfig {
+            every,
+            duration: Duration::ZERO,
+        };
+
+        assert!(outage(Duration::ZERO).validate().is_err());
+        assert!(outage(Duration::from_micros(999)).validate().is_err());
+        assert!(outage(Duration::from_millis(1)).validate().is_ok());
+    }
+}
@@ -0,0 +1,99 @@
+//! Recording of every GraphQL request and the response it got, for building regression fixtures
+use crate::handle::ByteResponse;
+use anyhow::Context;
+use http_body_util::{BodyExt, Full};
+use hyper::Response;
+use serde::{Deserialize, Serialize};
+use serde_json_bytes::{Map, Value, serde_json};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+use tracing::{error, info};
+
+/// Where requests and their responses are recorded to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordConfig {
+    /// The JSONL file that records are appended to, which is created if it doesn't exist
+    pub path: PathBuf,
+}
+
+/// Appends records to a file from a thread of its own, so that requests never wait on the disk. The thread stops
+/// once the recorder is dropped.
+#[derive(Debug)]
+pub struct Recorder {
+    records: Sender<Vec<u8>>,
+}
+
+impl Recorder {
+    pub fn new(cfg: &RecordConfig) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&cfg.path)
+            .with_context(|| format!("unable to open {} for recording", cfg.path.display()))?;
+        info!(path=%cfg.path.display(), "recording requests and responses");
+
+        let (records, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || write_records(rx, file))?;
+
+        Ok(Self { records })
+    }
+
+    /// Record the `request` body that was sent to `subgraph_name` along with the `response` to it, which is returned
+    /// unchanged apart from its body having been read into memory
+    pub async fn record(
+        &self,
+        subgraph_name: Option<&str>,
+        request: &[u8],
+        response: ByteResponse,
+    ) -> anyhow::Result<ByteResponse> {
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+
+        // Maps are built by hand since json! would sort the keys
+        let mut record = Map::new();
+        record.insert(
+            "subgraph",
+            subgraph_name.map(Value::from).unwrap_or_default(),
+        );
+        record.insert("request", json_or_string(request));
+        record.insert("status", parts.status.as_u16().into());
+        record.insert("response", json_or_string(&body));
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        // The recording thread only stops once this recorder has been dropped
+        let _ = self.records.send(line);
+
+        Ok(Response::from_parts(
+            parts,
+            Full::new(body).map_err(|never| match never {}).boxed(),
+        ))
+    }
+}
+
+/// Bodies are recorded as JSON where possible, so that the records are easy to read and query
+fn json_or_string(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::from(String::from_utf8_lossy(body).into_owned()))
+}
+
+/// Writes every record received to `file`, flushing whenever there are no more records waiting to be written
+fn write_records(records: Receiver<Vec<u8>>, file: File) {
+    let mut writer = BufWriter::new(file);
+    while let Ok(record) = records.recv() {
+        let result = std::iter::once(record)
+            .chain(records.try_iter())
+            .try_for_each(|record| writer.write_all(&record))
+            .and_then(|()| writer.flush());
+        if let Err(err) = result {
+            error!(%err, "unable to write recording");
+        }
+    }
+}
@@ -1,69 +1,233 @@
+use super::{FederationConfig, SchemaWatchConfig};
 use crate::{
-    handle::graphql::ResponseGenerationConfig,
+    error::SubgraphMockError,
+    handle::{cors::CorsConfig, graphql::ResponseGenerationConfig},
     latency::{LatencyConfig, LatencyGenerator},
+    outage::{OutageConfig, OutageSchedule},
+    record::RecordConfig,
+    tls::TlsConfig,
 };
 use anyhow::Error;
 use hyper::{
     HeaderMap,
     header::{HeaderName, HeaderValue},
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de};
 use serde_json_bytes::serde_json;
 use serde_yaml::Value;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, mem,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 use tracing::{info, warn};
 
 /// Allowed in the YAML, but not represented in the [BaseConfig] struct as we
 /// neither want nor need that data structure to be recursive.
 const SUBGRAPH_OVERRIDES_KEY: &str = "subgraph_overrides";
 
+/// Also kept out of the [BaseConfig] struct, since each profile is resolved against the base `response_generation`
+const PROFILES_KEY: &str = "profiles";
+
+/// Only allowed in subgraph overrides, where it is a path or list of paths to the subgraph's own schema
+const SUBGRAPH_SCHEMA_KEY: &str = "schema";
+
+/// Keys that configure the server as a whole, which are ignored in subgraph overrides
+const GLOBAL_KEYS: [&str; 22] = [
+    "bind_address",
+    "tls",
+    "http2_only",
+    "http",
+    "warmup",
+    "response_fixtures",
+    "federation",
+    "schema_watch",
+    "allow_latency_override",
+    "allow_error_override",
+    "allow_seed_override",
+    "cors",
+    "startup_delay",
+    "record",
+    "instance_name",
+    "subgraph_name_header",
+    "max_concurrent_requests",
+    "reject_excess_requests",
+    "deterministic",
+    "supergraph_endpoint",
+    "profiles",
+    "field_latency",
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BaseConfig {
     #[serde(default = "default_port")]
     pub port: u16,
+    #[serde(
+        default = "default_bind_address",
+        deserialize_with = "deserialize_bind_address"
+    )]
+    pub bind_address: IpAddr,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub http2_only: bool,
     #[serde(default)]
-    pub headers: HashMap<String, String>,
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
     #[serde(default)]
     pub latency: LatencyConfig,
     #[serde(default)]
+    pub error_latency: Option<LatencyConfig>,
+    #[serde(default = "default_inject_latency_on_errors")]
+    pub inject_latency_on_errors: bool,
+    #[serde(default)]
+    pub outage: Option<OutageConfig>,
+    #[serde(default)]
     pub response_generation: ResponseGenerationConfig,
     #[serde(default = "default_cache_responses")]
     pub cache_responses: bool,
+    #[serde(default)]
+    pub warmup: Vec<PathBuf>,
+    #[serde(default)]
+    pub response_fixtures: Option<PathBuf>,
+    #[serde(default)]
+    pub federation: FederationConfig,
+    #[serde(default)]
+    pub schema_watch: SchemaWatchConfig,
+    #[serde(default)]
+    pub allow_latency_override: bool,
+    #[serde(default)]
+    pub allow_error_override: bool,
+    #[serde(default)]
+    pub allow_seed_override: bool,
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    #[serde(default, deserialize_with = "humantime_serde::deserialize")]
+    pub startup_delay: Duration,
+    #[serde(default)]
+    pub record: Option<RecordConfig>,
+    #[serde(default)]
+    pub instance_name: Option<String>,
+    #[serde(default = "default_subgraph_name_header")]
+    pub subgraph_name_header: bool,
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+    #[serde(default)]
+    pub reject_excess_requests: bool,
+    #[serde(default)]
+    pub deterministic: bool,
+    #[serde(default)]
+    pub supergraph_endpoint: bool,
+    #[serde(default, deserialize_with = "deserialize_field_latency")]
+    pub field_latency: HashMap<String, Duration>,
 }
 
 pub fn default_port() -> u16 {
     8080
 }
 
+pub fn default_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn deserialize_bind_address<'de, D>(deserializer: D) -> Result<IpAddr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let address = String::deserialize(deserializer)?;
+    address
+        .parse()
+        .map_err(|err| de::Error::custom(format!("invalid bind_address `{address}`: {err}")))
+}
+
+fn deserialize_field_latency<'de, D>(deserializer: D) -> Result<HashMap<String, Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let field_latency =
+        HashMap::<String, humantime_serde::Serde<Duration>>::deserialize(deserializer)?;
+    field_latency
+        .into_iter()
+        .map(|(coordinate, latency)| match coordinate.split_once('.') {
+            Some((ty, field)) if !ty.is_empty() && !field.is_empty() && !field.contains('.') => {
+                Ok((coordinate, latency.into_inner()))
+            }
+            _ => Err(de::Error::custom(format!(
+                "invalid field_latency key `{coordinate}`, expected `Type.field`"
+            ))),
+        })
+        .collect()
+}
+
 fn default_cache_responses() -> bool {
     true
 }
 
+fn default_inject_latency_on_errors() -> bool {
+    true
+}
+
+fn default_subgraph_name_header() -> bool {
+    true
+}
+
 impl Default for BaseConfig {
     fn default() -> Self {
         Self {
             port: default_port(),
+            bind_address: default_bind_address(),
+            tls: None,
+            http2_only: false,
+            http: Default::default(),
             headers: Default::default(),
             latency: Default::default(),
+            error_latency: None,
+            inject_latency_on_errors: default_inject_latency_on_errors(),
+            outage: None,
             response_generation: Default::default(),
             cache_responses: default_cache_responses(),
+            warmup: Default::default(),
+            response_fixtures: None,
+            federation: Default::default(),
+            schema_watch: Default::default(),
+            allow_latency_override: false,
+            allow_error_override: false,
+            allow_seed_override: false,
+            cors: None,
+            startup_delay: Duration::ZERO,
+            record: None,
+            instance_name: None,
+            subgraph_name_header: default_subgraph_name_header(),
+            max_concurrent_requests: None,
+            reject_excess_requests: false,
+            deterministic: false,
+            supergraph_endpoint: false,
+            field_latency: Default::default(),
         }
     }
 }
 
 impl BaseConfig {
-    pub fn into_parts(
-        self,
-    ) -> anyhow::Result<(
-        u16,
-        bool,
-        LatencyGenerator,
-        HeaderMap<HeaderValue>,
-        ResponseGenerationConfig,
-    )> {
+    pub fn into_parts(self) -> anyhow::Result<ConfigParts> {
+        self.latency.validate()?;
         info!(config=%serde_json::to_string(&self.latency).unwrap(), "latency generation");
         let latency_generator = LatencyGenerator::new(self.latency);
 
+        if let Some(error_latency) = &self.error_latency {
+            error_latency.validate()?;
+            info!(config=%serde_json::to_string(error_latency).unwrap(), "error latency generation");
+        }
+        let error_latency_generator = self.error_latency.map(LatencyGenerator::new);
+
+        if let Some(outage) = &self.outage {
+            outage.validate()?;
+            info!(config=%serde_json::to_string(outage).unwrap(), "outage schedule");
+        }
+        let outage = self.outage.map(OutageSchedule::new);
+
         info!(headers=%serde_json::to_string(&self.headers).unwrap(), "additional headers");
         let additional_headers: anyhow::Result<HeaderMap<HeaderValue>> = self
             .headers
@@ -73,16 +237,87 @@ impl BaseConfig {
 
         let mut response_generation = self.response_generation;
         response_generation.merge_default_scalars();
+        response_generation.validate()?;
 
         info!(config=%serde_json::to_string(&response_generation).unwrap(), "response generation");
 
-        Ok((
-            self.port,
-            self.cache_responses,
+        Ok(ConfigParts {
+            server: ServerConfig {
+                listen_address: SocketAddr::new(self.bind_address, self.port),
+                tls: self.tls,
+                http2_only: self.http2_only,
+                http: self.http,
+                subgraph_ports: Default::default(),
+            },
+            cache_responses: self.cache_responses,
             latency_generator,
-            additional_headers?,
+            error_latency_generator,
+            inject_latency_on_errors: self.inject_latency_on_errors,
+            outage,
+            headers: additional_headers?,
             response_generation,
-        ))
+        })
+    }
+}
+
+/// A [BaseConfig] resolved into the values that make up a [Config], or one subgraph's entries in its
+/// [SubgraphOverrides]
+struct ConfigParts {
+    server: ServerConfig,
+    cache_responses: bool,
+    latency_generator: LatencyGenerator,
+    error_latency_generator: Option<LatencyGenerator>,
+    inject_latency_on_errors: bool,
+    outage: Option<OutageSchedule>,
+    headers: HeaderMap<HeaderValue>,
+    response_generation: ResponseGenerationConfig,
+}
+
+/// Settings for the listening server itself, which can't be overridden per subgraph
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen_address: SocketAddr,
+    pub tls: Option<TlsConfig>,
+    /// Only speak HTTP/2, which over plaintext means h2c with prior knowledge
+    pub http2_only: bool,
+    /// How long connections are kept open, and for how many requests
+    pub http: HttpConfig,
+    /// Subgraphs that are served on their own port, in addition to being routable by path on the main port
+    pub subgraph_ports: BTreeMap<String, u16>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: SocketAddr::new(default_bind_address(), default_port()),
+            tls: None,
+            http2_only: false,
+            http: Default::default(),
+            subgraph_ports: Default::default(),
+        }
+    }
+}
+
+/// Limits on how long a connection is reused, for simulating a server that recycles its connections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// How long an idle connection is kept open after responding to a request, or after it was opened if it hasn't
+    /// sent one yet. Kept open indefinitely when [None].
+    #[serde(default, with = "humantime_serde")]
+    pub keep_alive_timeout: Option<Duration>,
+    /// How many requests a connection is used for before it's closed. Unlimited when [None].
+    #[serde(default)]
+    pub max_requests_per_connection: Option<usize>,
+}
+
+impl HttpConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_requests_per_connection == Some(0) {
+            return Err(Error::msg(
+                "http.max_requests_per_connection must be at least 1",
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -90,17 +325,76 @@ impl BaseConfig {
 pub struct Config {
     pub headers: HeaderMap<HeaderValue>,
     pub latency_generator: LatencyGenerator,
+    /// Used instead of [Config::latency_generator] for responses with a non-2xx status, if set
+    pub error_latency_generator: Option<LatencyGenerator>,
+    /// Whether responses with a non-2xx status are delayed at all
+    pub inject_latency_on_errors: bool,
+    /// When set, every request fails with a 503 while an outage is scheduled
+    pub outage: Option<OutageSchedule>,
     pub response_generation: ResponseGenerationConfig,
     pub cache_responses: bool,
     pub subgraph_overrides: SubgraphOverrides,
+    /// Response generation configs that requests can select by name with the
+    /// [PROFILE_HEADER](crate::handle::graphql::PROFILE_HEADER), each of which overrides the base
+    /// `response_generation`
+    pub profiles: HashMap<String, ResponseGenerationConfig>,
+    /// Files containing operations whose responses are generated and cached on startup
+    pub warmup: Vec<PathBuf>,
+    /// A directory of `<operation name>.json` files whose contents are the responses to those operations
+    pub response_fixtures: Option<PathBuf>,
+    /// How the schema is patched to support federation
+    pub federation: FederationConfig,
+    /// How the schema files are watched for changes
+    pub schema_watch: SchemaWatchConfig,
+    /// Whether a request can choose its own latency with the [LATENCY_OVERRIDE_HEADER](crate::handle::LATENCY_OVERRIDE_HEADER)
+    pub allow_latency_override: bool,
+    /// Whether a request can force its own HTTP status or GraphQL error with the
+    /// [FORCE_STATUS_HEADER](crate::handle::graphql::FORCE_STATUS_HEADER) and
+    /// [FORCE_ERROR_HEADER](crate::handle::graphql::FORCE_ERROR_HEADER)
+    pub allow_error_override: bool,
+    /// Whether a request can seed the randomness of its response with the
+    /// [SEED_HEADER](crate::handle::graphql::SEED_HEADER), to reproduce a response exactly
+    pub allow_seed_override: bool,
+    /// Browser origins that may make requests to the mock. CORS headers are never sent when [None].
+    pub cors: Option<CorsConfig>,
+    /// How long after starting up the server responds to every request with a 503, as if it were still starting
+    pub startup_delay: Duration,
+    /// When set, every GraphQL request and its response are appended to a file
+    pub record: Option<RecordConfig>,
+    /// The name to send in the [SUBGRAPH_NAME_HEADER](crate::handle::SUBGRAPH_NAME_HEADER) for requests to `/`
+    pub instance_name: Option<String>,
+    /// Whether GraphQL responses name the subgraph that handled them in the
+    /// [SUBGRAPH_NAME_HEADER](crate::handle::SUBGRAPH_NAME_HEADER)
+    pub subgraph_name_header: bool,
+    /// How many GraphQL requests are handled at once, including their latency. Unlimited when [None].
+    pub max_concurrent_requests: Option<usize>,
+    /// Whether requests over [Config::max_concurrent_requests] are responded to with a 503, instead of waiting
+    /// for another request to finish
+    pub reject_excess_requests: bool,
+    /// Whether every random decision about a request is seeded from the request itself, so that identical requests
+    /// always get identical responses, even across restarts. Responses aren't cached in this mode.
+    pub deterministic: bool,
+    /// Whether the schema is served to routers as a schema source, at `GET /supergraph` and at an Uplink-style
+    /// `POST /uplink`
+    pub supergraph_endpoint: bool,
+    /// Extra latency for responses to operations that select a field, keyed by the field's `Type.field` coordinate
+    pub field_latency: HashMap<String, Duration>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct SubgraphOverrides {
     pub headers: HashMap<String, HeaderMap<HeaderValue>>,
     pub latency_generator: HashMap<String, LatencyGenerator>,
+    /// Only contains the subgraphs that have an error latency, either of their own or inherited from the base config
+    pub error_latency_generator: HashMap<String, LatencyGenerator>,
+    pub inject_latency_on_errors: HashMap<String, bool>,
+    /// Only contains the subgraphs that have an outage schedule, either of their own or inherited from the base
+    /// config
+    pub outage: HashMap<String, OutageSchedule>,
     pub response_generation: HashMap<String, ResponseGenerationConfig>,
     pub cache_responses: HashMap<String, bool>,
+    /// Only contains the subgraphs that are served with a schema of their own, instead of the supergraph schema
+    pub schemas: HashMap<String, Vec<PathBuf>>,
 }
 
 impl Default for Config {
@@ -108,16 +402,96 @@ impl Default for Config {
         Self {
             headers: Default::default(),
             latency_generator: LatencyGenerator::new(LatencyConfig::default()),
+            error_latency_generator: None,
+            inject_latency_on_errors: default_inject_latency_on_errors(),
+            outage: None,
             response_generation: Default::default(),
             cache_responses: default_cache_responses(),
             subgraph_overrides: Default::default(),
+            profiles: Default::default(),
+            warmup: Default::default(),
+            response_fixtures: None,
+            federation: Default::default(),
+            schema_watch: Default::default(),
+            allow_latency_override: false,
+            allow_error_override: false,
+            allow_seed_override: false,
+            cors: None,
+            startup_delay: Duration::ZERO,
+            record: None,
+            instance_name: None,
+            subgraph_name_header: default_subgraph_name_header(),
+            max_concurrent_requests: None,
+            reject_excess_requests: false,
+            deterministic: false,
+            supergraph_endpoint: false,
+            field_latency: Default::default(),
         }
     }
 }
 
 impl Config {
-    /// Parses a YAML file into a resolved port and [Config]
-    pub fn parse_yaml(mut base: Value) -> anyhow::Result<(u16, Config)> {
+    /// Substitutes every `${NAME}` in the text of a config file with the value of the environment variable `NAME`.
+    /// With a default, as in `${NAME:-default}`, the default is used when the variable is unset or empty, otherwise
+    /// an unset variable is an error. `$${` is left in the config as a literal `${`. Comment lines are left as they
+    /// are, so that they can document variables without having to set them.
+    pub fn interpolate_env(source: &str) -> Result<String, SubgraphMockError> {
+        let var = |name: &str| env::var(name).ok();
+        source
+            .split_inclusive('\n')
+            .map(|line| {
+                if line.trim_start().starts_with('#') {
+                    Ok(line.to_string())
+                } else {
+                    Self::interpolate(line, var)
+                }
+            })
+            .collect::<anyhow::Result<String>>()
+            .map_err(SubgraphMockError::Config)
+    }
+
+    fn interpolate(source: &str, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+        let mut interpolated = String::with_capacity(source.len());
+        let mut rest = source;
+        while let Some(start) = rest.find("${") {
+            if let Some(escaped) = rest[..start].strip_suffix('$') {
+                interpolated.push_str(escaped);
+                interpolated.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+            interpolated.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+
+            let end = rest
+                .find('}')
+                .ok_or_else(|| Error::msg("unterminated `${` in config file"))?;
+            let reference = &rest[..end];
+            let value = match reference.split_once(":-") {
+                Some((name, default)) => var(name)
+                    .filter(|value| !value.is_empty())
+                    .unwrap_or_else(|| default.to_string()),
+                None => var(reference).ok_or_else(|| {
+                    Error::msg(format!(
+                        "environment variable {reference} is not set, and `${{{reference}}}` in the config file \
+                         has no default"
+                    ))
+                })?,
+            };
+            interpolated.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        interpolated.push_str(rest);
+
+        Ok(interpolated)
+    }
+
+    /// Parses a YAML file into a resolved [ServerConfig] and [Config]
+    pub fn parse_yaml(base: Value) -> Result<(ServerConfig, Config), SubgraphMockError> {
+        Self::resolve_yaml(base).map_err(SubgraphMockError::Config)
+    }
+
+    fn resolve_yaml(mut base: Value) -> anyhow::Result<(ServerConfig, Config)> {
         let mapping = base
             .as_mapping_mut()
             .ok_or_else(|| Error::msg("config file must be a mapping"))?;
@@ -125,38 +499,70 @@ impl Config {
         let mut subgraph_cache_responses = HashMap::new();
         let mut subgraph_headers = HashMap::new();
         let mut subgraph_latency_generators = HashMap::new();
+        let mut subgraph_error_latency_generators = HashMap::new();
+        let mut subgraph_inject_latency_on_errors = HashMap::new();
+        let mut subgraph_outages = HashMap::new();
         let mut subgraph_response_generation_configs = HashMap::new();
+        let mut subgraph_ports = BTreeMap::new();
+        let mut subgraph_schemas = HashMap::new();
+        let profile_overrides = mapping.remove(PROFILES_KEY);
 
         if let Some(overrides) = mapping.remove(SUBGRAPH_OVERRIDES_KEY) {
             match overrides {
                 Value::Mapping(mapping) => {
-                    for (subgraph_name, subgraph_override) in mapping {
+                    for (subgraph_name, mut subgraph_override) in mapping {
                         let mut subgraph_config = base.clone();
 
                         let override_mapping = subgraph_override
-                            .as_mapping()
+                            .as_mapping_mut()
                             .ok_or_else(|| Error::msg("subgraph override must be a mapping"))?;
+                        let schema_paths = match override_mapping.remove(SUBGRAPH_SCHEMA_KEY) {
+                            Some(Value::String(path)) => Some(vec![PathBuf::from(path)]),
+                            Some(paths) => Some(serde_yaml::from_value::<Vec<PathBuf>>(paths)?),
+                            None => None,
+                        };
 
-                        if override_mapping.contains_key("port") {
-                            warn!("port overrides for subgraphs will be ignored")
+                        let has_port = override_mapping.contains_key("port");
+                        for key in GLOBAL_KEYS {
+                            if override_mapping.contains_key(key) {
+                                warn!("{key} overrides for subgraphs will be ignored")
+                            }
                         }
 
                         merge_yaml(subgraph_override, &mut subgraph_config);
                         let parsed_config: BaseConfig = serde_yaml::from_value(subgraph_config)?;
                         let subgraph_name: String = serde_yaml::from_value(subgraph_name)?;
+                        if has_port {
+                            subgraph_ports.insert(subgraph_name.clone(), parsed_config.port);
+                        }
+                        if let Some(schema_paths) = schema_paths {
+                            subgraph_schemas.insert(subgraph_name.clone(), schema_paths);
+                        }
 
                         info!("generating customized config for {}", subgraph_name);
-                        let (
-                            _port,
+                        let ConfigParts {
+                            server: _,
                             cache_responses,
                             latency_generator,
+                            error_latency_generator,
+                            inject_latency_on_errors,
+                            outage,
                             headers,
                             response_generation,
-                        ) = parsed_config.into_parts()?;
+                        } = parsed_config.into_parts()?;
 
                         subgraph_cache_responses.insert(subgraph_name.clone(), cache_responses);
                         subgraph_latency_generators
                             .insert(subgraph_name.clone(), latency_generator);
+                        if let Some(error_latency_generator) = error_latency_generator {
+                            subgraph_error_latency_generators
+                                .insert(subgraph_name.clone(), error_latency_generator);
+                        }
+                        subgraph_inject_latency_on_errors
+                            .insert(subgraph_name.clone(), inject_latency_on_errors);
+                        if let Some(outage) = outage {
+                            subgraph_outages.insert(subgraph_name.clone(), outage);
+                        }
                         subgraph_headers.insert(subgraph_name.clone(), headers);
                         subgraph_response_generation_configs
                             .insert(subgraph_name, response_generation);
@@ -166,22 +572,107 @@ impl Config {
             }
         }
 
-        let (port, cache_responses, latency, headers, response_generation) =
-            serde_yaml::from_value::<BaseConfig>(base)?.into_parts()?;
+        let mut profiles = HashMap::new();
+        if let Some(profile_overrides) = profile_overrides {
+            let Value::Mapping(profile_overrides) = profile_overrides else {
+                return Err(Error::msg("profiles must be a mapping"));
+            };
+            let base_response_generation = base
+                .get("response_generation")
+                .cloned()
+                .unwrap_or_else(|| Value::Mapping(Default::default()));
+            for (profile_name, profile_override) in profile_overrides {
+                let profile_name: String = serde_yaml::from_value(profile_name)?;
+                let mut profile_config = base_response_generation.clone();
+                merge_yaml(profile_override, &mut profile_config);
+                let mut response_generation: ResponseGenerationConfig =
+                    serde_yaml::from_value(profile_config).map_err(|err| {
+                        Error::from(err).context(format!("invalid profile {profile_name}"))
+                    })?;
+                response_generation.merge_default_scalars();
+                response_generation
+                    .validate()
+                    .map_err(|err| err.context(format!("invalid profile {profile_name}")))?;
+                info!("generating customized config for profile {}", profile_name);
+                profiles.insert(profile_name, response_generation);
+            }
+        }
+
+        let mut base_config: BaseConfig = serde_yaml::from_value(base)?;
+        let warmup = mem::take(&mut base_config.warmup);
+        let response_fixtures = base_config.response_fixtures.take();
+        let federation = mem::take(&mut base_config.federation);
+        let schema_watch = base_config.schema_watch;
+        let allow_latency_override = base_config.allow_latency_override;
+        let allow_error_override = base_config.allow_error_override;
+        let allow_seed_override = base_config.allow_seed_override;
+        let cors = base_config.cors.take();
+        let startup_delay = base_config.startup_delay;
+        let record = base_config.record.take();
+        let instance_name = base_config.instance_name.take();
+        let subgraph_name_header = base_config.subgraph_name_header;
+        let max_concurrent_requests = base_config.max_concurrent_requests;
+        let reject_excess_requests = base_config.reject_excess_requests;
+        let deterministic = base_config.deterministic;
+        let supergraph_endpoint = base_config.supergraph_endpoint;
+        let field_latency = mem::take(&mut base_config.field_latency);
+        if max_concurrent_requests == Some(0) {
+            return Err(Error::msg("max_concurrent_requests must be at least 1"));
+        }
+        if let Some(cors) = &cors {
+            cors.validate()?;
+        }
+        base_config.http.validate()?;
+        let ConfigParts {
+            mut server,
+            cache_responses,
+            latency_generator,
+            error_latency_generator,
+            inject_latency_on_errors,
+            outage,
+            headers,
+            response_generation,
+        } = base_config.into_parts()?;
+        server.subgraph_ports = subgraph_ports;
 
         Ok((
-            port,
+            server,
             Config {
                 headers,
-                latency_generator: latency,
+                latency_generator,
+                error_latency_generator,
+                inject_latency_on_errors,
+                outage,
                 response_generation,
                 cache_responses,
                 subgraph_overrides: SubgraphOverrides {
                     headers: subgraph_headers,
                     latency_generator: subgraph_latency_generators,
+                    error_latency_generator: subgraph_error_latency_generators,
+                    inject_latency_on_errors: subgraph_inject_latency_on_errors,
+                    outage: subgraph_outages,
                     response_generation: subgraph_response_generation_configs,
                     cache_responses: subgraph_cache_responses,
+                    schemas: subgraph_schemas,
                 },
+                profiles,
+                warmup,
+                response_fixtures,
+                federation,
+                schema_watch,
+                allow_latency_override,
+                allow_error_override,
+                allow_seed_override,
+                cors,
+                startup_delay,
+                record,
+                instance_name,
+                subgraph_name_header,
+                max_concurrent_requests,
+                reject_excess_requests,
+                deterministic,
+                supergraph_endpoint,
+                field_latency,
             },
         ))
     }
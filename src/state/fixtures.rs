@@ -0,0 +1,31 @@
+//! Canned responses loaded from a directory of fixture files
+use anyhow::Context;
+use serde_json_bytes::{Value, serde_json};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Responses keyed by the name of the operation that they are returned for
+pub type ResponseFixtures = BTreeMap<String, Value>;
+
+/// Load every `.json` file in `dir` as the response to the operation that the file is named after. Files with any
+/// other extension are ignored.
+pub fn load_fixtures(dir: &Path) -> anyhow::Result<ResponseFixtures> {
+    let mut fixtures = ResponseFixtures::new();
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("unable to read response fixtures from {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let Some(op_name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let response = serde_json::from_slice(&fs::read(&path)?)
+            .with_context(|| format!("invalid response fixture {}", path.display()))?;
+        fixtures.insert(op_name.to_string(), response);
+    }
+
+    Ok(fixtures)
+}
@@ -1,60 +1,637 @@
+use crate::{SubgraphMockError, handle::graphql, record::Recorder};
+use anyhow::Context;
 use notify::{Config as NotifyConfig, Event, EventKind, PollWatcher, RecursiveMode, Watcher};
-use std::{path::PathBuf, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
-use tracing::error;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{RwLock, Semaphore, SemaphorePermit, TryAcquireError, watch},
+    time::{Instant, sleep},
+};
+use tracing::{error, info, warn};
 
 mod config;
+mod fixtures;
 mod schema;
 
-pub use config::Config;
-pub use config::default_port;
-pub use schema::FederatedSchema;
+pub use config::{Config, HttpConfig, ServerConfig, default_port};
+pub use fixtures::{ResponseFixtures, load_fixtures};
+pub use schema::{FederatedSchema, FederationConfig, FederationType};
 
 use schema::update_schema;
 
 pub struct State {
     pub config: Arc<RwLock<Config>>,
     pub schema: Arc<RwLock<FederatedSchema>>,
+    /// The schemas of the subgraphs that are served with a schema of their own, see [State::schema_for]
+    pub subgraph_schemas: HashMap<String, Arc<RwLock<FederatedSchema>>>,
+    /// The outcome of the most recent attempt to reload the schema
+    pub schema_status: Arc<RwLock<SchemaStatus>>,
+    /// Canned responses loaded from [Config::response_fixtures], which are reloaded along with the schema
+    pub response_fixtures: Arc<RwLock<ResponseFixtures>>,
+    /// Appends every GraphQL request and its response to the file in [Config::record]
+    pub recorder: Option<Recorder>,
+    /// When the server started, which is when the [Config::startup_delay] starts counting down
+    pub started: Instant,
+    /// Set once the server starts shutting down, which ends any requests that are hanging
+    shutting_down: watch::Sender<bool>,
+    /// Permits for the requests being handled at once, when [Config::max_concurrent_requests] is set
+    request_limit: Option<Semaphore>,
     /// Handle to the pollwatcher that updates the schema for this config, so that it only drops out of scope when this state does
-    _schema_watcher: PollWatcher,
+    _schema_watcher: Option<PollWatcher>,
+    /// Handles to the pollwatchers that update the [State::subgraph_schemas]
+    _subgraph_schema_watchers: Vec<PollWatcher>,
+    /// Handle to the pollwatcher that reloads the response fixtures
+    _fixture_watcher: Option<PollWatcher>,
 }
 
 impl State {
-    pub fn new(config: Config, schema_path: PathBuf) -> anyhow::Result<Self> {
-        let schema = FederatedSchema::parse(&schema_path)?;
+    pub fn new(config: Config, schema_paths: Vec<PathBuf>) -> anyhow::Result<Self> {
+        let schema = FederatedSchema::parse_with_federation(&schema_paths, &config.federation)?;
+        let response_fixtures = match &config.response_fixtures {
+            Some(dir) => load_fixtures(dir)?,
+            None => ResponseFixtures::new(),
+        };
+        warn_about_config_mismatches(&config, &response_fixtures, &schema);
         let schema = Arc::new(RwLock::new(schema));
+        let mut subgraph_schemas = HashMap::new();
+        for (subgraph_name, paths) in &config.subgraph_overrides.schemas {
+            let schema = FederatedSchema::parse_with_federation(paths, &config.federation)
+                .with_context(|| {
+                    format!("unable to load the schema of subgraph {subgraph_name}")
+                })?;
+            warn_about_subgraph_config_mismatches(&config, subgraph_name, &schema);
+            subgraph_schemas.insert(subgraph_name.clone(), Arc::new(RwLock::new(schema)));
+        }
+        let subgraph_schema_paths = config.subgraph_overrides.schemas.clone();
+        let response_fixtures = Arc::new(RwLock::new(response_fixtures));
+        let (schema_watch, federation) = (config.schema_watch, config.federation.clone());
+        let fixtures_dir = config.response_fixtures.clone();
+        let recorder = config.record.as_ref().map(Recorder::new).transpose()?;
+        let request_limit = config.max_concurrent_requests.map(Semaphore::new);
+        let config = Arc::new(RwLock::new(config));
 
-        let lock = schema.clone();
-        // We have to use a PollWatcher because Docker on MacOS doesn't support filesystem events:
-        // https://docs.rs/notify/8.2.0/notify/index.html#docker-with-linux-on-macos-m1
-        let mut schema_watcher = PollWatcher::new(
-            move |res: Result<Event, _>| match res {
-                Ok(event) => {
-                    if let EventKind::Modify(_) = event.kind
-                        && let Some(path) = event.paths.first()
-                        && let Err(err) = update_schema(path, lock.clone())
-                    {
-                        error!("Failed to reload schema: {}", err);
-                    }
-                }
-                Err(errors) => {
-                    error!("Error watching schema file: {:?}", errors)
-                }
-            },
-            NotifyConfig::default()
-                .with_poll_interval(Duration::from_secs(1))
-                .with_compare_contents(true),
-        )?;
-        schema_watcher.watch(&schema_path, RecursiveMode::NonRecursive)?;
+        let schema_status = Arc::new(RwLock::new(SchemaStatus::default()));
+
+        let schema_watcher = if schema_watch.enabled {
+            Some(watch_schema(
+                &schema_paths,
+                federation.clone(),
+                schema_watch.debounce,
+                config.clone(),
+                schema.clone(),
+                response_fixtures.clone(),
+                schema_status.clone(),
+            )?)
+        } else {
+            info!("schema watching is disabled, the schema will not be reloaded");
+            None
+        };
+        let mut subgraph_schema_watchers = Vec::new();
+        if schema_watch.enabled {
+            for (subgraph_name, paths) in subgraph_schema_paths {
+                let subgraph_schema = subgraph_schemas[&subgraph_name].clone();
+                subgraph_schema_watchers.push(watch_subgraph_schema(
+                    &paths,
+                    subgraph_name,
+                    federation.clone(),
+                    schema_watch.debounce,
+                    config.clone(),
+                    subgraph_schema,
+                    schema_status.clone(),
+                )?);
+            }
+        }
+        let fixture_watcher = match fixtures_dir {
+            Some(dir) if schema_watch.enabled => Some(watch_fixtures(
+                dir,
+                schema_watch.debounce,
+                config.clone(),
+                schema.clone(),
+                response_fixtures.clone(),
+            )?),
+            _ => None,
+        };
 
         Ok(Self {
-            config: Arc::new(RwLock::new(config)),
+            config,
             schema,
+            subgraph_schemas,
+            schema_status,
+            response_fixtures,
+            recorder,
+            started: Instant::now(),
+            shutting_down: watch::Sender::new(false),
+            request_limit,
             _schema_watcher: schema_watcher,
+            _subgraph_schema_watchers: subgraph_schema_watchers,
+            _fixture_watcher: fixture_watcher,
         })
     }
 
-    pub fn default(schema_path: PathBuf) -> anyhow::Result<Self> {
-        Self::new(Config::default(), schema_path)
+    /// Create a state from an already parsed schema. The schema will never be reloaded since there are no files
+    /// to watch. Response fixtures and subgraph schemas are loaded once, and the state fails to be created if any of
+    /// them can't be loaded, or if the recording file can't be opened.
+    pub fn from_schema(config: Config, schema: FederatedSchema) -> Result<Self, SubgraphMockError> {
+        let response_fixtures = match config.response_fixtures.as_deref() {
+            Some(dir) => load_fixtures(dir).map_err(SubgraphMockError::Config)?,
+            None => ResponseFixtures::new(),
+        };
+        warn_about_config_mismatches(&config, &response_fixtures, &schema);
+        let recorder = config
+            .record
+            .as_ref()
+            .map(Recorder::new)
+            .transpose()
+            .map_err(SubgraphMockError::Config)?;
+        let request_limit = config.max_concurrent_requests.map(Semaphore::new);
+        let mut subgraph_schemas = HashMap::new();
+        for (subgraph_name, paths) in &config.subgraph_overrides.schemas {
+            let schema = FederatedSchema::parse_with_federation(paths, &config.federation)?;
+            warn_about_subgraph_config_mismatches(&config, subgraph_name, &schema);
+            subgraph_schemas.insert(subgraph_name.clone(), Arc::new(RwLock::new(schema)));
+        }
+        Ok(Self {
+            config: Arc::new(RwLock::new(config)),
+            schema: Arc::new(RwLock::new(schema)),
+            subgraph_schemas,
+            schema_status: Default::default(),
+            response_fixtures: Arc::new(RwLock::new(response_fixtures)),
+            recorder,
+            started: Instant::now(),
+            shutting_down: watch::Sender::new(false),
+            request_limit,
+            _schema_watcher: None,
+            _subgraph_schema_watchers: Vec::new(),
+            _fixture_watcher: None,
+        })
+    }
+
+    pub fn default(schema_paths: Vec<PathBuf>) -> anyhow::Result<Self> {
+        Self::new(Config::default(), schema_paths)
+    }
+
+    /// The schema that requests for `subgraph_name` are validated against and generated from, which is the subgraph's
+    /// own schema if it has one, or the supergraph schema otherwise
+    pub fn schema_for(&self, subgraph_name: Option<&str>) -> &Arc<RwLock<FederatedSchema>> {
+        subgraph_name
+            .and_then(|name| self.subgraph_schemas.get(name))
+            .unwrap_or(&self.schema)
+    }
+
+    /// Ends every request that is hanging, and any that start hanging from now on, so that they don't hold up a
+    /// graceful shutdown
+    pub fn shut_down(&self) {
+        self.shutting_down.send_replace(true);
+    }
+
+    /// Takes one of the [Config::max_concurrent_requests] permits, which is held until the permit is dropped. When
+    /// they're all taken, this waits for one to be released, or fails immediately if `reject_excess` is set. There's
+    /// nothing to take without a limit.
+    pub async fn request_permit(
+        &self,
+        reject_excess: bool,
+    ) -> Result<Option<SemaphorePermit<'_>>, TryAcquireError> {
+        let Some(request_limit) = &self.request_limit else {
+            return Ok(None);
+        };
+        let permit = if reject_excess {
+            request_limit.try_acquire()?
+        } else {
+            // The semaphore is never closed, so waiting can't fail
+            request_limit
+                .acquire()
+                .await
+                .map_err(|_| TryAcquireError::Closed)?
+        };
+        Ok(Some(permit))
+    }
+
+    /// Waits for `duration`, or indefinitely if there isn't one. Returns false if the wait was cut short by
+    /// [State::shut_down].
+    pub async fn hang(&self, duration: Option<Duration>) -> bool {
+        let mut shutting_down = self.shutting_down.subscribe();
+        let shutdown = shutting_down.wait_for(|shutting_down| *shutting_down);
+        match duration {
+            Some(duration) => tokio::select! {
+                _ = sleep(duration) => true,
+                _ = shutdown => false,
+            },
+            None => {
+                // The sender lives as long as this state, so waiting can't fail
+                let _ = shutdown.await;
+                false
+            }
+        }
+    }
+}
+
+/// Scalar generators for types that aren't scalars are never used, and response overrides or fixtures with fields that
+/// aren't in the schema have drifted from it. Both are most likely due to a mistake in the config.
+fn warn_about_config_mismatches(
+    config: &Config,
+    response_fixtures: &ResponseFixtures,
+    schema: &FederatedSchema,
+) {
+    for mismatch in graphql::response_mismatches(response_fixtures, schema) {
+        warn!("response fixture does not match the schema: {mismatch}");
+    }
+
+    let base_mismatches = config.response_generation.scalar_mismatches(schema);
+    for mismatch in &base_mismatches {
+        warn!("scalar generator will never be used: {mismatch}");
+    }
+    let base_override_mismatches = config
+        .response_generation
+        .response_override_mismatches(schema);
+    for mismatch in &base_override_mismatches {
+        warn!("response override does not match the schema: {mismatch}");
+    }
+
+    // Subgraph overrides inherit the base scalars, so only report the generators that they add themselves. Subgraphs
+    // with a schema of their own are checked against it instead, see [warn_about_subgraph_config_mismatches].
+    for (subgraph_name, rgen_cfg) in &config.subgraph_overrides.response_generation {
+        if config
+            .subgraph_overrides
+            .schemas
+            .contains_key(subgraph_name)
+        {
+            continue;
+        }
+        for mismatch in rgen_cfg.scalar_mismatches(schema) {
+            if !base_mismatches.contains(&mismatch) {
+                warn!(%subgraph_name, "scalar generator will never be used: {mismatch}");
+            }
+        }
+        for mismatch in rgen_cfg.response_override_mismatches(schema) {
+            if !base_override_mismatches.contains(&mismatch) {
+                warn!(%subgraph_name, "response override does not match the schema: {mismatch}");
+            }
+        }
+    }
+    // As do profiles
+    for (profile, rgen_cfg) in &config.profiles {
+        for mismatch in rgen_cfg.scalar_mismatches(schema) {
+            if !base_mismatches.contains(&mismatch) {
+                warn!(%profile, "scalar generator will never be used: {mismatch}");
+            }
+        }
+        for mismatch in rgen_cfg.response_override_mismatches(schema) {
+            if !base_override_mismatches.contains(&mismatch) {
+                warn!(%profile, "response override does not match the schema: {mismatch}");
+            }
+        }
+    }
+}
+
+/// Like [warn_about_config_mismatches], for a subgraph that is served with a schema of its own
+fn warn_about_subgraph_config_mismatches(
+    config: &Config,
+    subgraph_name: &str,
+    schema: &FederatedSchema,
+) {
+    let (scalar_mismatches, override_mismatches) =
+        subgraph_config_mismatches(config, subgraph_name, schema);
+    for mismatch in scalar_mismatches {
+        warn!(%subgraph_name, "scalar generator will never be used: {mismatch}");
+    }
+    for mismatch in override_mismatches {
+        warn!(%subgraph_name, "response override does not match the schema: {mismatch}");
+    }
+}
+
+/// The scalar generator and response override mismatches between `schema` and the response generation config that
+/// `subgraph_name` is served with. Everything that the subgraph inherits from the base config is checked too, since
+/// the base config was only checked against the supergraph schema.
+fn subgraph_config_mismatches(
+    config: &Config,
+    subgraph_name: &str,
+    schema: &FederatedSchema,
+) -> (Vec<String>, Vec<String>) {
+    let rgen_cfg = config
+        .subgraph_overrides
+        .response_generation
+        .get(subgraph_name)
+        .unwrap_or(&config.response_generation);
+    (
+        rgen_cfg.scalar_mismatches(schema),
+        rgen_cfg.response_override_mismatches(schema),
+    )
+}
+
+/// Watch `schema_paths` for modifications, reloading `schema` and recording the outcome in `schema_status` when they
+/// happen. Reloaded schemas are checked against `config` for mismatches again. The returned watcher stops watching
+/// when it is dropped.
+fn watch_schema(
+    schema_paths: &[PathBuf],
+    federation: FederationConfig,
+    debounce_window: Duration,
+    config: Arc<RwLock<Config>>,
+    schema: Arc<RwLock<FederatedSchema>>,
+    response_fixtures: Arc<RwLock<ResponseFixtures>>,
+    schema_status: Arc<RwLock<SchemaStatus>>,
+) -> anyhow::Result<PollWatcher> {
+    let paths = schema_paths.to_vec();
+    watch_paths(schema_paths, "schema-reload", debounce_window, move || {
+        // Any change requires a reparse of every file since they are merged into one schema
+        let result = update_schema(&paths, &federation, schema.clone());
+        match &result {
+            // Nothing cached for the previous schema can be served anymore, so it would only take up memory
+            Ok(()) => {
+                _ = graphql::flush_caches_blocking();
+                warn_about_config_mismatches(
+                    &config.blocking_read(),
+                    &response_fixtures.blocking_read(),
+                    &schema.blocking_read(),
+                );
+            }
+            Err(err) => error!("Failed to reload schema: {}", err),
+        }
+        schema_status.blocking_write().last_reload = Some(SchemaReload::new(result));
+    })
+}
+
+/// Like [watch_schema], but for the schema of `subgraph_name`, whose reloads are recorded under its name in
+/// `schema_status`
+fn watch_subgraph_schema(
+    schema_paths: &[PathBuf],
+    subgraph_name: String,
+    federation: FederationConfig,
+    debounce_window: Duration,
+    config: Arc<RwLock<Config>>,
+    schema: Arc<RwLock<FederatedSchema>>,
+    schema_status: Arc<RwLock<SchemaStatus>>,
+) -> anyhow::Result<PollWatcher> {
+    let paths = schema_paths.to_vec();
+    watch_paths(schema_paths, "schema-reload", debounce_window, move || {
+        let result = update_schema(&paths, &federation, schema.clone());
+        match &result {
+            Ok(()) => {
+                _ = graphql::flush_caches_blocking();
+                warn_about_subgraph_config_mismatches(
+                    &config.blocking_read(),
+                    &subgraph_name,
+                    &schema.blocking_read(),
+                );
+            }
+            Err(err) => error!(%subgraph_name, "Failed to reload subgraph schema: {}", err),
+        }
+        schema_status
+            .blocking_write()
+            .subgraphs
+            .entry(subgraph_name.clone())
+            .or_default()
+            .last_reload = Some(SchemaReload::new(result));
+    })
+}
+
+/// Watch the `dir` of response fixtures for changes, reloading `response_fixtures` when they happen. If any fixture
+/// fails to load, the previously loaded fixtures keep being served.
+fn watch_fixtures(
+    dir: PathBuf,
+    debounce_window: Duration,
+    config: Arc<RwLock<Config>>,
+    schema: Arc<RwLock<FederatedSchema>>,
+    response_fixtures: Arc<RwLock<ResponseFixtures>>,
+) -> anyhow::Result<PollWatcher> {
+    let watched = dir.clone();
+    watch_paths(
+        std::slice::from_ref(&watched),
+        "fixture-reload",
+        debounce_window,
+        move || match load_fixtures(&dir) {
+            Ok(fixtures) => {
+                info!(dir=%dir.display(), n_fixtures = fixtures.len(), "response fixtures loaded");
+                warn_about_config_mismatches(
+                    &config.blocking_read(),
+                    &fixtures,
+                    &schema.blocking_read(),
+                );
+                *response_fixtures.blocking_write() = fixtures;
+            }
+            Err(err) => error!("Failed to reload response fixtures: {:#}", err),
+        },
+    )
+}
+
+/// Calls `on_change` on a thread named `thread_name` whenever any of `paths` are created, modified or removed. The
+/// returned watcher stops watching when it is dropped.
+fn watch_paths(
+    paths: &[PathBuf],
+    thread_name: &str,
+    debounce_window: Duration,
+    on_change: impl FnMut() + Send + 'static,
+) -> anyhow::Result<PollWatcher> {
+    // Reloads happen on a thread of their own so that bursts of modifications can be coalesced. The thread
+    // stops once the watcher, and with it the sending half of the channel, is dropped.
+    let (reload_tx, reload_rx) = mpsc::channel();
+    thread::Builder::new()
+        .name(thread_name.to_string())
+        .spawn(move || debounce(reload_rx, debounce_window, on_change))?;
+
+    // We have to use a PollWatcher because Docker on MacOS doesn't support filesystem events:
+    // https://docs.rs/notify/8.2.0/notify/index.html#docker-with-linux-on-macos-m1
+    let mut watcher = PollWatcher::new(
+        move |res: Result<Event, _>| match res {
+            Ok(event) => {
+                if let EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) =
+                    event.kind
+                {
+                    // The reload thread only stops once this watcher has been dropped
+                    let _ = reload_tx.send(());
+                }
+            }
+            Err(errors) => {
+                error!("Error watching files: {:?}", errors)
+            }
+        },
+        NotifyConfig::default()
+            .with_poll_interval(Duration::from_secs(1))
+            .with_compare_contents(true),
+    )?;
+    for path in paths {
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+    }
+
+    Ok(watcher)
+}
+
+/// How the schema files, and the response fixtures if there are any, are watched for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaWatchConfig {
+    /// When disabled, the schema is only parsed once at startup and no watcher is started
+    #[serde(default = "default_watch_enabled")]
+    pub enabled: bool,
+    /// How long the schema files have to go without being modified before they are reloaded, so that editors
+    /// writing a file in several steps only cause a single reload
+    #[serde(
+        default = "default_debounce",
+        deserialize_with = "humantime_serde::deserialize"
+    )]
+    pub debounce: Duration,
+}
+
+fn default_watch_enabled() -> bool {
+    true
+}
+
+fn default_debounce() -> Duration {
+    Duration::from_millis(250)
+}
+
+impl Default for SchemaWatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_watch_enabled(),
+            debounce: default_debounce(),
+        }
+    }
+}
+
+/// Calls `f` once for every burst of `events`, after `window` has passed without any further events
+fn debounce(events: Receiver<()>, window: Duration, mut f: impl FnMut()) {
+    while events.recv().is_ok() {
+        loop {
+            match events.recv_timeout(window) {
+                Ok(()) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        f();
+    }
+}
+
+/// Whether the schema being served is up to date with the files it was loaded from
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchemaStatus {
+    /// [None] until the schema files change for the first time
+    pub last_reload: Option<SchemaReload>,
+    /// The status of the schemas of subgraphs that have their own, once their files have changed
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub subgraphs: BTreeMap<String, SchemaStatus>,
+}
+
+/// The outcome of reloading the schema. The last successfully loaded schema keeps being served after a failure.
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaReload {
+    pub success: bool,
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub timestamp: SystemTime,
+    pub error: Option<String>,
+}
+
+impl SchemaReload {
+    fn new(result: anyhow::Result<()>) -> Self {
+        Self {
+            success: result.is_ok(),
+            timestamp: SystemTime::now(),
+            error: result.err().map(|err| err.to_string()),
+        }
+    }
+}
+
+fn serialize_rfc3339<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subgraph_config_is_checked_against_its_schema() -> anyhow::Result<()> {
+        let (_, config) = Config::parse_yaml(serde_yaml::from_str(
+            r#"
+            subgraph_overrides:
+              inventory:
+                schema: tests/data/inventory.graphql
+                response_generation:
+                  scalars:
+                    Item:
+                      type: uuid
+                  response_overrides:
+                    GetItems:
+                      data:
+                        items:
+                          - id: "1"
+                    GetPosts:
+                      data:
+                        posts:
+                          - id: "1"
+            "#,
+        )?)?;
+        let inventory = include_str!("../../tests/data/inventory.graphql");
+        let schema = FederatedSchema::parse_string(inventory, "inventory.graphql")?;
+
+        // Items are only in the inventory schema, and posts are only in the supergraph
+        let (scalar_mismatches, override_mismatches) =
+            subgraph_config_mismatches(&config, "inventory", &schema);
+        assert_eq!(scalar_mismatches.len(), 1, "{scalar_mismatches:?}");
+        assert!(scalar_mismatches[0].contains("`Item` is an object type"));
+        assert_eq!(override_mismatches.len(), 1, "{override_mismatches:?}");
+        assert!(override_mismatches[0].contains("posts"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn states_fail_for_files_that_cant_be_loaded() -> anyhow::Result<()> {
+        let supergraph = include_str!("../../tests/data/schema.graphql");
+        let schema = || FederatedSchema::parse_string(supergraph, "schema.graphql");
+        let config = |yaml: &str| -> anyhow::Result<Config> {
+            Ok(Config::parse_yaml(serde_yaml::from_str(yaml)?)?.1)
+        };
+
+        assert!(State::from_schema(config("{}")?, schema()?).is_ok());
+        assert!(matches!(
+            State::from_schema(config("response_fixtures: tests/data/missing")?, schema()?),
+            Err(SubgraphMockError::Config(_))
+        ));
+        assert!(matches!(
+            State::from_schema(
+                config(
+                    "subgraph_overrides: { inventory: { schema: tests/data/missing.graphql } }"
+                )?,
+                schema()?
+            ),
+            Err(SubgraphMockError::Io { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn debounce_coalesces_bursts() {
+        let (tx, rx) = mpsc::channel();
+        let (calls_tx, calls_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            debounce(rx, Duration::from_millis(100), || {
+                calls_tx.send(()).unwrap()
+            })
+        });
+
+        for _ in 0..5 {
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        calls_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(calls_rx.recv_timeout(Duration::from_millis(300)).is_err());
+
+        // A later burst is acted upon separately
+        tx.send(()).unwrap();
+        calls_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        drop(tx);
+        handle.join().unwrap();
+        assert!(calls_rx.try_recv().is_err());
     }
 }
@@ -0,0 +1,225 @@
+use apollo_compiler::{
+    Name, Node,
+    ast::{
+        Definition, Directive, DirectiveDefinition, DirectiveList, DirectiveLocation, Document,
+        FieldDefinition, InputValueDefinition, Type, Value,
+    },
+    collections::{IndexMap, IndexSet},
+    name,
+};
+use tracing::{debug, warn};
+
+/// The directive names that have been brought into scope by the `@link` directives on a schema.
+#[derive(Debug, Default)]
+struct LinkedNames {
+    /// Directives imported by name, after applying any `as` renames
+    imports: IndexSet<String>,
+    /// Namespace prefixes that can be used to reference any element of a linked spec, e.g. `federation__`
+    prefixes: Vec<String>,
+}
+
+impl LinkedNames {
+    fn contains(&self, directive_name: &str) -> bool {
+        self.imports.contains(directive_name)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| directive_name.starts_with(prefix.as_str()))
+    }
+}
+
+/// `@link` directives describe external imports of other GraphQL schema files. We cannot fetch the linked specs, so
+/// instead we inject stub definitions for every linked directive that the schema uses but doesn't define. The stubs
+/// accept any argument that is used with them (typed as `_Any`) and are valid in any type system location, which is
+/// enough for the schema to validate.
+///
+/// Both the `import: ["@foo", { name: "@bar", as: "@baz" }]` form and namespaced references using the `as:` prefix
+/// (or the spec name from the url if there is no `as:` argument) are supported.
+pub fn inject_linked_directive_stubs(ast: &mut Document) {
+    let mut linked = LinkedNames::default();
+    for def in &ast.definitions {
+        let directives = match def {
+            Definition::SchemaDefinition(schema_def) => &schema_def.directives,
+            Definition::SchemaExtension(schema_ext) => &schema_ext.directives,
+            _ => continue,
+        };
+
+        for directive in directives.get_all("link") {
+            collect_link(directive, &mut linked);
+        }
+    }
+
+    let defined: IndexSet<&Name> = ast
+        .definitions
+        .iter()
+        .filter_map(|def| match def {
+            Definition::DirectiveDefinition(directive_def) => Some(&directive_def.name),
+            _ => None,
+        })
+        .collect();
+
+    // The arguments used with each undefined linked directive, so that the stub can accept all of them
+    let mut stubs: IndexMap<Name, IndexSet<Name>> = IndexMap::default();
+    for directive in ast.definitions.iter().flat_map(directive_usages) {
+        if defined.contains(&directive.name) || !linked.contains(directive.name.as_str()) {
+            continue;
+        }
+
+        stubs
+            .entry(directive.name.clone())
+            .or_default()
+            .extend(directive.arguments.iter().map(|arg| arg.name.clone()));
+    }
+
+    for (name, arguments) in stubs {
+        debug!(directive=%name, "injecting stub definition for linked directive");
+        ast.definitions
+            .push(stub_definition(name, arguments.into_iter()));
+    }
+}
+
+fn collect_link(directive: &Directive, linked: &mut LinkedNames) {
+    let Some(url) = directive
+        .specified_argument_by_name("url")
+        .and_then(|url| url.as_str())
+    else {
+        warn!("@link directive is missing a url and will be ignored");
+        return;
+    };
+
+    let namespace = directive
+        .specified_argument_by_name("as")
+        .and_then(|alias| alias.as_str())
+        .or_else(|| spec_name(url));
+    if let Some(namespace) = namespace {
+        linked.prefixes.push(format!("{namespace}__"));
+    }
+
+    let imports = directive
+        .specified_argument_by_name("import")
+        .and_then(|imports| imports.as_list())
+        .unwrap_or_default();
+
+    for import in imports {
+        let imported = match import.as_ref() {
+            Value::String(name) => Some(name.as_str()),
+            Value::Object(fields) => {
+                let field = |field_name: &str| {
+                    fields
+                        .iter()
+                        .find(|(name, _)| name == field_name)
+                        .and_then(|(_, value)| value.as_str())
+                };
+                field("as").or_else(|| field("name"))
+            }
+            _ => None,
+        };
+
+        // Only directives need stubs. Imported types must be defined by the schema itself.
+        if let Some(directive_name) = imported.and_then(|name| name.strip_prefix('@')) {
+            linked.imports.insert(directive_name.to_string());
+        }
+    }
+}
+
+/// The name of a linked spec is the last path segment of its url that isn't a version, e.g. `federation`
+/// for `https://specs.apollo.dev/federation/v2.3`.
+fn spec_name(url: &str) -> Option<&str> {
+    let is_version = |segment: &str| {
+        segment
+            .strip_prefix('v')
+            .is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+    };
+
+    url.split('/')
+        .rev()
+        .find(|segment| !segment.is_empty() && !is_version(segment))
+}
+
+/// All directives applied to a type system definition, including those on its fields, arguments, and values.
+fn directive_usages(def: &Definition) -> Vec<&Node<Directive>> {
+    fn fields_usages<'a>(
+        directives: &'a DirectiveList,
+        fields: &'a [Node<FieldDefinition>],
+    ) -> Vec<&'a Node<Directive>> {
+        directives
+            .iter()
+            .chain(fields.iter().flat_map(|field| {
+                field
+                    .directives
+                    .iter()
+                    .chain(field.arguments.iter().flat_map(|arg| arg.directives.iter()))
+            }))
+            .collect()
+    }
+
+    fn input_usages<'a>(
+        directives: &'a DirectiveList,
+        fields: &'a [Node<InputValueDefinition>],
+    ) -> Vec<&'a Node<Directive>> {
+        directives
+            .iter()
+            .chain(fields.iter().flat_map(|field| field.directives.iter()))
+            .collect()
+    }
+
+    match def {
+        Definition::SchemaDefinition(def) => def.directives.iter().collect(),
+        Definition::SchemaExtension(def) => def.directives.iter().collect(),
+        Definition::ScalarTypeDefinition(def) => def.directives.iter().collect(),
+        Definition::ScalarTypeExtension(def) => def.directives.iter().collect(),
+        Definition::ObjectTypeDefinition(def) => fields_usages(&def.directives, &def.fields),
+        Definition::ObjectTypeExtension(def) => fields_usages(&def.directives, &def.fields),
+        Definition::InterfaceTypeDefinition(def) => fields_usages(&def.directives, &def.fields),
+        Definition::InterfaceTypeExtension(def) => fields_usages(&def.directives, &def.fields),
+        Definition::UnionTypeDefinition(def) => def.directives.iter().collect(),
+        Definition::UnionTypeExtension(def) => def.directives.iter().collect(),
+        Definition::EnumTypeDefinition(def) => def
+            .directives
+            .iter()
+            .chain(def.values.iter().flat_map(|value| value.directives.iter()))
+            .collect(),
+        Definition::EnumTypeExtension(def) => def
+            .directives
+            .iter()
+            .chain(def.values.iter().flat_map(|value| value.directives.iter()))
+            .collect(),
+        Definition::InputObjectTypeDefinition(def) => input_usages(&def.directives, &def.fields),
+        Definition::InputObjectTypeExtension(def) => input_usages(&def.directives, &def.fields),
+        Definition::OperationDefinition(_)
+        | Definition::FragmentDefinition(_)
+        | Definition::DirectiveDefinition(_) => vec![],
+    }
+}
+
+fn stub_definition(name: Name, arguments: impl Iterator<Item = Name>) -> Definition {
+    Definition::DirectiveDefinition(Node::new(DirectiveDefinition {
+        description: None,
+        name,
+        arguments: arguments
+            .map(|arg| {
+                Node::new(InputValueDefinition {
+                    description: None,
+                    name: arg,
+                    ty: Node::new(Type::Named(name!("_Any"))),
+                    default_value: None,
+                    directives: Default::default(),
+                })
+            })
+            .collect(),
+        repeatable: true,
+        locations: vec![
+            DirectiveLocation::Schema,
+            DirectiveLocation::Scalar,
+            DirectiveLocation::Object,
+            DirectiveLocation::FieldDefinition,
+            DirectiveLocation::ArgumentDefinition,
+            DirectiveLocation::Interface,
+            DirectiveLocation::Union,
+            DirectiveLocation::Enum,
+            DirectiveLocation::EnumValue,
+            DirectiveLocation::InputObject,
+            DirectiveLocation::InputFieldDefinition,
+        ],
+    }))
+}
@@ -2,18 +2,19 @@ use anyhow::anyhow;
 use apollo_compiler::{
     Name, Node, Schema,
     ast::{
-        Definition, Directive, DirectiveList, Document, FieldDefinition, InputValueDefinition,
-        OperationType, SchemaDefinition, Type,
+        Definition, Directive, Document, FieldDefinition, InputValueDefinition, OperationType,
+        SchemaDefinition, Type,
     },
     collections::IndexSet,
     name,
     schema::{Component, ComponentName, ComponentOrigin, ExtendedType, ObjectType, UnionType},
 };
-use tracing::warn;
-
+use serde::{Deserialize, Serialize};
 mod definitions;
+mod link;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FederationType {
     Subgraph,
     Supergraph,
@@ -24,64 +25,79 @@ pub enum FederationType {
 ///
 /// This means patching in a schema definition if it doesn't exist, and ensuring all relevant directives are in scope.
 ///
-/// Returns the federation type of this schema as inferred by the presence of key directives and types.
-/// Subgraph schemas are identified by the presence of the `@link` directive on their schema extension.
-/// Supergraph schemas are identified by the presence of the `join__Graph` enum.
-pub fn patch_ast(ast: &mut Document) -> FederationType {
-    let schema_extension = ast.definitions.iter().find_map(|def| match def {
-        Definition::SchemaExtension(node) => Some(node),
-        _ => None,
-    });
+/// Returns the federation type of this schema, which is `federation_type` if set and otherwise inferred by
+/// [infer_federation_type].
+pub fn patch_ast(ast: &mut Document, federation_type: Option<FederationType>) -> FederationType {
+    let fed_type = federation_type.unwrap_or_else(|| infer_federation_type(ast));
 
-    // The `join__Graph` enum is a required part of the supergraph spec
-    let fed_type = if ast
-        .definitions
-        .iter()
-        .any(|definition| definition.name().is_some_and(|name| name == "join__Graph"))
-    {
-        FederationType::Supergraph
-    } else if let Some(extension) = schema_extension
-        // If `join__Graph` is not present, but the schema is still extended with `@link`, then this is a subgraph schema
-        && extension.directives.iter().any(|dir| dir.name == "link")
-    {
-        // Federated subgraph schemas can omit the root schema definition entirely, and it is expected to be implicitly added
+    if let FederationType::Subgraph = fed_type {
+        // Federated subgraph schemas can omit the root schema definition entirely, and it is expected to be implicitly added.
+        // The schema extension may still declare a custom query root, in which case we must not add our own.
         if !ast
             .definitions
             .iter()
             .any(|def| matches!(def, Definition::SchemaDefinition(_)))
         {
+            let extension_declares_query = ast.definitions.iter().any(|def| match def {
+                Definition::SchemaExtension(ext) => ext
+                    .root_operations
+                    .iter()
+                    .any(|op| op.0 == OperationType::Query),
+                _ => false,
+            });
+            let root_operations = if extension_declares_query {
+                vec![]
+            } else {
+                vec![Node::new((OperationType::Query, name!("Query")))]
+            };
+
             ast.definitions
                 .push(Definition::SchemaDefinition(Node::new(SchemaDefinition {
                     description: None,
                     directives: Default::default(),
-                    root_operations: vec![Node::new((OperationType::Query, name!("Query")))],
+                    root_operations,
                 })));
         }
         // The federation spec requires that all these directives be implicitly added to the schema for a subgraph server
         ast.definitions
             .append(&mut definitions::federation_directives());
-
-        FederationType::Subgraph
-    } else {
-        FederationType::None
-    };
+    }
 
     if let FederationType::Subgraph | FederationType::Supergraph = fed_type {
         // The `@link` directive must be followed to import values that may be referenced in the file
-        // This behavior is currently not implemented.
-        for def in &ast.definitions {
-            if let Definition::SchemaDefinition(schema_def) = def {
-                process_link_directives(&schema_def.directives);
-            }
-            if let Definition::SchemaExtension(schema_ext) = def {
-                process_link_directives(&schema_ext.directives);
-            }
-        }
+        link::inject_linked_directive_stubs(ast);
     }
 
     fed_type
 }
 
+/// Infers the federation type of a schema by the presence of key directives and types.
+/// Subgraph schemas are identified by the presence of the `@link` directive on their schema extension.
+/// Supergraph schemas are identified by the presence of the `join__Graph` enum.
+fn infer_federation_type(ast: &Document) -> FederationType {
+    // The `join__Graph` enum is a required part of the supergraph spec
+    if ast
+        .definitions
+        .iter()
+        .any(|definition| definition.name().is_some_and(|name| name == "join__Graph"))
+    {
+        return FederationType::Supergraph;
+    }
+
+    // If `join__Graph` is not present, but the schema is still extended with `@link`, then this is a subgraph schema
+    let schema_extension = ast.definitions.iter().find_map(|def| match def {
+        Definition::SchemaExtension(node) => Some(node),
+        _ => None,
+    });
+    if schema_extension
+        .is_some_and(|extension| extension.directives.iter().any(|dir| dir.name == "link"))
+    {
+        FederationType::Subgraph
+    } else {
+        FederationType::None
+    }
+}
+
 /// We need to be able to intercept and handle queries for entities and service:
 /// {
 ///   _entities(representations: [_Any!]!): [_Entity]!
@@ -96,12 +112,19 @@ pub fn patch_ast(ast: &mut Document) -> FederationType {
 ///
 /// The directive definitions are copied from here:
 ///   https://github.com/apollographql/router/blob/23e580e22a4401cc2e7a952b241a1ec955b29c99/apollo-federation/src/api_schema.rs#L156https://github.com/apollographql/router/blob/23e580e22a4401cc2e7a952b241a1ec955b29c99/apollo-federation/src/api_schema.rs#L156
-pub fn patch_schema(schema: &mut Schema, federation_type: FederationType) -> anyhow::Result<()> {
+///
+/// With `strict_entities`, supergraph types are only members of the `_Entity` union when they are joined to a
+/// subgraph with a key.
+pub fn patch_schema(
+    schema: &mut Schema,
+    federation_type: FederationType,
+    strict_entities: bool,
+) -> anyhow::Result<()> {
     // Resolve federated object types for the _Entity union.
     let members: IndexSet<ComponentName> = schema
         .types
         .iter()
-        .filter(|(_, ty)| ty.is_object() && is_federated_type(schema, ty))
+        .filter(|(_, ty)| ty.is_object() && is_federated_type(schema, ty, strict_entities))
         .map(|(name, _)| ComponentName {
             origin: ComponentOrigin::Definition,
             name: name.clone(),
@@ -164,9 +187,14 @@ pub fn patch_schema(schema: &mut Schema, federation_type: FederationType) -> any
     };
 
     // Inject _entities query if appropriate and the _service query
-    let query_root = match schema.types.get_mut(query_type_name).unwrap() {
-        ExtendedType::Object(obj) => obj,
-        _ => return Err(anyhow!("query root is not an object")),
+    let query_root = match schema.types.get_mut(query_type_name) {
+        Some(ExtendedType::Object(obj)) => obj,
+        Some(_) => return Err(anyhow!("query root `{query_type_name}` is not an object")),
+        None => {
+            return Err(anyhow!(
+                "query root `{query_type_name}` is declared but never defined"
+            ));
+        }
     };
 
     if has_federated_members {
@@ -222,9 +250,9 @@ pub fn patch_schema(schema: &mut Schema, federation_type: FederationType) -> any
 }
 
 /// Determines if a type is federated based on its schema definition
-fn is_federated_type(schema: &Schema, ty: &ExtendedType) -> bool {
+fn is_federated_type(schema: &Schema, ty: &ExtendedType, strict_entities: bool) -> bool {
     ty.directives().iter().any(|directive| {
-        is_federated_directive(schema, directive)
+        is_federated_directive(schema, directive, strict_entities)
             // Do not include the query type if it is defined
             && schema
                 .schema_definition
@@ -238,26 +266,27 @@ fn is_federated_type(schema: &Schema, ty: &ExtendedType) -> bool {
 ///
 /// If we are loading a supergraph schema, types that are federated will use `@join__type`.
 /// If we are loading a subgraph schema, types that are federated will use [`@key`](key_definition).
-fn is_federated_directive(schema: &Schema, directive: &Component<Directive>) -> bool {
+/// With `strict_entities`, a `@join__type` only makes its type federated if it has a `key`.
+fn is_federated_directive(
+    schema: &Schema,
+    directive: &Component<Directive>,
+    strict_entities: bool,
+) -> bool {
     match directive.name.as_str() {
+        "join__type"
+            if strict_entities && directive.specified_argument_by_name("key").is_none() =>
+        {
+            false
+        }
         "key" | "join__type" => {
-            // federated unless explicitly marked resolvable: false
+            // federated unless explicitly marked resolvable: false. Directives without a boolean `resolvable`
+            // argument aren't federation's, which happens when a schema isn't detected as federated.
             directive
                 .argument_by_name("resolvable", schema)
                 .ok()
                 .and_then(|arg| arg.to_bool())
-                .expect("the @key and @join__type directives specify 'resolvable' as a boolean argument")
+                .unwrap_or(false)
         }
         _ => false,
     }
 }
-
-/// `@link` directives describe external imports of other GraphQL schema files. We do not currently handle
-/// that external resolution as our present use cases only are "importing" the federation spec itself.
-fn process_link_directives(directives: &DirectiveList) {
-    for directive in directives {
-        if directive.name == "link" {
-            warn!("@link directive detected, but link directive resolution is not implemented.")
-        }
-    }
-}
@@ -1,8 +1,17 @@
+use crate::error::SubgraphMockError;
 use anyhow::anyhow;
-use apollo_compiler::{Schema, ast::Document, validation::Valid};
+use apollo_compiler::{
+    Node, Schema,
+    ast::{Definition, DirectiveDefinition, DirectiveLocation, Document},
+    collections::HashMap,
+    name,
+    validation::Valid,
+};
+use serde::{Deserialize, Serialize};
 use std::{
     fs,
     hash::{Hash, Hasher},
+    mem,
     ops::Deref,
     path::{Path, PathBuf},
     sync::Arc,
@@ -12,6 +21,38 @@ use tracing::info;
 
 mod federation;
 
+pub use federation::FederationType;
+
+/// How schemas are patched to support federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederationConfig {
+    /// When disabled, schemas are treated as plain GraphQL: no federation directives are stubbed and neither
+    /// `_entities` nor `_service` is added to the query root
+    #[serde(default = "default_federation_enabled")]
+    pub enabled: bool,
+    /// Bypasses inferring the federation type from the schema when set
+    #[serde(default)]
+    pub federation_type: Option<FederationType>,
+    /// Limits the `_Entity` union of a supergraph to the types that some subgraph declares a key for, rather than
+    /// every type joined to a subgraph, so that value types can't be resolved as entities
+    #[serde(default)]
+    pub strict_entities: bool,
+}
+
+fn default_federation_enabled() -> bool {
+    true
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_federation_enabled(),
+            federation_type: None,
+            strict_entities: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct FederatedSchema {
     valid: Valid<Schema>,
@@ -27,25 +68,93 @@ impl Deref for FederatedSchema {
 }
 
 impl FederatedSchema {
-    /// Parse the file at `path` as a GraphQL schema.
-    pub fn parse(path: &PathBuf) -> anyhow::Result<Self> {
-        info!(path=%path.display(), "loading and parsing supergraph schema");
-        let source = fs::read_to_string(path)?;
+    /// Parse the files at `paths` as a single GraphQL schema. Any directories will be expanded to the `.graphql`
+    /// and `.graphqls` files that they directly contain.
+    pub fn parse(paths: &[PathBuf]) -> Result<Self, SubgraphMockError> {
+        Self::parse_with_federation(paths, &FederationConfig::default())
+    }
 
-        Self::parse_string(source, path)
+    /// Like [FederatedSchema::parse], but patching the schema for federation according to `federation`
+    pub fn parse_with_federation(
+        paths: &[PathBuf],
+        federation: &FederationConfig,
+    ) -> Result<Self, SubgraphMockError> {
+        let mut sources = Vec::new();
+        for path in schema_files(paths)? {
+            info!(path=%path.display(), "loading and parsing supergraph schema");
+            let source = fs::read_to_string(&path).map_err(SubgraphMockError::io(&path))?;
+            sources.push((source, path));
+        }
+
+        Self::parse_sources(sources, federation)
     }
 
     /// Parse `source` as a GraphQL schema. `path` will be used in diagnostic errors to identify this schema.
-    pub fn parse_string(source: impl ToString, path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        // Parse the raw AST as federation-compatible schemas won't start out as valid GraphQL
-        let mut ast = Document::parse(source.to_string(), path).map_err(|err| anyhow!(err))?;
-        let federation_type = federation::patch_ast(&mut ast);
+    pub fn parse_string(
+        source: impl ToString,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, SubgraphMockError> {
+        Self::parse_string_with_federation(source, path, &FederationConfig::default())
+    }
 
-        let mut schema = ast.to_schema().map_err(|err| anyhow!(err))?;
-        federation::patch_schema(&mut schema, federation_type)?;
+    /// Like [FederatedSchema::parse_string], but patching the schema for federation according to `federation`
+    pub fn parse_string_with_federation(
+        source: impl ToString,
+        path: impl AsRef<Path>,
+        federation: &FederationConfig,
+    ) -> Result<Self, SubgraphMockError> {
+        Self::parse_sources(
+            [(source.to_string(), path.as_ref().to_path_buf())],
+            federation,
+        )
+    }
+
+    /// Parse each of `sources` and merge them into one GraphQL schema. Merging happens at the AST level so that
+    /// type extensions in one file can apply to types defined in another.
+    fn parse_sources(
+        sources: impl IntoIterator<Item = (String, PathBuf)>,
+        federation: &FederationConfig,
+    ) -> Result<Self, SubgraphMockError> {
+        let mut merged: Option<(Document, String)> = None;
+
+        for (source, path) in sources {
+            // Parse the raw AST as federation-compatible schemas won't start out as valid GraphQL
+            let ast = Document::parse(source.as_str(), &path)
+                .map_err(|err| SubgraphMockError::Parse(anyhow!(err)))?;
+
+            merged = Some(match merged {
+                None => (ast, source),
+                Some((mut merged_ast, mut merged_source)) => {
+                    merge_documents(&mut merged_ast, ast).map_err(SubgraphMockError::Parse)?;
+                    merged_source.push('\n');
+                    merged_source.push_str(&source);
+                    (merged_ast, merged_source)
+                }
+            });
+        }
+
+        let (mut ast, source) = merged
+            .ok_or_else(|| SubgraphMockError::Parse(anyhow!("no schema files were provided")))?;
+        define_one_of(&mut ast);
+        let federation_type = federation
+            .enabled
+            .then(|| federation::patch_ast(&mut ast, federation.federation_type));
+
+        let mut schema = ast
+            .to_schema()
+            .map_err(|err| SubgraphMockError::Validation(anyhow!(err)))?;
+        match federation_type {
+            Some(federation_type) => {
+                federation::patch_schema(&mut schema, federation_type, federation.strict_entities)
+                    .map_err(SubgraphMockError::Validation)?
+            }
+            None => info!("federation is disabled, treating the schema as plain GraphQL"),
+        }
         Ok(Self {
-            valid: schema.validate().map_err(|err| anyhow!(err))?,
-            source: source.to_string(),
+            valid: schema
+                .validate()
+                .map_err(|err| SubgraphMockError::Validation(anyhow!(err)))?,
+            source,
         })
     }
 
@@ -61,16 +170,122 @@ impl Hash for FederatedSchema {
     }
 }
 
-pub fn update_schema(path: &PathBuf, lock: Arc<RwLock<FederatedSchema>>) -> anyhow::Result<()> {
-    let schema = FederatedSchema::parse(path)?;
+/// Moves all definitions from `other` into `base`. Identical definitions of the same named element are
+/// deduplicated, but conflicting definitions are rejected.
+fn merge_documents(base: &mut Document, other: Document) -> anyhow::Result<()> {
+    let existing: HashMap<_, _> = base
+        .definitions
+        .iter()
+        .filter(|def| !def.is_extension_definition())
+        .filter_map(|def| Some(((mem::discriminant(def), def.name()?.clone()), def.clone())))
+        .collect();
+
+    Arc::make_mut(&mut base.sources)
+        .extend(other.sources.iter().map(|(id, file)| (*id, file.clone())));
+
+    for def in other.definitions {
+        if !def.is_extension_definition()
+            && let Some(name) = def.name()
+            && let Some(previous) = existing.get(&(mem::discriminant(&def), name.clone()))
+        {
+            if *previous == def {
+                continue;
+            }
+
+            return Err(anyhow!(
+                "`{name}` is defined differently in {} and {}",
+                source_name(base, previous),
+                source_name(base, &def),
+            ));
+        }
+
+        base.definitions.push(def);
+    }
+
+    Ok(())
+}
+
+/// `@oneOf` is part of the GraphQL spec, but it isn't built into the compiler yet, so schemas that use it without
+/// defining it would otherwise fail to validate
+fn define_one_of(ast: &mut Document) {
+    let mut uses_one_of = false;
+    for def in &ast.definitions {
+        match def {
+            Definition::DirectiveDefinition(directive) if directive.name == "oneOf" => return,
+            Definition::InputObjectTypeDefinition(input) => {
+                uses_one_of |= input.directives.has("oneOf");
+            }
+            Definition::InputObjectTypeExtension(input) => {
+                uses_one_of |= input.directives.has("oneOf");
+            }
+            _ => {}
+        }
+    }
+
+    if uses_one_of {
+        ast.definitions
+            .push(Definition::DirectiveDefinition(Node::new(
+                DirectiveDefinition {
+                    description: None,
+                    name: name!("oneOf"),
+                    arguments: vec![],
+                    repeatable: false,
+                    locations: vec![DirectiveLocation::InputObject],
+                },
+            )));
+    }
+}
+
+fn source_name(doc: &Document, def: &Definition) -> String {
+    def.location()
+        .and_then(|location| doc.sources.get(&location.file_id()))
+        .map(|file| file.path().display().to_string())
+        .unwrap_or_else(|| "an unknown file".to_string())
+}
+
+/// Expands directories in `paths` into the schema files that they contain, in a stable order.
+pub fn schema_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, SubgraphMockError> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut dir_files: Vec<PathBuf> = fs::read_dir(path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|entry| entry.path()))
+                        .collect()
+                })
+                .map_err(SubgraphMockError::io(path))?;
+            dir_files.retain(|file| {
+                file.is_file()
+                    && file
+                        .extension()
+                        .is_some_and(|ext| ext == "graphql" || ext == "graphqls")
+            });
+            dir_files.sort();
+            files.extend(dir_files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    Ok(files)
+}
+
+pub fn update_schema(
+    paths: &[PathBuf],
+    federation: &FederationConfig,
+    lock: Arc<RwLock<FederatedSchema>>,
+) -> anyhow::Result<()> {
+    let schema = FederatedSchema::parse_with_federation(paths, federation)?;
     *lock.blocking_write() = schema;
-    info!(path=%path.display(), "new supergraph schema loaded");
+    info!(paths=?paths, "new supergraph schema loaded");
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use apollo_compiler::schema::ExtendedType;
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -110,4 +325,188 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn custom_query_root_subgraph() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/custom-root-subgraph.graphql");
+        let validated =
+            FederatedSchema::parse_string(schema, "test-data/custom-root-subgraph.graphql")?;
+
+        assert_eq!(
+            validated.schema_definition.query.as_ref().unwrap().as_str(),
+            "RootQuery"
+        );
+        let root = validated.get_object("RootQuery").unwrap();
+        assert!(root.fields.contains_key("_service"));
+        assert!(root.fields.contains_key("_entities"));
+        assert!(!validated.types.contains_key("Query"));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_query_root_on_schema_extension() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/custom-root-extension-subgraph.graphql");
+        let validated = FederatedSchema::parse_string(
+            schema,
+            "test-data/custom-root-extension-subgraph.graphql",
+        )?;
+
+        let root = validated.get_object("RootQuery").unwrap();
+        assert!(root.fields.contains_key("_service"));
+        assert!(root.fields.contains_key("_entities"));
+        assert!(!validated.types.contains_key("Query"));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_query_root_non_federated() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/custom-root-non-federated.graphql");
+        let validated =
+            FederatedSchema::parse_string(schema, "test-data/custom-root-non-federated.graphql")?;
+
+        let root = validated.get_object("RootQuery").unwrap();
+        assert!(root.fields.contains_key("_service"));
+        assert!(!root.fields.contains_key("_entities"));
+        assert!(!validated.types.contains_key("Query"));
+        Ok(())
+    }
+
+    #[test]
+    fn federation_can_be_disabled() -> anyhow::Result<()> {
+        // This `@key` has nothing to do with federation, so it doesn't declare the `resolvable` argument
+        let schema = include_str!("test-data/plain-with-key-directive.graphql");
+        let plain = FederatedSchema::parse_string_with_federation(
+            schema,
+            "test-data/plain-with-key-directive.graphql",
+            &FederationConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        )?;
+
+        // Nothing is added, not even `_service`
+        assert_eq!(schema, plain.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn strict_entities_require_a_key() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/supergraph.graphql");
+        let path = "test-data/supergraph.graphql";
+        let entities = |strict_entities| {
+            let validated = FederatedSchema::parse_string_with_federation(
+                schema,
+                path,
+                &FederationConfig {
+                    strict_entities,
+                    ..Default::default()
+                },
+            )?;
+            let ExtendedType::Union(entity) = &validated.types["_Entity"] else {
+                panic!("_Entity should be a union");
+            };
+            anyhow::Ok(
+                entity
+                    .members
+                    .iter()
+                    .map(|member| member.name.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(entities(false)?, ["Address", "Post", "User"]);
+        // `Address` is joined to a subgraph without a key, so it's a value type rather than an entity
+        assert_eq!(entities(true)?, ["Post", "User"]);
+        Ok(())
+    }
+
+    #[test]
+    fn federation_type_can_be_overridden() -> anyhow::Result<()> {
+        // Without `@link` this isn't recognised as a subgraph, so `@key` is never defined
+        let schema = include_str!("test-data/unlinked-subgraph.graphql");
+        let path = "test-data/unlinked-subgraph.graphql";
+        assert!(FederatedSchema::parse_string(schema, path).is_err());
+
+        let validated = FederatedSchema::parse_string_with_federation(
+            schema,
+            path,
+            &FederationConfig {
+                federation_type: Some(FederationType::Subgraph),
+                ..Default::default()
+            },
+        )?;
+        let query = validated.get_object("Query").unwrap();
+        assert!(query.fields.contains_key("_entities"));
+        assert!(query.fields.contains_key("_service"));
+        Ok(())
+    }
+
+    #[test]
+    fn one_of_inputs_validate() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/one-of-input.graphql");
+        let validated = FederatedSchema::parse_string(schema, "test-data/one-of-input.graphql")?;
+
+        assert!(validated.directive_definitions.contains_key("oneOf"));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_query_type_is_an_error() {
+        let err = FederatedSchema::parse_string("type User { id: ID }", "no-query.graphql")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Schema does not define a query type");
+
+        let err = FederatedSchema::parse_string(
+            "schema { query: Missing }\ntype User { id: ID }",
+            "undefined-query.graphql",
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(err, "query root `Missing` is declared but never defined");
+    }
+
+    #[test]
+    fn linked_directives_are_stubbed() -> anyhow::Result<()> {
+        let schema = include_str!("test-data/linked-subgraph.graphql");
+        let validated = FederatedSchema::parse_string(schema, "test-data/linked-subgraph.graphql")?;
+
+        let cache = validated.directive_definitions.get("cache").unwrap();
+        assert!(cache.argument_by_name("maxAge").is_some());
+
+        let rate_limit = validated.directive_definitions.get("rateLimit").unwrap();
+        assert!(rate_limit.argument_by_name("max").is_some());
+        assert!(rate_limit.argument_by_name("window").is_some());
+
+        assert!(validated.directive_definitions.contains_key("ext__owner"));
+        assert!(!validated.directive_definitions.contains_key("limit"));
+        Ok(())
+    }
+
+    #[test]
+    fn split_schema_files_merge() -> anyhow::Result<()> {
+        let dir =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/state/schema/test-data/split");
+        let schema = FederatedSchema::parse(&[dir])?;
+
+        let user = schema.get_object("User").unwrap();
+        assert!(user.fields.contains_key("posts"));
+        assert!(schema.get_object("Post").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_schema_files_are_rejected() {
+        let dir =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/state/schema/test-data/conflict");
+        let err = FederatedSchema::parse(&[dir.join("a.graphql"), dir.join("b.graphql")])
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("`User` is defined differently"), "{err}");
+        assert!(
+            err.contains("a.graphql") && err.contains("b.graphql"),
+            "{err}"
+        );
+    }
 }
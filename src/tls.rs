@@ -0,0 +1,52 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, sync::Arc};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+
+/// Paths to the PEM encoded certificate chain and private key used to serve HTTPS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Load the certificate chain and private key from disk into an acceptor that can be used to wrap incoming
+    /// connections. Both HTTP/2 and HTTP/1.1 are advertised via ALPN unless `http2_only` is set.
+    pub fn acceptor(&self, http2_only: bool) -> anyhow::Result<TlsAcceptor> {
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| {
+                format!(
+                    "failed to load TLS certificate from {}",
+                    self.cert_path.display()
+                )
+            })?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).with_context(|| {
+            format!(
+                "failed to load TLS private key from {}",
+                self.key_path.display()
+            )
+        })?;
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("TLS certificate and private key are not valid for use together")?;
+        config.alpn_protocols = if http2_only {
+            vec![b"h2".to_vec()]
+        } else {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        };
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
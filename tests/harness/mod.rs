@@ -5,17 +5,26 @@ use apollo_parser::Parser;
 use apollo_smith::{Document, DocumentBuilder};
 use arbitrary::Unstructured;
 use cached::proc_macro::cached;
+use futures::stream::{FuturesUnordered, StreamExt};
 use http_body_util::{BodyExt, Full};
-use hyper::{Request, Response as HyperResponse, body::Bytes};
+use hyper::{HeaderMap, Request, Response as HyperResponse, body::Bytes};
 use rand::{Rng, SeedableRng, rngs::StdRng};
 use serde_json_bytes::{Value, serde_json};
-use std::{borrow::Borrow, path::PathBuf, sync::Arc};
+use std::{borrow::Borrow, env, fs, path::PathBuf, sync::Arc};
 use subgraph_mock::{
     Args,
-    handle::{ByteResponse, graphql::GraphQLRequest, handle_request},
+    handle::{
+        ByteResponse,
+        graphql::{GraphQLRequest, SEED_HEADER},
+        handle_request,
+    },
+    latency::SawDirection,
     state::State,
 };
-use tokio::time::{self, Duration, Instant};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    time::{self, Duration, Instant},
+};
 use tracing::debug;
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
@@ -35,6 +44,15 @@ fn schema_pathbuf<S: Into<String>>(schema_file_name: Option<S>) -> PathBuf {
     PathBuf::from(format!("{pkg_root}/tests/data/{schema_file_name}.graphql"))
 }
 
+/// Copies the test schema into a file of its own that can be edited without affecting other tests
+pub fn schema_copy(name: &str) -> anyhow::Result<PathBuf> {
+    let dir = env::temp_dir().join(format!("subgraph-mock-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{name}.graphql"));
+    fs::write(&path, fs::read_to_string(schema_pathbuf(None::<String>))?)?;
+    Ok(path)
+}
+
 /// Initializes the state of the mock server based on the optional config file name that maps to
 /// a YAML config located in `tests/data/config`.
 ///
@@ -55,13 +73,25 @@ pub fn initialize(
         )
         .try_init();
 
+    args(config_file_name, schema_file_name)
+        .init()
+        .map(|(server, state)| (server.listen_address.port(), Arc::new(state)))
+}
+
+/// The command line [Args] for the given config file name in `tests/data/config` and schema file name in `tests/data`
+pub fn args(config_file_name: Option<&str>, schema_file_name: Option<&str>) -> Args {
     let pkg_root = env!("CARGO_MANIFEST_DIR");
-    let args = Args {
+    Args {
         config: config_file_name
             .map(|name| PathBuf::from(format!("{pkg_root}/tests/data/config/{name}"))),
-        schema: schema_pathbuf(schema_file_name),
-    };
-    args.init().map(|(port, state)| (port, Arc::new(state)))
+        schema: vec![schema_pathbuf(schema_file_name)],
+        bind: None,
+        watch: None,
+        print_schema: false,
+        port: None,
+        cache_responses: None,
+        null_ratio: None,
+    }
 }
 
 /// Cached supergraph document that is used as the basis for generating requests
@@ -96,8 +126,6 @@ fn generate_schema(schema_file_name: Option<String>) -> anyhow::Result<Valid<Sch
 ///
 /// Run your test case with `RUST_LOG=debug` to see the query generated for a given RNG seed. This will allow you
 /// to then make assumptions about the structure of your responses in the test.
-///
-/// Borrows heavily from the example in the apollo-smith docs.
 pub async fn make_request<T>(
     rng_seed: u64,
     state: Arc<State>,
@@ -106,6 +134,48 @@ pub async fn make_request<T>(
 where
     T: Borrow<Option<String>>,
 {
+    send_request(
+        generate_operation(rng_seed)?,
+        None,
+        state,
+        subgraph_name,
+        true,
+    )
+    .await
+}
+
+/// Identical to [make_request], but the response is generated from `response_seed` by sending it in the
+/// [SEED_HEADER], when there is one. Combined with a config that has `allow_seed_override` set and caching turned
+/// off, such as `seeded_no_cache.yaml`, this sends the same generated query repeatedly to check that responses
+/// differ without a seed, and match with the same one.
+pub async fn make_seeded_request<T>(
+    rng_seed: u64,
+    response_seed: Option<u64>,
+    state: Arc<State>,
+    subgraph_name: T,
+) -> anyhow::Result<ByteResponse>
+where
+    T: Borrow<Option<String>>,
+{
+    let mut headers = HeaderMap::new();
+    if let Some(response_seed) = response_seed {
+        headers.insert(SEED_HEADER, response_seed.into());
+    }
+    send_request_with_headers(
+        generate_operation(rng_seed)?,
+        None,
+        state,
+        subgraph_name,
+        true,
+        headers,
+    )
+    .await
+}
+
+/// Generates a random operation for the test schema from `rng_seed`, which is always the same for the same seed.
+///
+/// Borrows heavily from the example in the apollo-smith docs.
+fn generate_operation(rng_seed: u64) -> anyhow::Result<String> {
     let apollo_smith_doc = generate_test_doc()?;
 
     let mut rng = StdRng::seed_from_u64(rng_seed);
@@ -120,7 +190,7 @@ where
 
     debug!("Query for seed {rng_seed}:\n{operation_def}");
 
-    send_request(operation_def, None, state, subgraph_name, true).await
+    Ok(operation_def)
 }
 
 /// Runs a single request to the mock server through the handler method. Uses the operation provided.
@@ -140,6 +210,80 @@ pub async fn send_request<T>(
     subgraph_name: T,
     validate: bool,
 ) -> anyhow::Result<ByteResponse>
+where
+    T: Borrow<Option<String>>,
+{
+    send_request_with_headers(
+        operation_def,
+        schema_name,
+        state,
+        subgraph_name,
+        validate,
+        HeaderMap::new(),
+    )
+    .await
+}
+
+/// Identical to [send_request], but attaches the provided `headers` to the request sent to the mock server.
+pub async fn send_request_with_headers<T>(
+    operation_def: String,
+    schema_name: Option<String>,
+    state: Arc<State>,
+    subgraph_name: T,
+    validate: bool,
+    headers: HeaderMap,
+) -> anyhow::Result<ByteResponse>
+where
+    T: Borrow<Option<String>>,
+{
+    send_graphql_request(
+        operation_def,
+        JsonMap::new(),
+        schema_name,
+        state,
+        subgraph_name,
+        validate,
+        headers,
+    )
+    .await
+}
+
+/// Identical to [send_request], but sends `variables` along with the operation. `variables` must be a JSON object.
+pub async fn send_request_with_variables<T>(
+    operation_def: String,
+    variables: Value,
+    schema_name: Option<String>,
+    state: Arc<State>,
+    subgraph_name: T,
+    validate: bool,
+) -> anyhow::Result<ByteResponse>
+where
+    T: Borrow<Option<String>>,
+{
+    let Value::Object(variables) = variables else {
+        return Err(anyhow!("variables must be an object"));
+    };
+    send_graphql_request(
+        operation_def,
+        variables,
+        schema_name,
+        state,
+        subgraph_name,
+        validate,
+        HeaderMap::new(),
+    )
+    .await
+}
+
+async fn send_graphql_request<T>(
+    operation_def: String,
+    variables: JsonMap,
+    schema_name: Option<String>,
+    state: Arc<State>,
+    subgraph_name: T,
+    validate: bool,
+    headers: HeaderMap,
+) -> anyhow::Result<ByteResponse>
 where
     T: Borrow<Option<String>>,
 {
@@ -151,13 +295,14 @@ where
     let body = serde_json::to_vec(&GraphQLRequest {
         query: operation_def.clone(),
         operation_name: None,
-        variables: JsonMap::new(),
+        variables,
     })?;
 
-    let req = Request::builder()
+    let mut req = Request::builder()
         .method("POST")
         .uri(uri)
         .body(Full::<Bytes>::from(body))?;
+    *req.headers_mut() = headers;
 
     // Rip the body out, validate it, then repackage it to return
     let (parts, body) = handle_request(req, state).await?.into_parts();
@@ -187,6 +332,131 @@ where
     Ok(HyperResponse::from_parts(parts, boxed_body))
 }
 
+/// Runs `n` concurrent requests through [make_request] with the same `rng_seed`, for asserting on how the random parts
+/// of their responses are distributed. Bodies are read in full so that responses can be inspected more than once.
+pub async fn make_requests<T>(
+    rng_seed: u64,
+    n: usize,
+    state: Arc<State>,
+    subgraph_name: T,
+) -> anyhow::Result<Vec<HyperResponse<Bytes>>>
+where
+    T: Borrow<Option<String>>,
+{
+    let subgraph_name = subgraph_name.borrow();
+    buffer_responses((0..n).map(|_| make_request(rng_seed, state.clone(), subgraph_name))).await
+}
+
+/// Identical to [make_requests], but sends `operation_def` with [send_request] instead of a generated query
+pub async fn send_requests<T>(
+    operation_def: &str,
+    n: usize,
+    state: Arc<State>,
+    subgraph_name: T,
+    validate: bool,
+) -> anyhow::Result<Vec<HyperResponse<Bytes>>>
+where
+    T: Borrow<Option<String>>,
+{
+    let subgraph_name = subgraph_name.borrow();
+    buffer_responses((0..n).map(|_| {
+        send_request(
+            operation_def.to_string(),
+            None,
+            state.clone(),
+            subgraph_name,
+            validate,
+        )
+    }))
+    .await
+}
+
+async fn buffer_responses(
+    requests: impl Iterator<Item = impl Future<Output = anyhow::Result<ByteResponse>>>,
+) -> anyhow::Result<Vec<HyperResponse<Bytes>>> {
+    let mut requests: FuturesUnordered<_> = requests.collect();
+    let mut responses = Vec::with_capacity(requests.len());
+    while let Some(response) = requests.next().await {
+        let (parts, body) = response?.into_parts();
+        responses.push(HyperResponse::from_parts(
+            parts,
+            body.collect().await?.to_bytes(),
+        ));
+    }
+
+    Ok(responses)
+}
+
+/// Asserts that the fraction of `items` that match `predicate` is within `tolerance` of `expected`, for checking that
+/// something random happens about as often as it is configured to
+#[track_caller]
+pub fn assert_ratio<T>(
+    items: impl IntoIterator<Item = T>,
+    mut predicate: impl FnMut(&T) -> bool,
+    expected: f64,
+    tolerance: f64,
+) {
+    let (mut matching, mut total) = (0, 0);
+    for item in items {
+        total += 1;
+        if predicate(&item) {
+            matching += 1;
+        }
+    }
+
+    assert!(total > 0, "there are no items to take a ratio of");
+    let ratio = matching as f64 / total as f64;
+    assert!(
+        (ratio - expected).abs() <= tolerance,
+        "{matching} of {total} matched ({ratio:.3}), expected {expected} ± {tolerance}"
+    );
+}
+
+/// Writes a minimal HTTP/1.1 POST of `body` to `path` on an open connection to a running mock server and reads back
+/// the response. Returns the status line and the response body. The connection is kept alive so that it can be
+/// reused for subsequent requests.
+pub async fn send_raw_request<S>(
+    stream: &mut S,
+    path: &str,
+    body: &str,
+) -> anyhow::Result<(String, String)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!(
+                "connection closed before the response headers ended"
+            ));
+        }
+        if line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse()?;
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok((status_line.trim_end().to_string(), String::from_utf8(body)?))
+}
+
 /// Run a single request with a timed lifecycle and assert that the generated latency for it matches
 /// `expected`. Returns the generated latency as a convenience for advancing time correctly as needed.
 async fn test_latency<T>(
@@ -330,6 +600,7 @@ pub async fn assert_is_saw<T>(
     base: u64,
     amplitude: u64,
     period: Duration,
+    direction: SawDirection,
     rng_seed: u64,
     state: Arc<State>,
     subgraph_name: T,
@@ -337,34 +608,40 @@ pub async fn assert_is_saw<T>(
 where
     T: Borrow<Option<String>>,
 {
-    // At t=0 seconds, our saw wave is at the bottom of the wave
-    let elapsed = test_latency(base, rng_seed, state.clone(), subgraph_name.borrow()).await?;
+    // The expected latency at a point on the rising slope. A falling saw is its mirror image.
+    let at = |rising: u64| match direction {
+        SawDirection::Rising => base + rising,
+        SawDirection::Falling => base + amplitude - rising,
+    };
 
-    // Advancing a half period should move us halfway up the slope
+    // At t=0 seconds, our saw wave is at the start of the slope
+    let elapsed = test_latency(at(0), rng_seed, state.clone(), subgraph_name.borrow()).await?;
+
+    // Advancing a half period should move us halfway along the slope
     time::advance(period.div_f64(2.0) - elapsed).await;
     let elapsed = test_latency(
-        base + amplitude / 2,
+        at(amplitude / 2),
         rng_seed,
         state.clone(),
         subgraph_name.borrow(),
     )
     .await?;
 
-    // Advancing another half period minus 1ms should hit the 'top'. By the nature of a saw wave, the drop from the
-    // top and 0 are a straight line (effectively simultaneous). So our function will never actually hit amplitude
-    // because it resets to 0 in that same tick of time.
+    // Advancing another half period minus 1ms should hit the end of the slope. By the nature of a saw wave, the
+    // jump from the end back to the start is a straight line (effectively simultaneous). So our function will never
+    // actually hit the end because it resets in that same tick of time.
     time::advance(period.div_f64(2.0) - elapsed - Duration::from_millis(1)).await;
     test_latency(
-        base + amplitude - 1,
+        at(amplitude - 1),
         rng_seed,
         state.clone(),
         subgraph_name.borrow(),
     )
     .await?;
 
-    // Advancing 1ms should put us back at the bottom of the wave
+    // Advancing 1ms should put us back at the start of the slope
     time::advance(Duration::from_millis(1)).await;
-    test_latency(base, rng_seed, state.clone(), subgraph_name.borrow()).await?;
+    test_latency(at(0), rng_seed, state.clone(), subgraph_name.borrow()).await?;
 
     Ok(())
 }
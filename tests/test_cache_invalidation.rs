@@ -0,0 +1,58 @@
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode, body::Bytes};
+use serde_json_bytes::{Value, serde_json};
+use std::{fs, sync::Arc};
+use subgraph_mock::{
+    handle::handle_request,
+    state::{Config, State},
+};
+use tokio::time::{Duration, Instant, sleep};
+
+mod harness;
+
+async fn get(path: &str, state: Arc<State>) -> anyhow::Result<Value> {
+    let req = Request::get(path).body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(serde_json::from_slice(
+        &resp.into_body().collect().await?.to_bytes(),
+    )?)
+}
+
+/// The caches are global, so this is the only test in this file to keep other requests from adding to them
+#[tokio::test]
+async fn schema_reloads_flush_caches() -> anyhow::Result<()> {
+    let path = harness::schema_copy("schema_reloads_flush_caches")?;
+    let state = Arc::new(State::new(Config::default(), vec![path.clone()])?);
+
+    let resp = harness::send_request(
+        "{ posts { id } }".to_string(),
+        None,
+        state.clone(),
+        None,
+        true,
+    )
+    .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let stats = get("/debug/cache", state.clone()).await?;
+    assert_eq!(stats["responses"]["size"], 1);
+    assert_eq!(stats["documents"]["size"], 1);
+
+    // A valid change to the schema, after which nothing generated for the old one should be kept around
+    let schema = fs::read_to_string(&path)?;
+    fs::write(&path, format!("{schema}\ntype Unused {{ id: ID! }}\n"))?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while get("/status", state.clone()).await?["schema"]["last_reload"]["success"] != true {
+        assert!(Instant::now() < deadline, "schema was not reloaded in time");
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    let stats = get("/debug/cache", state).await?;
+    assert_eq!(stats["responses"]["size"], 0);
+    assert_eq!(stats["documents"]["size"], 0);
+
+    fs::remove_file(path)?;
+    Ok(())
+}
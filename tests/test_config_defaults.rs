@@ -1,7 +1,10 @@
-use anyhow::ensure;
-use futures::stream::{FuturesUnordered, StreamExt};
-use harness::{Post, Query, User, assert_is_sine, make_request, parse_response};
+use harness::{
+    Post, Query, Response, User, assert_is_sine, assert_ratio, make_request, make_requests,
+    parse_response,
+};
+use serde_json_bytes::serde_json;
 use std::time::Duration;
+use subgraph_mock::handle::REQUEST_ID_HEADER;
 
 mod harness;
 
@@ -34,9 +37,13 @@ async fn default_headers() -> anyhow::Result<()> {
     let headers = response.headers();
 
     assert_eq!(200, response.status());
-    assert_eq!(1, headers.len());
+    assert_eq!(4, headers.len());
 
     assert!(headers.contains_key("content-type"));
+    assert!(headers.contains_key(REQUEST_ID_HEADER));
+    // Responses are cached by default, so they can be revalidated
+    assert!(headers.contains_key("etag"));
+    assert!(headers.contains_key("age"));
     Ok(())
 }
 
@@ -63,21 +70,18 @@ async fn default_response_generation_caches() -> anyhow::Result<()> {
 #[tokio::test(flavor = "multi_thread")]
 async fn default_response_generation() -> anyhow::Result<()> {
     let (_, state) = harness::initialize(Some("default_no_cache.yaml"), None)?;
-    let mut responses: Vec<Query> = Vec::with_capacity(1000);
-    let mut requests: FuturesUnordered<_> = (0..1000)
-        .map(|_| {
-            // This produces a query that has all data types represented. To see it, run the test with RUST_LOG=debug.
-            async {
-                let response = make_request(7, state.clone(), None).await?;
-                ensure!(200 == response.status());
-                parse_response(response).await
-            }
+    // This produces a query that has all data types represented. To see it, run the test with RUST_LOG=debug.
+    let responses: Vec<Query> = make_requests(7, 1000, state, None)
+        .await?
+        .into_iter()
+        .map(|response| {
+            assert_eq!(200, response.status());
+            let response: Response = serde_json::from_slice(response.body())?;
+            Ok(response
+                .data
+                .expect("data should be present on successful responses"))
         })
-        .collect();
-
-    while let Some(response) = requests.next().await {
-        responses.push(response?);
-    }
+        .collect::<anyhow::Result<_>>()?;
 
     // This field is a top-level alias in the query that requests a single user by ID (and is hence nullable)
     let user_alias = "nwHYPt6HYPXJ1";
@@ -96,6 +100,19 @@ async fn default_response_generation() -> anyhow::Result<()> {
         );
     }
 
+    // the default null ratio is 50% null
+    assert_ratio(
+        &responses,
+        |response| {
+            response
+                .aliased
+                .get(user_alias)
+                .is_some_and(|user| !user.is_null())
+        },
+        0.5,
+        0.05,
+    );
+
     let (users, posts): (Vec<User>, Vec<Post>) = {
         let (users, posts): (Vec<_>, Vec<_>) = responses
             .into_iter()
@@ -117,11 +134,6 @@ async fn default_response_generation() -> anyhow::Result<()> {
         )
     };
 
-    let user_count = users.len();
-
-    // the default null ratio is 50% null
-    assert_eq!("0.5", format!("{:.1}", user_count as f32 / 1000.0));
-
     for user in &users {
         // the default float range is -1.0 to 1.0
         assert!(
@@ -139,20 +151,17 @@ async fn default_response_generation() -> anyhow::Result<()> {
         assert!(user.id.is_some_and(|id| (0..=100).contains(&id)));
     }
 
-    let true_count = users
-        .iter()
-        .filter(|user| {
+    // booleans are configured to always be 50% true.
+    assert_ratio(
+        &users,
+        |user| {
             user.aliased
                 .get(user_is_active_alias)
                 .and_then(|is_active| is_active.as_bool())
                 .expect("is_active should be a bool")
-        })
-        .count();
-
-    // booleans are configured to always be 50% true.
-    assert_eq!(
-        "0.5",
-        format!("{:.1}", true_count as f32 / user_count as f32)
+        },
+        0.5,
+        0.05,
     );
 
     for post in posts {
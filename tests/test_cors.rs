@@ -0,0 +1,75 @@
+use http_body_util::Empty;
+use hyper::{
+    HeaderMap, Request, StatusCode,
+    body::Bytes,
+    header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_REQUEST_METHOD, HeaderValue, ORIGIN,
+    },
+};
+use std::sync::Arc;
+use subgraph_mock::{
+    handle::{ByteResponse, handle_request},
+    state::State,
+};
+
+mod harness;
+
+const ALLOWED_ORIGIN: &str = "https://studio.example.com";
+
+async fn preflight(state: Arc<State>, origin: &'static str) -> anyhow::Result<ByteResponse> {
+    let req = Request::builder()
+        .method("OPTIONS")
+        .uri("/")
+        .header(ORIGIN, origin)
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .body(Empty::<Bytes>::new())?;
+    handle_request(req, state).await
+}
+
+#[tokio::test]
+async fn preflight_requests() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("cors.yaml"), None)?;
+
+    let resp = preflight(state.clone(), ALLOWED_ORIGIN).await?;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let headers = resp.headers();
+    assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], ALLOWED_ORIGIN);
+    assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+    assert_eq!(
+        headers[ACCESS_CONTROL_ALLOW_HEADERS],
+        "content-type, apollographql-client-name"
+    );
+
+    // Other origins get no CORS headers, which the browser treats as a rejection
+    let resp = preflight(state, "https://elsewhere.example.com").await?;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    // Without a CORS config, OPTIONS is just another unsupported method
+    let (_, state) = harness::initialize(None, None)?;
+    let resp = preflight(state, ALLOWED_ORIGIN).await?;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn graphql_responses() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("cors.yaml"), None)?;
+    let headers = HeaderMap::from_iter([(ORIGIN, HeaderValue::from_static(ALLOWED_ORIGIN))]);
+
+    let resp = harness::send_request_with_headers(
+        "{ posts { id } }".to_string(),
+        None,
+        state,
+        None,
+        true,
+        headers,
+    )
+    .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], ALLOWED_ORIGIN);
+
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use anyhow::ensure;
-use futures::{
-    StreamExt,
-    stream::{self, FuturesUnordered},
+use harness::{
+    Response, assert_ratio, make_request, make_requests, make_seeded_request,
+    send_request_with_headers,
 };
-use harness::{make_request, parse_response};
+use http_body_util::BodyExt;
+use hyper::{HeaderMap, header::HeaderValue};
+use serde_json_bytes::serde_json;
+use std::time::Duration;
+use subgraph_mock::handle::graphql::SEED_HEADER;
 
 mod harness;
 
@@ -11,36 +14,159 @@ mod harness;
 async fn custom_ratios() -> anyhow::Result<()> {
     let (_, state) = harness::initialize(Some("custom_ratios.yaml"), None)?;
 
-    let mut responses = Vec::with_capacity(1000);
-    let mut requests: FuturesUnordered<_> = (0..1000)
-        .map(|_| async {
-            let response = make_request(1122833, state.clone(), None).await?;
-            ensure!(200 == response.status());
-            Ok(response)
-        })
-        .collect();
-
-    while let Some(response) = requests.next().await {
-        responses.push(response?);
+    let responses = make_requests(1122833, 1000, state, None).await?;
+    assert!(responses.iter().all(|response| response.status() == 200));
+
+    assert_ratio(
+        &responses,
+        |response| response.headers().contains_key("sometimes-present"),
+        0.5,
+        0.05,
+    );
+    assert_ratio(
+        &responses,
+        |response| {
+            serde_json::from_slice::<Response>(response.body())
+                .ok()
+                .and_then(|response| response.data?.user)
+                .is_some()
+        },
+        0.8,
+        0.05,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn deterministic() -> anyhow::Result<()> {
+    let response = async |state, rng_seed| {
+        let response = make_request(rng_seed, state, None).await?;
+        let header = response.headers().get("sometimes-present").cloned();
+        let body = response.into_body().collect().await?.to_bytes();
+        anyhow::Ok((header, body))
+    };
+
+    // Every request gets the same response from a restarted server, as does repeating it
+    let (_, state) = harness::initialize(Some("deterministic.yaml"), None)?;
+    let (_, restarted) = harness::initialize(Some("deterministic.yaml"), None)?;
+    let mut responses = Vec::new();
+    for rng_seed in 0..50 {
+        let expected = response(state.clone(), rng_seed).await?;
+        assert_eq!(response(state.clone(), rng_seed).await?, expected);
+        assert_eq!(response(restarted.clone(), rng_seed).await?, expected);
+        responses.push(expected);
     }
 
+    // While different requests still get different responses
     let header_count = responses
         .iter()
-        .filter_map(|response| response.headers().get("sometimes-present"))
+        .filter(|(header, _)| header.is_some())
         .count();
+    assert!((5..45).contains(&header_count), "{header_count}");
 
-    let non_null_count = stream::iter(responses)
-        .filter_map(async |response| {
-            parse_response(response)
-                .await
-                .ok()
-                .and_then(|query| query.user)
-        })
-        .count()
-        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn seed_override() -> anyhow::Result<()> {
+    let response = async |state, seed: Option<&'static str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(seed) = seed {
+            headers.insert(SEED_HEADER, HeaderValue::from_static(seed));
+        }
+        let response = send_request_with_headers(
+            "{ posts { id title author { name } } }".to_string(),
+            None,
+            state,
+            None,
+            true,
+            headers,
+        )
+        .await?;
+        anyhow::Ok(response.into_body().collect().await?.to_bytes())
+    };
+
+    // Seeded responses are generated fresh rather than served from the cache, and are the same on every server
+    let (_, state) = harness::initialize(Some("seed_override.yaml"), None)?;
+    let (_, restarted) = harness::initialize(Some("seed_override.yaml"), None)?;
+    let cached = response(state.clone(), None).await?;
+    let seeded = response(state.clone(), Some("42")).await?;
+    assert_ne!(seeded, cached);
+    assert_eq!(response(state.clone(), Some("42")).await?, seeded);
+    assert_eq!(response(restarted, Some("42")).await?, seeded);
+    assert_ne!(response(state.clone(), Some("43")).await?, seeded);
+    // Invalid seeds are ignored
+    assert_eq!(response(state, Some("not a seed")).await?, cached);
+
+    // The header is only honored when the config allows it
+    let (_, state) = harness::initialize(Some("seed_override_disallowed.yaml"), None)?;
+    assert_ne!(
+        response(state.clone(), Some("42")).await?,
+        response(state, Some("42")).await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn seeded_generated_requests() -> anyhow::Result<()> {
+    let response = async |state, rng_seed, response_seed| {
+        let response = make_seeded_request(rng_seed, response_seed, state, None).await?;
+        assert_eq!(200, response.status());
+        anyhow::Ok(response.into_body().collect().await?.to_bytes())
+    };
+
+    let (_, state) = harness::initialize(Some("seeded_no_cache.yaml"), None)?;
+    let (_, restarted) = harness::initialize(Some("seeded_no_cache.yaml"), None)?;
+    let mut unseeded_matches = 0;
+    for rng_seed in 0..20 {
+        // Without caching, the same query gets a different response every time
+        if response(state.clone(), rng_seed, None).await?
+            == response(state.clone(), rng_seed, None).await?
+        {
+            unseeded_matches += 1;
+        }
+
+        // Unless it is seeded, whatever the query
+        let seeded = response(state.clone(), rng_seed, Some(7)).await?;
+        assert_eq!(response(state.clone(), rng_seed, Some(7)).await?, seeded);
+        assert_eq!(
+            response(restarted.clone(), rng_seed, Some(7)).await?,
+            seeded
+        );
+    }
+    // Some generated queries only select fields that can't vary much, like a single boolean
+    assert!(unseeded_matches < 10, "{unseeded_matches}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn seeded_timestamps() -> anyhow::Result<()> {
+    let response = async |state, seed: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(SEED_HEADER, HeaderValue::from_static(seed));
+        let response = send_request_with_headers(
+            "{ posts { id createdAt updatedAt } }".to_string(),
+            Some("timestamps".to_string()),
+            state,
+            None,
+            true,
+            headers,
+        )
+        .await?;
+        assert_eq!(200, response.status());
+        anyhow::Ok(response.into_body().collect().await?.to_bytes())
+    };
 
-    assert_eq!("0.5", format!("{:.1}", header_count as f64 / 1000.0));
-    assert_eq!("0.8", format!("{:.1}", non_null_count as f64 / 1000.0));
+    // Timestamps are relative to a fixed time rather than to when the response is generated, so a seeded response
+    // is still the same once the clock has moved on
+    let (_, state) = harness::initialize(Some("seed_override.yaml"), Some("timestamps"))?;
+    let seeded = response(state.clone(), "42").await?;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(response(state.clone(), "42").await?, seeded);
+    assert_ne!(response(state, "43").await?, seeded);
 
     Ok(())
 }
@@ -0,0 +1,77 @@
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode, body::Bytes};
+use serde_json_bytes::{Value, serde_json};
+use std::sync::Arc;
+use subgraph_mock::{handle::handle_request, state::State};
+
+mod harness;
+
+async fn cache_stats(state: Arc<State>) -> anyhow::Result<Value> {
+    let req = Request::get("/debug/cache").body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(serde_json::from_slice(
+        &resp.into_body().collect().await?.to_bytes(),
+    )?)
+}
+
+/// The caches are global, so this is the only test in this file to keep other requests from skewing the stats or
+/// being flushed
+#[tokio::test]
+async fn cache_statistics() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+    let before = cache_stats(state.clone()).await?;
+
+    for _ in 0..3 {
+        let resp = harness::send_request(
+            "{ users { id name } }".to_string(),
+            None,
+            state.clone(),
+            None,
+            true,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let after = cache_stats(state.clone()).await?;
+    let delta = |cache: &str, stat: &str| {
+        after[cache][stat].as_u64().unwrap() - before[cache][stat].as_u64().unwrap()
+    };
+
+    // The first request generates a response, the others are served from the cache
+    assert_eq!(delta("responses", "misses"), 1);
+    assert_eq!(delta("responses", "hits"), 2);
+    assert_eq!(delta("responses", "size"), 1);
+    // Only the response that was generated needed a validated document
+    assert_eq!(delta("documents", "misses"), 1);
+    assert_eq!(delta("documents", "size"), 1);
+
+    // Flushing empties both caches, so the next response has to be generated again
+    let req = Request::post("/debug/flush-cache").body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state.clone()).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let flushed: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+    assert_eq!(flushed["flushed"]["responses"], after["responses"]["size"]);
+
+    let flushed = cache_stats(state.clone()).await?;
+    assert_eq!(flushed["documents"]["size"], 0);
+    assert_eq!(flushed["responses"]["size"], 0);
+
+    harness::send_request(
+        "{ users { id name } }".to_string(),
+        None,
+        state.clone(),
+        None,
+        true,
+    )
+    .await?;
+    let stats = cache_stats(state).await?;
+    assert_eq!(
+        stats["responses"]["misses"].as_u64().unwrap(),
+        after["responses"]["misses"].as_u64().unwrap() + 1
+    );
+
+    Ok(())
+}
@@ -1,8 +1,16 @@
-use futures::{
-    StreamExt,
-    stream::{self, FuturesUnordered},
+use futures::{StreamExt, stream::FuturesUnordered};
+use harness::{
+    Response, assert_ratio, make_request, parse_response_with_errors, send_request,
+    send_request_with_headers, send_requests,
 };
-use harness::{Response, make_request, parse_response_with_errors};
+use http_body_util::BodyExt;
+use hyper::{
+    HeaderMap,
+    header::{HeaderName, HeaderValue},
+};
+use serde_json_bytes::{Value, serde_json};
+use subgraph_mock::handle::graphql::{FORCE_ERROR_HEADER, FORCE_STATUS_HEADER};
+use tokio::time::{Duration, Instant, sleep};
 
 mod harness;
 
@@ -10,49 +18,365 @@ mod harness;
 async fn error_ratios() -> anyhow::Result<()> {
     let (_, state) = harness::initialize(Some("error_ratios.yaml"), None)?;
 
-    let mut responses = Vec::with_capacity(4000);
-    let mut requests: FuturesUnordered<_> = (0..4000)
-        .map(|_| async { make_request(72, state.clone(), None).await })
+    // Field errors only fail nullable top-level fields, so the query needs one
+    let query = r#"{ user(id: "1") { id name } posts { id } }"#;
+    let responses = send_requests(query, 4000, state, None, true).await?;
+
+    // 50% of our requests should have HTTP errors
+    assert_ratio(
+        &responses,
+        |response| !response.status().is_success(),
+        0.5,
+        0.05,
+    );
+
+    let graphql_responses: Vec<Response> = responses
+        .iter()
+        .filter(|response| response.status().is_success())
+        .filter_map(|response| serde_json::from_slice(response.body()).ok())
         .collect();
 
-    while let Some(response) = requests.next().await {
-        responses.push(response?);
+    // 50% of our remaining responses should have GraphQL response errors
+    assert_ratio(
+        &graphql_responses,
+        |response| response.data.is_none(),
+        0.5,
+        0.05,
+    );
+
+    // 50% of the requests with no response errors should have field-level errors
+    assert_ratio(
+        graphql_responses
+            .iter()
+            .filter(|response| response.data.is_some()),
+        |response| !response.errors.is_empty(),
+        0.5,
+        0.05,
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn retry_after() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("retry_after.yaml"), None)?;
+
+    let mut saw_unavailable = false;
+    for _ in 0..100 {
+        let response = make_request(72, state.clone(), None).await?;
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|header| header.to_str().ok());
+
+        if response.status() == 503 {
+            saw_unavailable = true;
+            assert_eq!(retry_after, Some("30"));
+        } else {
+            // Only server errors are injected, so there are no 429s
+            assert!(response.status().is_server_error());
+            assert_eq!(retry_after, None);
+        }
     }
 
-    let (successes, failures): (Vec<_>, Vec<_>) = responses
-        .into_iter()
-        .partition(|response| response.status().is_success());
+    assert!(saw_unavailable);
 
-    // 50% of our requests should have HTTP errors
-    assert_eq!("0.5", format!("{:.1}", failures.len() as f64 / 4000.0));
+    Ok(())
+}
 
-    let graphql_responses: Vec<Response> = stream::iter(successes.into_iter())
-        .filter_map(async |response| parse_response_with_errors(response).await.ok())
-        .collect()
-        .await;
+#[tokio::test]
+async fn max_depth() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("max_depth.yaml"), None)?;
 
-    let (no_response_errors, response_errors): (Vec<_>, Vec<_>) = graphql_responses
-        .into_iter()
-        .partition(|response| response.data.is_some());
+    let response = send_request(
+        "{ posts { id } }".to_string(),
+        None,
+        state.clone(),
+        None,
+        true,
+    )
+    .await?;
+    assert_eq!(response.status(), 200);
 
-    // 50% of our remaining responses should have GraphQL response errors
+    let response = send_request(
+        "{ posts { author { posts { id } } } }".to_string(),
+        None,
+        state,
+        None,
+        false,
+    )
+    .await?;
+    assert_eq!(response.status(), 400);
+
+    let response = parse_response_with_errors(response).await?;
+    assert!(response.data.is_none());
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0].message, "Query depth limit exceeded");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn forced_errors() -> anyhow::Result<()> {
+    let forced_status = HeaderMap::from_iter([(
+        HeaderName::from_static(FORCE_STATUS_HEADER),
+        HeaderValue::from_static("503"),
+    )]);
+    let forced_error = HeaderMap::from_iter([(
+        HeaderName::from_static(FORCE_ERROR_HEADER),
+        HeaderValue::from_static("Forced by test"),
+    )]);
+    let query = "{ posts { id } }";
+
+    // Overrides are ignored unless the config allows them
+    let (_, state) = harness::initialize(None, None)?;
+    for headers in [forced_status.clone(), forced_error.clone()] {
+        let response =
+            send_request_with_headers(query.to_string(), None, state.clone(), None, true, headers)
+                .await?;
+        assert_eq!(response.status(), 200);
+        assert!(parse_response_with_errors(response).await?.data.is_some());
+    }
+
+    let (_, state) = harness::initialize(Some("error_override.yaml"), None)?;
+    let response = send_request_with_headers(
+        query.to_string(),
+        None,
+        state.clone(),
+        None,
+        false,
+        forced_status,
+    )
+    .await?;
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "30");
+
+    // Forced rate limiting responses say when to retry too, unlike other errors
+    for (status, retry_after) in [("429", Some("30")), ("500", None)] {
+        let headers = HeaderMap::from_iter([(
+            HeaderName::from_static(FORCE_STATUS_HEADER),
+            HeaderValue::from_static(status),
+        )]);
+        let response =
+            send_request_with_headers(query.to_string(), None, state.clone(), None, false, headers)
+                .await?;
+        assert_eq!(response.status().as_str(), status);
+        assert_eq!(
+            response
+                .headers()
+                .get("retry-after")
+                .and_then(|header| header.to_str().ok()),
+            retry_after
+        );
+    }
+
+    let response =
+        send_request_with_headers(query.to_string(), None, state, None, false, forced_error)
+            .await?;
+    assert_eq!(response.status(), 200);
+    let response = parse_response_with_errors(response).await?;
+    assert!(response.data.is_none());
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0].message, "Forced by test");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn operation_lists() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("operation_lists.yaml"), None)?;
+    let strict = Some("strict".to_string());
+
+    for (query, subgraph_name, allowed) in [
+        ("query GetPosts { posts { id } }", &None, true),
+        ("query GetUsers { users { id } }", &None, true),
+        ("query DeleteEverything { posts { id } }", &None, false),
+        ("query GetPosts { posts { id } }", &strict, true),
+        ("query GetUsers { users { id } }", &strict, false),
+        ("{ users { id } }", &strict, true),
+        // Denying takes precedence over allowing
+        ("query DeleteEverything { posts { id } }", &strict, false),
+    ] {
+        let response =
+            send_request(query.to_string(), None, state.clone(), subgraph_name, false).await?;
+        assert_eq!(response.status(), 200);
+        let response = parse_response_with_errors(response).await?;
+        if allowed {
+            assert!(response.errors.is_empty(), "{query} should be allowed");
+        } else {
+            assert!(response.data.is_none(), "{query} should be denied");
+            assert_eq!(response.errors[0].message, "Operation is not allowed");
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn malformed_responses() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("malformed_responses.yaml"), None)?;
+
+    for subgraph_name in [None, Some("data_string"), Some("not_json"), Some("empty")] {
+        let response = send_request(
+            "{ posts { id } }".to_string(),
+            None,
+            state.clone(),
+            subgraph_name.map(str::to_string),
+            false,
+        )
+        .await?;
+        assert_eq!(response.status(), 200);
+
+        let body = response.into_body().collect().await?.to_bytes();
+        let parsed = serde_json::from_slice::<Value>(&body);
+        match subgraph_name {
+            Some("data_string") => assert!(parsed?["data"].is_string()),
+            Some("empty") => assert!(body.is_empty()),
+            _ => assert!(parsed.is_err(), "{body:?} should not be valid JSON"),
+        }
+    }
+
+    // Validation errors keep their status and body
+    let response = send_request(
+        "{ posts { id } } invalid".to_string(),
+        None,
+        state.clone(),
+        None,
+        false,
+    )
+    .await?;
+    assert_eq!(response.status(), 400);
+    let body: Value = serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+    assert!(body["errors"].is_array());
+
+    // As do response overrides
+    let response = send_request(
+        "query Overridden { posts { id } }".to_string(),
+        None,
+        state.clone(),
+        None,
+        false,
+    )
+    .await?;
+    assert_eq!(response.status(), 200);
+    let body: Value = serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+    assert_eq!(body["data"]["posts"][0]["id"], "1");
+
+    // Including those for anonymous operations, which are keyed by the hash of their query
+    let response =
+        send_request("{ posts { title } }".to_string(), None, state, None, false).await?;
+    assert_eq!(response.status(), 200);
+    let body: Value = serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+    assert_eq!(body["data"]["posts"][0]["title"], "Anonymous");
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn hang() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("hang.yaml"), None)?;
+    let query = "{ posts { id } }";
+
+    // Hanging requests are responded to as usual once the hang is over
+    let start = Instant::now();
+    let response = send_request(query.to_string(), None, state.clone(), None, true).await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(start.elapsed(), Duration::from_secs(30));
+
+    // Without a duration, requests hang until the server shuts down
+    let request = tokio::spawn(send_request(
+        query.to_string(),
+        None,
+        state.clone(),
+        Some("forever".to_string()),
+        false,
+    ));
+    sleep(Duration::from_secs(3600)).await;
+    assert!(!request.is_finished());
+
+    state.shut_down();
+    assert_eq!(request.await??.status(), 503);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrency_limit() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("concurrency_limit.yaml"), None)?;
+    let query = "{ posts { id } }";
+    let send_requests = |count| {
+        (0..count)
+            .map(|_| {
+                let state = state.clone();
+                async move {
+                    let start = Instant::now();
+                    let response =
+                        send_request(query.to_string(), None, state, None, false).await?;
+                    anyhow::Ok((response.status().as_u16(), start.elapsed()))
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+    };
+
+    // Requests over the limit wait for an earlier one to finish, including its latency
+    let mut responses = send_requests(3)
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    responses.sort_by_key(|(_, elapsed)| *elapsed);
     assert_eq!(
-        "0.5",
-        format!("{:.1}", response_errors.len() as f64 / 2000.0)
+        responses,
+        [
+            (200, Duration::from_secs(1)),
+            (200, Duration::from_secs(1)),
+            (200, Duration::from_secs(2)),
+        ]
     );
 
-    // 50% of the requests with no response errors should have field-level errors
+    // Or are turned away immediately
+    state.config.write().await.reject_excess_requests = true;
+    let mut responses = send_requests(3)
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    responses.sort_by_key(|(_, elapsed)| *elapsed);
     assert_eq!(
-        "0.5",
-        format!(
-            "{:.1}",
-            no_response_errors
-                .into_iter()
-                .filter(|response| !response.errors.is_empty())
-                .count() as f64
-                / 1000.0
-        )
+        responses,
+        [
+            (503, Duration::ZERO),
+            (200, Duration::from_secs(1)),
+            (200, Duration::from_secs(1)),
+        ]
     );
 
     Ok(())
 }
+
+#[tokio::test(start_paused = true)]
+async fn scheduled_outage() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("outage.yaml"), None)?;
+    let query = "{ posts { id } }";
+
+    // The subgraph is up for the first 8 seconds of every 10, then down for the remaining 2
+    for (advance, status, retry_after) in [
+        (0, 200, None),
+        (7900, 200, None),
+        (100, 503, Some("2")),
+        (1500, 503, Some("1")),
+        (500, 200, None),
+    ] {
+        tokio::time::advance(Duration::from_millis(advance)).await;
+        let response = send_request(query.to_string(), None, state.clone(), None, true).await?;
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response
+                .headers()
+                .get("retry-after")
+                .map(|header| header.to_str().unwrap()),
+            retry_after
+        );
+    }
+
+    Ok(())
+}
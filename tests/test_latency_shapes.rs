@@ -1,5 +1,10 @@
-use harness::{assert_is_saw, assert_is_square, assert_is_triangle};
-use tokio::time::Duration;
+use harness::{
+    assert_is_saw, assert_is_square, assert_is_triangle, make_request, make_seeded_request,
+    send_request, send_request_with_headers,
+};
+use hyper::{HeaderMap, header::HeaderValue};
+use subgraph_mock::{handle::LATENCY_OVERRIDE_HEADER, latency::SawDirection};
+use tokio::time::{Duration, Instant};
 
 mod harness;
 
@@ -12,7 +17,35 @@ async fn saw_wave() -> anyhow::Result<()> {
 
     // The configured latency generator is a saw wave with a base value of 10 ms, an amplitude of 20ms,
     // and a period of 10 seconds.
-    assert_is_saw(10, 20, Duration::from_secs(10), rng_seed, state, None).await
+    assert_is_saw(
+        10,
+        20,
+        Duration::from_secs(10),
+        SawDirection::Rising,
+        rng_seed,
+        state,
+        None,
+    )
+    .await
+}
+
+#[tokio::test(start_paused = true)]
+async fn falling_saw_wave() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("falling_saw_wave.yaml"), None)?;
+    let rng_seed = 12;
+
+    // The configured latency generator is a falling saw wave with a base value of 10 ms, an amplitude of 20ms,
+    // and a period of 10 seconds.
+    assert_is_saw(
+        10,
+        20,
+        Duration::from_secs(10),
+        SawDirection::Falling,
+        rng_seed,
+        state,
+        None,
+    )
+    .await
 }
 
 #[tokio::test(start_paused = true)]
@@ -34,3 +67,134 @@ async fn triangle_wave() -> anyhow::Result<()> {
     // and a period of 10 seconds.
     assert_is_triangle(0, 10, Duration::from_secs(10), rng_seed, state, None).await
 }
+
+#[tokio::test(start_paused = true)]
+async fn error_latency() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("error_latency.yaml"), None)?;
+
+    // Every request is answered with an HTTP error, which uses the error latency instead of the base latency
+    for (subgraph_name, expected) in [
+        (None, 2000),
+        (Some("fail_fast".to_string()), 50),
+        (Some("no_error_latency".to_string()), 0),
+    ] {
+        let start = Instant::now();
+        let response = make_request(12, state.clone(), subgraph_name).await?;
+        assert!(response.status().is_server_error());
+        assert_eq!(Duration::from_millis(expected), start.elapsed());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_failures() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("slow_failures.yaml"), None)?;
+
+    // Failures are picked before the latency, so only they wait for the error latency
+    let (mut failures, mut successes) = (0, 0);
+    for seed in 0..20 {
+        let start = Instant::now();
+        let response = make_request(seed, state.clone(), None).await?;
+        if response.status().is_server_error() {
+            failures += 1;
+            assert_eq!(Duration::from_secs(30), start.elapsed());
+        } else {
+            successes += 1;
+            assert_eq!(Duration::from_millis(10), start.elapsed());
+        }
+    }
+    assert!(failures > 0 && successes > 0);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn seeded_jitter() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("seeded_jitter.yaml"), None)?;
+    let latency = async |seed: Option<u64>| {
+        let start = Instant::now();
+        let response = make_seeded_request(3, seed, state.clone(), None).await?;
+        assert_eq!(200, response.status());
+        anyhow::Ok(start.elapsed())
+    };
+
+    // Jitter is drawn from the request's RNG, so seeded requests are delayed by the same latency every time
+    let seeded = latency(Some(7)).await?;
+    for _ in 0..5 {
+        assert_eq!(latency(Some(7)).await?, seeded);
+    }
+
+    // While unseeded requests still vary
+    let mut unseeded = Vec::new();
+    for _ in 0..5 {
+        unseeded.push(latency(None).await?);
+    }
+    assert!(unseeded.iter().any(|latency| *latency != unseeded[0]));
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn latency_override() -> anyhow::Result<()> {
+    let mut headers = HeaderMap::new();
+    headers.insert(LATENCY_OVERRIDE_HEADER, HeaderValue::from_static("500"));
+
+    for (config, expected) in [("latency_override.yaml", 500), ("saw_wave.yaml", 10)] {
+        let (_, state) = harness::initialize(Some(config), None)?;
+
+        // The header is only honored when the config allows it, otherwise the configured latency is used
+        let start = Instant::now();
+        let response = send_request_with_headers(
+            "{ posts { id } }".to_string(),
+            None,
+            state,
+            None,
+            true,
+            headers.clone(),
+        )
+        .await?;
+        assert_eq!(200, response.status());
+        assert_eq!(Duration::from_millis(expected), start.elapsed());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn field_latency() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("field_latency.yaml"), None)?;
+
+    for (query, expected) in [
+        ("{ posts { id } }", 10),
+        ("{ posts { title } }", 110),
+        // Fields are only counted once, including when they are selected through fragments
+        ("{ posts { title ... on Post { title } } }", 110),
+        (
+            "query { user(id: 1) { ...UserFields } } fragment UserFields on User { name posts { title } }",
+            360,
+        ),
+    ] {
+        let start = Instant::now();
+        let response = send_request(query.to_string(), None, state.clone(), None, true).await?;
+        assert_eq!(200, response.status());
+        assert_eq!(Duration::from_millis(expected), start.elapsed(), "{query}");
+    }
+
+    // A requested latency replaces the field latency along with the rest
+    let mut headers = HeaderMap::new();
+    headers.insert(LATENCY_OVERRIDE_HEADER, HeaderValue::from_static("500"));
+    let start = Instant::now();
+    send_request_with_headers(
+        "{ posts { title } }".to_string(),
+        None,
+        state,
+        None,
+        true,
+        headers,
+    )
+    .await?;
+    assert_eq!(Duration::from_millis(500), start.elapsed());
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use http_body_util::BodyExt;
+use serde_json_bytes::{Value, json, serde_json};
+use std::{env, fs, path::PathBuf, sync::Arc};
+use subgraph_mock::state::{Config, State};
+use tokio::time::{Duration, Instant, sleep};
+
+mod harness;
+
+#[tokio::test]
+async fn requests_are_recorded() -> anyhow::Result<()> {
+    let path = env::temp_dir().join(format!("subgraph-mock-record-{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let (_, config) = Config::parse_yaml(serde_yaml::from_str(&format!(
+        "record:\n  path: {}",
+        path.display()
+    ))?)?;
+    let schema_path = PathBuf::from(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/schema.graphql"
+    ));
+    let state = Arc::new(State::new(config, vec![schema_path])?);
+
+    let mut responses = Vec::new();
+    for (query, subgraph_name) in [
+        ("{ posts { id } }", None),
+        ("{ users { id } }", Some("users".to_string())),
+    ] {
+        let resp =
+            harness::send_request(query.to_string(), None, state.clone(), subgraph_name, false)
+                .await?;
+        // The response is passed on unchanged after being recorded
+        let body: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+        assert!(body["data"].is_object());
+        responses.push(body);
+    }
+
+    // Records are written in the background
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let records = loop {
+        let records = fs::read_to_string(&path).unwrap_or_default();
+        if records.lines().count() == 2 {
+            break records;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "requests were not recorded in time"
+        );
+        sleep(Duration::from_millis(50)).await;
+    };
+
+    let records: Vec<Value> = records
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(records[0]["subgraph"], Value::Null);
+    assert_eq!(records[0]["request"]["query"], json!("{ posts { id } }"));
+    assert_eq!(records[0]["status"], json!(200));
+    assert_eq!(records[0]["response"], responses[0]);
+    assert_eq!(records[1]["subgraph"], json!("users"));
+    assert_eq!(records[1]["response"], responses[1]);
+
+    fs::remove_file(path)?;
+    Ok(())
+}
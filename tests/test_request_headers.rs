@@ -0,0 +1,410 @@
+use harness::{send_request, send_request_with_headers};
+use http_body_util::{BodyExt, Full};
+use hyper::{HeaderMap, Request, body::Bytes, header::HeaderValue};
+use serde_json_bytes::{Value, json, serde_json};
+use std::time::Duration;
+use subgraph_mock::handle::{REQUEST_ID_HEADER, handle_request};
+
+mod harness;
+
+const QUERY: &str = "{ posts { id } }";
+
+#[tokio::test]
+async fn echo_request_headers() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("echo_headers.yaml"), None)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-present", HeaderValue::from_static("first"));
+    headers.append("x-present", HeaderValue::from_static("second"));
+    headers.insert("x-ignored", HeaderValue::from_static("not configured"));
+
+    let response =
+        send_request_with_headers(QUERY.to_string(), None, state, None, true, headers).await?;
+    assert_eq!(200, response.status());
+
+    let echoed: Vec<_> = response
+        .headers()
+        .get_all("x-echo-x-present")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    assert_eq!(echoed, vec!["first", "second"]);
+
+    assert!(!response.headers().contains_key("x-echo-x-missing"));
+    assert!(!response.headers().contains_key("x-echo-x-ignored"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_id() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+
+    // A request's own id is echoed back
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_static("router-request-1"),
+    );
+    let response =
+        send_request_with_headers(QUERY.to_string(), None, state.clone(), None, true, headers)
+            .await?;
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "router-request-1");
+
+    // Requests without one are each given a UUID
+    let mut generated = Vec::new();
+    for _ in 0..2 {
+        let response = send_request(QUERY.to_string(), None, state.clone(), None, true).await?;
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str()?.to_string();
+        assert!(uuid::Uuid::parse_str(&request_id).is_ok(), "{request_id}");
+        generated.push(request_id);
+    }
+    assert_ne!(generated[0], generated[1]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn header_templates() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("header_templates.yaml"), None)?;
+    let request = |path: &str, operation_name: Option<&str>| {
+        let body =
+            json!({ "query": "query Posts { posts { id } }", "operationName": operation_name });
+        Request::post(path)
+            .header(REQUEST_ID_HEADER, "router-request-1")
+            .body(Full::new(Bytes::from(serde_json::to_vec(&body)?)))
+            .map_err(anyhow::Error::from)
+    };
+
+    let response = handle_request(request("/posts", Some("Posts"))?, state.clone()).await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-operation"], "Posts");
+    assert_eq!(
+        response.headers()["x-served-by"],
+        "mock-posts/router-request-1"
+    );
+
+    // Variables that a request doesn't have are left empty
+    let response = handle_request(request("/", None)?, state).await?;
+    assert_eq!(response.headers()["x-operation"], "");
+    assert_eq!(response.headers()["x-served-by"], "mock-/router-request-1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn header_conditions() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("header_conditions.yaml"), None)?;
+
+    let response = send_request_with_headers(
+        QUERY.to_string(),
+        None,
+        state.clone(),
+        None,
+        true,
+        HeaderMap::new(),
+    )
+    .await?;
+    assert_eq!(401, response.status());
+
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", HeaderValue::from_static("Bearer token"));
+    headers.insert("x-tenant", HeaderValue::from_static("other"));
+    let response =
+        send_request_with_headers(QUERY.to_string(), None, state.clone(), None, true, headers)
+            .await?;
+    assert_eq!(403, response.status());
+
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", HeaderValue::from_static("Bearer token"));
+    headers.insert("x-tenant", HeaderValue::from_static("mock"));
+    let response =
+        send_request_with_headers(QUERY.to_string(), None, state, None, true, headers).await?;
+    assert_eq!(200, response.status());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stream() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("stream.yaml"), None)?;
+    let query = r#"{ posts @stream(initialCount: 1, label: "posts") { id author { posts @stream { id } } } }"#;
+    let multipart = || {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "accept",
+            HeaderValue::from_static("multipart/mixed;deferSpec=20220824, application/json"),
+        );
+        headers
+    };
+
+    let response = send_request_with_headers(
+        query.to_string(),
+        None,
+        state.clone(),
+        None,
+        false,
+        multipart(),
+    )
+    .await?;
+    assert_eq!(
+        response.headers()["content-type"],
+        "multipart/mixed;boundary=\"graphql\";deferSpec=20220824"
+    );
+    let body = response.into_body().collect().await?.to_bytes();
+    let body = std::str::from_utf8(&body)?;
+    let payloads = body
+        .strip_suffix("\r\n--graphql--\r\n")
+        .unwrap()
+        .split("\r\n--graphql\r\ncontent-type: application/json\r\n\r\n")
+        .skip(1)
+        .map(serde_json::from_str::<Value>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The initial payload only has the first post, whose author's posts are all streamed
+    let initial = &payloads[0];
+    assert_eq!(initial["hasNext"], true);
+    let posts = initial["data"]["posts"].as_array().unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["author"]["posts"], json!([]));
+    // Followed by the rest of the posts, with their authors' posts whole, then the first post's author's posts
+    assert_eq!(payloads.len(), 1 + 3 + 4);
+    for (index, payload) in payloads[1..4].iter().enumerate() {
+        let incremental = &payload["incremental"][0];
+        assert_eq!(incremental["path"], json!(["posts", index + 1]));
+        assert_eq!(incremental["label"], "posts");
+        assert_eq!(
+            incremental["items"][0]["author"]["posts"]
+                .as_array()
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+    for (index, payload) in payloads[4..].iter().enumerate() {
+        let incremental = &payload["incremental"][0];
+        assert_eq!(
+            incremental["path"],
+            json!(["posts", 0, "author", "posts", index])
+        );
+        assert!(incremental.get("label").is_none());
+        assert_eq!(payload["hasNext"], index < 3);
+    }
+
+    // Clients that don't accept incremental responses get the whole response at once, as do streams that are off
+    for (query, headers) in [
+        (query, HeaderMap::new()),
+        ("{ posts @stream(if: false) { id } }", multipart()),
+    ] {
+        let response =
+            send_request_with_headers(query.to_string(), None, state.clone(), None, false, headers)
+                .await?;
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: Value =
+            serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+        assert_eq!(body["data"]["posts"].as_array().unwrap().len(), 4);
+        assert!(body.get("hasNext").is_none());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn conditional_requests() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+    let request = async |if_none_match: Option<&str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = if_none_match {
+            headers.insert("if-none-match", HeaderValue::from_str(etag)?);
+        }
+        send_request_with_headers(QUERY.to_string(), None, state.clone(), None, false, headers)
+            .await
+    };
+
+    let response = request(None).await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["age"], "0");
+    let etag = response.headers()["etag"].to_str()?.to_string();
+
+    // The age of a cached response grows, while its entity tag stays the same
+    tokio::time::advance(Duration::from_secs(5)).await;
+    let response = request(None).await?;
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    let age: u64 = response.headers()["age"].to_str()?.parse()?;
+    assert!(age >= 5, "{age}");
+
+    for if_none_match in [
+        etag.clone(),
+        format!("W/{etag}"),
+        format!("\"other\", {etag}"),
+    ] {
+        let response = request(Some(&if_none_match)).await?;
+        assert_eq!(response.status(), 304, "{if_none_match}");
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        assert!(response.into_body().collect().await?.to_bytes().is_empty());
+    }
+    let response = request(Some("\"other\"")).await?;
+    assert_eq!(response.status(), 200);
+
+    // Only cached responses can be revalidated
+    let (_, state) = harness::initialize(Some("default_no_cache.yaml"), None)?;
+    let response = send_request_with_headers(
+        QUERY.to_string(),
+        None,
+        state,
+        None,
+        false,
+        HeaderMap::new(),
+    )
+    .await?;
+    assert!(response.headers().get("etag").is_none());
+    assert!(response.headers().get("age").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn content_type() -> anyhow::Result<()> {
+    // Responses are plain JSON unless configured otherwise
+    let (_, state) = harness::initialize(None, None)?;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "accept",
+        HeaderValue::from_static("application/graphql-response+json"),
+    );
+    let response =
+        send_request_with_headers(QUERY.to_string(), None, state, None, true, headers).await?;
+    assert_eq!(response.headers()["content-type"], "application/json");
+
+    let (_, state) = harness::initialize(Some("content_type.yaml"), None)?;
+    for (accept, subgraph_name, expected) in [
+        (None, None, "application/json"),
+        (Some("application/json"), None, "application/json"),
+        (
+            Some("application/json;q=0.9, application/graphql-response+json"),
+            None,
+            "application/graphql-response+json",
+        ),
+        (
+            Some("application/graphql-response+json;q=0, application/json"),
+            None,
+            "application/json",
+        ),
+        (
+            Some("application/json"),
+            Some("modern".to_string()),
+            "application/graphql-response+json",
+        ),
+    ] {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert("accept", HeaderValue::from_static(accept));
+        }
+        let response = send_request_with_headers(
+            QUERY.to_string(),
+            None,
+            state.clone(),
+            subgraph_name,
+            true,
+            headers,
+        )
+        .await?;
+        assert_eq!(
+            response.headers()["content-type"],
+            expected,
+            "accepting {accept:?}"
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_error_status() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("content_type.yaml"), None)?;
+
+    for (query, accept, expected) in [
+        // Request errors are only 400s under the GraphQL-over-HTTP media type
+        ("{ posts { nope } }", None, 200),
+        (
+            "{ posts { nope } }",
+            Some("application/graphql-response+json"),
+            400,
+        ),
+        ("{ posts { id } }", None, 200),
+        (
+            "{ posts { id } }",
+            Some("application/graphql-response+json"),
+            200,
+        ),
+    ] {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert("accept", HeaderValue::from_static(accept));
+        }
+        let response =
+            send_request_with_headers(query.to_string(), None, state.clone(), None, false, headers)
+                .await?;
+        assert_eq!(response.status(), expected, "{query} accepting {accept:?}");
+    }
+
+    // Unless the status of validation errors is configured, which leaves other requests alone
+    for (subgraph_name, query, expected) in [
+        ("lenient", "{ posts { nope } }", 200),
+        ("lenient", "{ posts { id } }", 200),
+        ("strict", "{ posts { nope } }", 400),
+        ("strict", "{ posts { id } }", 200),
+    ] {
+        let response = send_request(
+            query.to_string(),
+            None,
+            state.clone(),
+            Some(subgraph_name.to_string()),
+            false,
+        )
+        .await?;
+        assert_eq!(response.status(), expected, "{query} to {subgraph_name}");
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+        assert_eq!(body["errors"].is_array(), query.contains("nope"), "{body}");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("profiles.yaml"), None)?;
+    let query = |profile: Option<&'static str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(profile) = profile {
+            headers.insert("x-mock-profile", HeaderValue::from_static(profile));
+        }
+        let state = state.clone();
+        async move {
+            let response =
+                send_request_with_headers(QUERY.to_string(), None, state, None, false, headers)
+                    .await?;
+            let body = response.into_body().collect().await?.to_bytes();
+            anyhow::Ok(serde_json::from_slice::<serde_json::Value>(&body)?)
+        }
+    };
+
+    assert_eq!(
+        query(None).await?["data"]["posts"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    // Profiles only override what they set, keeping the rest of the base config
+    let empty = query(Some("empty")).await?;
+    assert_eq!(empty["data"]["posts"], serde_json::json!([]));
+    let failed = query(Some("error-prone")).await?;
+    assert!(failed["errors"].is_array(), "{failed}");
+    // Unknown profiles fall back to the default config
+    let unknown = query(Some("unknown")).await?;
+    assert_eq!(unknown["data"]["posts"].as_array().unwrap().len(), 2);
+
+    Ok(())
+}
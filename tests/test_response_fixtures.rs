@@ -0,0 +1,64 @@
+use http_body_util::BodyExt;
+use hyper::StatusCode;
+use serde_json_bytes::{Value, json, serde_json};
+use std::{env, fs, path::PathBuf, sync::Arc};
+use subgraph_mock::state::{Config, State};
+use tokio::time::{Duration, Instant, sleep};
+
+mod harness;
+
+async fn response_data(state: Arc<State>, query: &str) -> anyhow::Result<Value> {
+    let resp = harness::send_request(query.to_string(), None, state, None, false).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+    Ok(body["data"].clone())
+}
+
+#[tokio::test]
+async fn fixtures_are_served_and_reloaded() -> anyhow::Result<()> {
+    let dir = env::temp_dir().join(format!("subgraph-mock-fixtures-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("GetPosts.json"),
+        r#"{ "data": { "posts": [{ "id": "canned" }] } }"#,
+    )?;
+    // Only JSON files are fixtures
+    fs::write(dir.join("GetUsers.txt"), "not a fixture")?;
+
+    let (_, config) = Config::parse_yaml(serde_yaml::from_str(&format!(
+        "response_fixtures: {}\nschema_watch:\n  debounce: 10ms",
+        dir.display()
+    ))?)?;
+    let schema_path = PathBuf::from(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/schema.graphql"
+    ));
+    let state = Arc::new(State::new(config, vec![schema_path])?);
+
+    assert_eq!(
+        response_data(state.clone(), "query GetPosts { posts { id } }").await?,
+        json!({ "posts": [{ "id": "canned" }] })
+    );
+    // Other operations are still generated
+    let generated = response_data(state.clone(), "query GetUsers { users { id } }").await?;
+    assert!(generated["users"].is_array());
+
+    fs::write(
+        dir.join("GetUsers.json"),
+        r#"{ "data": { "users": [{ "id": "reloaded" }] } }"#,
+    )?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while response_data(state.clone(), "query GetUsers { users { id } }").await?
+        != json!({ "users": [{ "id": "reloaded" }] })
+    {
+        assert!(
+            Instant::now() < deadline,
+            "fixture was not reloaded in time"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -79,3 +79,28 @@ async fn custom_scalars() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn inverted_ranges_are_rejected() {
+    for (config_file_name, expected) in [
+        (
+            "inverted_int_range.yaml",
+            "scalars.Int: min (100) is greater than max (10)",
+        ),
+        (
+            "inverted_float_range.yaml",
+            "scalars.Float: min (1) is greater than max (-1)",
+        ),
+        // Subgraph overrides and weighted generators are checked too
+        (
+            "inverted_string_length.yaml",
+            "scalars.String: min_len (20) is greater than max_len (10)",
+        ),
+    ] {
+        let err = harness::args(Some(config_file_name), None)
+            .init()
+            .err()
+            .unwrap_or_else(|| panic!("{config_file_name} should fail to load"));
+        assert_eq!(err.to_string(), expected);
+    }
+}
@@ -0,0 +1,109 @@
+use clap::Parser;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, StatusCode, body::Bytes};
+use serde_json_bytes::{Value, json, serde_json};
+use std::{fs, sync::Arc};
+use subgraph_mock::{
+    Args,
+    handle::handle_request,
+    state::{Config, State},
+};
+use tokio::time::{Duration, Instant, sleep};
+
+mod harness;
+
+async fn status(state: Arc<State>) -> anyhow::Result<Value> {
+    let req = Request::builder()
+        .method("GET")
+        .uri("/status")
+        .body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(serde_json::from_slice(
+        &resp.into_body().collect().await?.to_bytes(),
+    )?)
+}
+
+/// Polls the status endpoint until the last reload satisfies `predicate`, since the schema watcher polls the
+/// filesystem in the background
+async fn wait_for_reload(
+    state: Arc<State>,
+    predicate: impl Fn(&Value) -> bool,
+) -> anyhow::Result<Value> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let last_reload = status(state.clone()).await?["schema"]["last_reload"].clone();
+        if predicate(&last_reload) {
+            return Ok(last_reload);
+        }
+        if Instant::now() > deadline {
+            anyhow::bail!("schema was not reloaded in time, last reload: {last_reload}");
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn status_reports_failed_reloads() -> anyhow::Result<()> {
+    let path = harness::schema_copy("status_reports_failed_reloads")?;
+    let state = Arc::new(State::new(Config::default(), vec![path.clone()])?);
+
+    assert_eq!(
+        status(state.clone()).await?,
+        json!({ "schema": { "last_reload": null } })
+    );
+
+    fs::write(&path, "type Query { broken: DoesNotExist }")?;
+    let last_reload = wait_for_reload(state.clone(), |reload| !reload.is_null()).await?;
+    assert_eq!(last_reload["success"], json!(false));
+    assert!(
+        last_reload["error"]
+            .as_str()
+            .unwrap()
+            .contains("DoesNotExist")
+    );
+    assert!(last_reload["timestamp"].is_string());
+
+    // The last good schema is still served
+    let resp = harness::send_request(
+        "{ posts { id } }".to_string(),
+        None,
+        state.clone(),
+        None,
+        true,
+    )
+    .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    fs::write(&path, include_str!("data/schema.graphql"))?;
+    let last_reload = wait_for_reload(state, |reload| reload["success"] == json!(true)).await?;
+    assert_eq!(last_reload["error"], Value::Null);
+
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn watching_can_be_disabled() -> anyhow::Result<()> {
+    let path = harness::schema_copy("watching_can_be_disabled")?;
+    let args = Args::try_parse_from([
+        "subgraph-mock",
+        "--schema",
+        path.to_str().unwrap(),
+        "--watch=false",
+    ])?;
+    let (_, state) = args.init()?;
+    let state = Arc::new(state);
+
+    fs::write(&path, "type Query { broken: DoesNotExist }")?;
+    // Long enough for the watcher to have polled the file and the debounce window to have passed, if it existed
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        status(state.clone()).await?,
+        json!({ "schema": { "last_reload": null } })
+    );
+
+    fs::remove_file(path)?;
+    Ok(())
+}
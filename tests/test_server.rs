@@ -0,0 +1,613 @@
+use clap::Parser;
+use harness::send_raw_request;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{
+    Request, StatusCode,
+    body::Bytes,
+    client::conn::{http1, http2},
+    header::ALLOW,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use serde_json_bytes::{Value, json, serde_json};
+use std::{future, net::SocketAddr, sync::Arc};
+use subgraph_mock::{
+    Args, Cli, Command, ConnectionOptions, SubgraphMockError,
+    handle::{graphql::ResponseGenerationConfig, handle_request},
+    serve, serve_connections,
+    state::{Config, FederatedSchema, State},
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    time::{Duration, Instant, timeout},
+};
+
+mod harness;
+
+#[tokio::test]
+async fn graceful_shutdown() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(serve_connections(
+        listener,
+        Default::default(),
+        state,
+        async {
+            let _ = shutdown_rx.await;
+        },
+    ));
+
+    // An idle keep-alive connection should not prevent the server from shutting down
+    let mut stream = TcpStream::connect(addr).await?;
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let (status, _) = send_raw_request(&mut stream, "/", &body).await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    shutdown_tx.send(()).unwrap();
+    timeout(Duration::from_secs(5), server).await???;
+
+    assert!(TcpStream::connect(addr).await.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn bind_address() -> anyhow::Result<()> {
+    let (server, _) = harness::args(None, None).init()?;
+    assert_eq!(server.listen_address, "0.0.0.0:8080".parse()?);
+
+    let (server, _) = harness::args(Some("bind_address.yaml"), None).init()?;
+    assert_eq!(server.listen_address, "127.0.0.1:8043".parse()?);
+
+    // The command line flag takes precedence over the config file
+    let mut args = harness::args(Some("bind_address.yaml"), None);
+    args.bind = Some("::1".parse()?);
+    let (server, _) = args.init()?;
+    assert_eq!(server.listen_address, "[::1]:8043".parse()?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn flags_override_the_config_file() -> anyhow::Result<()> {
+    let pkg_root = env!("CARGO_MANIFEST_DIR");
+    let config = format!("{pkg_root}/tests/data/config/subgraph_override.yaml");
+    let schema = format!("{pkg_root}/tests/data/schema.graphql");
+    let args = Args::try_parse_from([
+        "subgraph-mock",
+        "--config",
+        &config,
+        "--schema",
+        &schema,
+        "--port=9000",
+        "--cache-responses=false",
+        "--null-ratio=1/3",
+    ])?;
+    let (server, state) = args.init()?;
+    assert_eq!(server.listen_address.port(), 9000);
+
+    // Including over the config file's subgraph overrides
+    let config = state.config.read().await;
+    assert!(!config.cache_responses);
+    assert_eq!(config.subgraph_overrides.response_generation.len(), 1);
+    assert!(
+        !config
+            .subgraph_overrides
+            .cache_responses
+            .values()
+            .any(|cache| *cache)
+    );
+    assert_eq!(config.response_generation.null_ratio, Some((1, 3)));
+    assert!(
+        config
+            .subgraph_overrides
+            .response_generation
+            .values()
+            .all(|rgen_cfg| rgen_cfg.null_ratio == Some((1, 3)))
+    );
+
+    for ratio in ["3/1", "1/0", "half"] {
+        let flag = format!("--null-ratio={ratio}");
+        assert!(Args::try_parse_from(["subgraph-mock", "--schema", &schema, &flag]).is_err());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn env_interpolation() -> anyhow::Result<()> {
+    let (server, state) = harness::args(Some("env_interpolation.yaml"), None).init()?;
+    assert_eq!(server.listen_address, "127.0.0.1:8044".parse()?);
+    assert_eq!(
+        state.config.read().await.instance_name.as_deref(),
+        Some(env!("CARGO_PKG_NAME"))
+    );
+
+    let err = harness::args(Some("env_interpolation_unset.yaml"), None)
+        .init()
+        .err()
+        .expect("the variable has no default");
+    assert!(
+        err.to_string().contains("SUBGRAPH_MOCK_UNSET_PORT"),
+        "{err}"
+    );
+
+    assert_eq!(
+        Config::interpolate_env("name: $${NOT_INTERPOLATED}")?,
+        "name: ${NOT_INTERPOLATED}"
+    );
+    // Comments can mention variables without them having to be set
+    assert_eq!(
+        Config::interpolate_env("# Set `${SUBGRAPH_MOCK_UNSET_PORT}`\nport: ${PORT:-8080}\n")?,
+        "# Set `${SUBGRAPH_MOCK_UNSET_PORT}`\nport: 8080\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_bind_address() {
+    let err = harness::args(Some("invalid_bind_address.yaml"), None)
+        .init()
+        .err()
+        .expect("a hostname is not a valid bind address");
+    assert!(err.to_string().contains("bind_address"), "{err}");
+}
+
+#[test]
+fn typed_errors() -> anyhow::Result<()> {
+    let err = FederatedSchema::parse(&["tests/data/missing.graphql".into()]).unwrap_err();
+    assert!(
+        matches!(&err, SubgraphMockError::Io { path, .. } if path.ends_with("missing.graphql")),
+        "{err}"
+    );
+
+    let err = FederatedSchema::parse_string("type Query {", "schema.graphql").unwrap_err();
+    assert!(matches!(err, SubgraphMockError::Parse(_)), "{err}");
+
+    let err =
+        FederatedSchema::parse_string("type Query { a: Missing }", "schema.graphql").unwrap_err();
+    assert!(matches!(err, SubgraphMockError::Validation(_)), "{err}");
+
+    let err = Config::parse_yaml(serde_yaml::from_str("bind_address: localhost")?).unwrap_err();
+    assert!(matches!(err, SubgraphMockError::Config(_)), "{err}");
+
+    let err = ResponseGenerationConfig::builder()
+        .depth_limit_status(1000)
+        .build()
+        .unwrap_err();
+    assert!(matches!(err, SubgraphMockError::Config(_)), "{err}");
+
+    Ok(())
+}
+
+#[test]
+fn validate_subcommand() -> anyhow::Result<()> {
+    let schema = format!("{}/tests/data/schema.graphql", env!("CARGO_MANIFEST_DIR"));
+
+    // Without a subcommand, the arguments are for serving as they always were
+    let cli = Cli::try_parse_from(["subgraph-mock", "--schema", &schema, "--print-schema"])?;
+    assert!(matches!(cli.command(), Command::Serve(args) if args.print_schema));
+    let cli = Cli::try_parse_from(["subgraph-mock", "serve", "--schema", &schema])?;
+    assert!(matches!(cli.command(), Command::Serve(args) if !args.print_schema));
+
+    let Command::Validate(args) =
+        Cli::try_parse_from(["subgraph-mock", "validate", "--schema", &schema])?.command()
+    else {
+        panic!("expected the validate command");
+    };
+    assert!(args.validate()?.types.contains_key("_Entity"));
+
+    let Command::Validate(args) = Cli::try_parse_from([
+        "subgraph-mock",
+        "validate",
+        "--schema",
+        "tests/data/missing.graphql",
+    ])?
+    .command() else {
+        panic!("expected the validate command");
+    };
+    assert!(matches!(args.validate(), Err(SubgraphMockError::Io { .. })));
+
+    assert!(Cli::try_parse_from(["subgraph-mock", "validate"]).is_err());
+    assert!(Cli::try_parse_from(["subgraph-mock", "--schema", &schema, "validate"]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gen_subcommand() -> anyhow::Result<()> {
+    let data = format!("{}/tests/data", env!("CARGO_MANIFEST_DIR"));
+    let generate_with = |query: &str, args: &[&str]| -> anyhow::Result<Value> {
+        let schema = format!("{data}/schema.graphql");
+        let query = format!("{data}/warmup/{query}.graphql");
+        let Command::Gen(args) = Cli::try_parse_from(
+            [
+                "subgraph-mock",
+                "gen",
+                "--schema",
+                &schema,
+                "--query",
+                &query,
+            ]
+            .into_iter()
+            .chain(args.iter().copied()),
+        )?
+        .command() else {
+            panic!("expected the gen command");
+        };
+        args.generate()
+    };
+    let generate = |query: &str| generate_with(query, &[]);
+
+    let response = generate("posts")?;
+    assert!(response["data"]["posts"].is_array(), "{response}");
+
+    // Invalid queries get the same errors as the server would respond with
+    let response = generate("invalid")?;
+    assert!(response["data"].is_null(), "{response}");
+    assert!(response["errors"][0]["message"].is_string(), "{response}");
+
+    // Responses are reproducible with a deterministic config or a seed
+    let deterministic = format!("{data}/config/deterministic.yaml");
+    let deterministic = ["--config", deterministic.as_str()];
+    assert_eq!(
+        generate_with("posts", &deterministic)?,
+        generate_with("posts", &deterministic)?
+    );
+    assert_eq!(
+        generate_with("posts", &["--seed", "7"])?,
+        generate_with("posts", &["--seed", "7"])?
+    );
+    assert_ne!(
+        generate_with("posts", &["--seed", "7"])?,
+        generate_with("posts", &["--seed", "8"])?
+    );
+
+    Ok(())
+}
+
+/// Start serving on an ephemeral port with the given config, returning the address to connect to
+async fn start_server(config_file_name: Option<&str>) -> anyhow::Result<SocketAddr> {
+    let (server, state) = harness::args(config_file_name, None).init()?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(serve_connections(
+        listener,
+        ConnectionOptions::new(&server)?,
+        Arc::new(state),
+        future::pending(),
+    ));
+    Ok(addr)
+}
+
+/// Send a query using HTTP/2 with prior knowledge, returning the response status
+async fn send_h2c_request(addr: SocketAddr) -> anyhow::Result<u16> {
+    let stream = TcpStream::connect(addr).await?;
+    let (mut sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let request = Request::post(format!("http://{addr}/"))
+        .header("content-type", "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+    let response = sender.send_request(request).await?;
+    let status = response.status().as_u16();
+    response.into_body().collect().await?;
+
+    Ok(status)
+}
+
+#[tokio::test]
+async fn http2_only() -> anyhow::Result<()> {
+    let addr = start_server(Some("http2_only.yaml")).await?;
+    assert_eq!(send_h2c_request(addr).await?, 200);
+
+    // HTTP/1.1 clients are turned away
+    let mut stream = TcpStream::connect(addr).await?;
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    assert!(send_raw_request(&mut stream, "/", &body).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn http1_and_h2c_without_http2_only() -> anyhow::Result<()> {
+    let addr = start_server(None).await?;
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let (status, _) = send_raw_request(&mut stream, "/", &body).await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    // The HTTP/2 connection preface is still detected when HTTP/1.1 is allowed
+    assert_eq!(send_h2c_request(addr).await?, 200);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reset_connections() -> anyhow::Result<()> {
+    let addr = start_server(Some("reset.yaml")).await?;
+
+    // HTTP/1.1 connections are closed without a response
+    let mut stream = TcpStream::connect(addr).await?;
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    assert!(send_raw_request(&mut stream, "/", &body).await.is_err());
+
+    // HTTP/2 requests have their stream reset
+    assert!(send_h2c_request(addr).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn connection_recycling() -> anyhow::Result<()> {
+    let addr = start_server(Some("connection_recycling.yaml")).await?;
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let mut buf = [0; 1];
+
+    // Connections are closed once they've been idle for the keep-alive timeout
+    let mut stream = TcpStream::connect(addr).await?;
+    let (status, _) = send_raw_request(&mut stream, "/", &body).await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let start = Instant::now();
+    assert_eq!(
+        timeout(Duration::from_secs(5), stream.read(&mut buf)).await??,
+        0
+    );
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // And right after the last request they can be used for
+    let mut stream = TcpStream::connect(addr).await?;
+    for _ in 0..2 {
+        let (status, _) = send_raw_request(&mut stream, "/", &body).await?;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+    let start = Instant::now();
+    assert_eq!(
+        timeout(Duration::from_secs(5), stream.read(&mut buf)).await??,
+        0
+    );
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    // Idle connections are closed even if they never send a request
+    let mut stream = TcpStream::connect(addr).await?;
+    let start = Instant::now();
+    assert_eq!(
+        timeout(Duration::from_secs(5), stream.read(&mut buf)).await??,
+        0
+    );
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn serve_programmatically() -> anyhow::Result<()> {
+    let schema = FederatedSchema::parse_string(
+        std::fs::read_to_string("tests/data/schema.graphql")?,
+        "schema.graphql",
+    )?;
+
+    // Find a free port for the server to bind to
+    let addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let server = tokio::spawn(serve(Config::default(), schema, addr));
+
+    let mut stream = timeout(Duration::from_secs(5), async {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    })
+    .await?;
+
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let (status, _) = send_raw_request(&mut stream, "/", &body).await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    server.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn subgraph_ports() -> anyhow::Result<()> {
+    let (server, state) = harness::args(Some("subgraph_ports.yaml"), None).init()?;
+    assert_eq!(
+        server.subgraph_ports.into_iter().collect::<Vec<_>>(),
+        vec![("special_subgraph".to_string(), 8044)]
+    );
+
+    // Bind an ephemeral port rather than the configured one, but serve it the same way the subgraph port would be
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let options = ConnectionOptions {
+        subgraph: Some("special_subgraph".into()),
+        ..Default::default()
+    };
+    tokio::spawn(serve_connections(
+        listener,
+        options,
+        Arc::new(state),
+        future::pending(),
+    ));
+
+    // Every path on a subgraph's own port is handled as that subgraph, even if it names a different one
+    for path in ["/", "/graphql", "/other_subgraph"] {
+        let stream = TcpStream::connect(addr).await?;
+        let (mut sender, conn) = http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(conn);
+
+        let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+        let request = Request::post(format!("http://{addr}{path}"))
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(body)))?;
+        let response = sender.send_request(request).await?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("test-header").unwrap(),
+            "test-header-overridden-value"
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsupported_methods() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+
+    for (method, path, allow) in [
+        ("GET", "/", "POST"),
+        ("PUT", "/subgraph", "POST"),
+        ("DELETE", "/status", "GET"),
+        ("POST", "/health", "GET"),
+        ("POST", "/debug/cache", "GET"),
+        ("GET", "/debug/flush-cache", "POST"),
+    ] {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Empty::<Bytes>::new())?;
+        let resp = handle_request(req, state.clone()).await?;
+        assert_eq!(
+            resp.status(),
+            StatusCode::METHOD_NOT_ALLOWED,
+            "{method} {path}"
+        );
+        assert_eq!(resp.headers()[ALLOW], allow, "{method} {path}");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn supergraph_endpoint() -> anyhow::Result<()> {
+    let sdl = include_str!("data/schema.graphql");
+    let uplink = |state: Arc<State>, if_after_id: Option<String>| async move {
+        let body = json!({
+            "operationName": "SupergraphSdl",
+            "query": "query SupergraphSdl($apiKey: String!, $ref: String!, $ifAfterId: ID) { routerConfig(ref: $ref, apiKey: $apiKey, ifAfterId: $ifAfterId) { __typename ... on RouterConfigResult { id supergraphSdl minDelaySeconds } ... on Unchanged { id minDelaySeconds } } }",
+            "variables": { "apiKey": "key", "ref": "graph@current", "ifAfterId": if_after_id },
+        });
+        let req =
+            Request::post("/uplink").body(Full::new(Bytes::from(serde_json::to_vec(&body)?)))?;
+        let resp = handle_request(req, state).await?;
+        let body: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+        anyhow::Ok(body["data"]["routerConfig"].clone())
+    };
+
+    let (_, state) = harness::initialize(Some("supergraph_endpoint.yaml"), None)?;
+    let req = Request::get("/supergraph").body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state.clone()).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.into_body().collect().await?.to_bytes(), sdl);
+    let req = Request::delete("/supergraph").body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state.clone()).await?;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()[ALLOW], "GET");
+
+    let router_config = uplink(state.clone(), None).await?;
+    assert_eq!(router_config["__typename"], "RouterConfigResult");
+    assert_eq!(router_config["supergraphSdl"], sdl);
+    // A router that already has the schema is told that it hasn't changed
+    let id = router_config["id"].as_str().unwrap().to_string();
+    let router_config = uplink(state.clone(), Some(id)).await?;
+    assert_eq!(router_config["__typename"], "Unchanged");
+    assert!(router_config.get("supergraphSdl").is_none());
+
+    // Without the endpoint enabled, `GET /supergraph` is a method that isn't allowed
+    let (_, state) = harness::initialize(None, None)?;
+    let req = Request::get("/supergraph").body(Empty::<Bytes>::new())?;
+    let resp = handle_request(req, state).await?;
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(resp.headers()[ALLOW], "POST");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn response_padding() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("response_padding.yaml"), None)?;
+    let query = "{ posts { id title } }";
+
+    let response =
+        harness::send_request(query.to_string(), None, state.clone(), None, true).await?;
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(body.len(), 4096);
+    let body: Value = serde_json::from_slice(&body)?;
+    assert!(body["data"]["posts"].is_array());
+    assert!(body["extensions"]["__padding"].is_string());
+
+    // Responses that are already larger than the target are left alone
+    let response = harness::send_request(
+        query.to_string(),
+        None,
+        state,
+        Some("tiny_target".to_string()),
+        true,
+    )
+    .await?;
+    let body: Value = serde_json::from_slice(&response.into_body().collect().await?.to_bytes())?;
+    assert!(body.get("extensions").is_none(), "{body}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn not_found() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(None, None)?;
+    // Paths that can't be a subgraph aren't found, whatever the method
+    for req in [
+        Request::options("*").body(Empty::<Bytes>::new())?,
+        Request::get("/debug/unknown").body(Empty::<Bytes>::new())?,
+    ] {
+        let resp = handle_request(req, state.clone()).await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let body: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+        assert_eq!(body, json!({ "errors": [{ "message": "Not found" }] }));
+    }
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn startup_delay() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("startup_delay.yaml"), None)?;
+    let health = || async {
+        let req = Request::get("/health").body(Empty::<Bytes>::new())?;
+        let resp = handle_request(req, state.clone()).await?;
+        let status = resp.status();
+        let body: serde_json_bytes::Value =
+            serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+        anyhow::Ok((status, body))
+    };
+    let query = || {
+        harness::send_request(
+            "{ posts { id } }".to_string(),
+            None,
+            state.clone(),
+            None,
+            true,
+        )
+    };
+
+    assert_eq!(
+        health().await?,
+        (StatusCode::SERVICE_UNAVAILABLE, json!({ "ready": false }))
+    );
+    assert_eq!(query().await?.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(health().await?, (StatusCode::OK, json!({ "ready": true })));
+    assert_eq!(query().await?.status(), StatusCode::OK);
+
+    Ok(())
+}
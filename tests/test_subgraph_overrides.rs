@@ -1,4 +1,8 @@
 use harness::{make_request, parse_response};
+use http_body_util::BodyExt;
+use serde_json_bytes::{Value, serde_json};
+use std::sync::Arc;
+use subgraph_mock::{handle::SUBGRAPH_NAME_HEADER, state::State};
 
 mod harness;
 
@@ -42,3 +46,82 @@ async fn subgraph_overrides() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn subgraph_name_header() -> anyhow::Result<()> {
+    let name_header = async |config: Option<&str>, subgraph_name: Option<&str>| {
+        let (_, state) = harness::initialize(config, None)?;
+        let response = harness::send_request(
+            "{ posts { id } }".to_string(),
+            None,
+            state,
+            subgraph_name.map(str::to_string),
+            false,
+        )
+        .await?;
+        anyhow::Ok(
+            response
+                .headers()
+                .get(SUBGRAPH_NAME_HEADER)
+                .map(|value| value.to_str().unwrap().to_string()),
+        )
+    };
+
+    assert_eq!(name_header(None, None).await?, None);
+    assert_eq!(
+        name_header(None, Some("posts")).await?,
+        Some("posts".to_string())
+    );
+    assert_eq!(
+        name_header(Some("instance_name.yaml"), None).await?,
+        Some("mock-1".to_string())
+    );
+    assert_eq!(
+        name_header(Some("instance_name.yaml"), Some("posts")).await?,
+        Some("posts".to_string())
+    );
+    assert_eq!(
+        name_header(Some("no_subgraph_name_header.yaml"), Some("posts")).await?,
+        None
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn subgraph_schema() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("subgraph_schema.yaml"), None)?;
+
+    let query = async |query: &str, state: Arc<State>, subgraph_name: Option<&str>| {
+        let response = harness::send_request(
+            query.to_string(),
+            None,
+            state,
+            subgraph_name.map(str::to_string),
+            false,
+        )
+        .await?;
+        let body = response.into_body().collect().await?.to_bytes();
+        anyhow::Ok(serde_json::from_slice::<Value>(&body)?)
+    };
+
+    // The subgraph is validated against its own schema
+    let items = query("{ items { id inStock } }", state.clone(), Some("inventory")).await?;
+    assert!(
+        items["errors"].is_null() && items["data"].is_object(),
+        "{items}"
+    );
+    let posts = query("{ posts { id } }", state.clone(), Some("inventory")).await?;
+    assert!(posts["errors"].is_array(), "{posts}");
+
+    // Everything else still uses the schema the server was started with
+    let posts = query("{ posts { id } }", state.clone(), None).await?;
+    assert!(
+        posts["errors"].is_null() && posts["data"].is_object(),
+        "{posts}"
+    );
+    let items = query("{ items { id } }", state.clone(), Some("other")).await?;
+    assert!(items["errors"].is_array(), "{items}");
+
+    Ok(())
+}
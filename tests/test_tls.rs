@@ -0,0 +1,70 @@
+use harness::send_raw_request;
+use serde_json_bytes::{json, serde_json};
+use std::{future, sync::Arc};
+use subgraph_mock::{ConnectionOptions, serve_connections};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{
+        ClientConfig, RootCertStore,
+        crypto::ring,
+        pki_types::{CertificateDer, ServerName, pem::PemObject},
+    },
+};
+
+mod harness;
+
+#[tokio::test]
+async fn serves_https() -> anyhow::Result<()> {
+    let (server, state) = harness::args(Some("tls.yaml"), None).init()?;
+    let options = ConnectionOptions::new(&server)?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(serve_connections(
+        listener,
+        options,
+        Arc::new(state),
+        future::pending(),
+    ));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file("tests/data/tls/cert.pem")?)?;
+    let client_config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+
+    let tcp = TcpStream::connect(addr).await?;
+    let mut stream = connector
+        .connect(ServerName::try_from("localhost")?, tcp)
+        .await?;
+
+    let body = serde_json::to_string(&json!({ "query": "{ posts { id } }" }))?;
+    let (status, body) = send_raw_request(&mut stream, "/", &body).await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert!(body["data"]["posts"].is_array(), "{body}");
+
+    // A TLS listener never serves plaintext requests
+    let mut plaintext = TcpStream::connect(addr).await?;
+    assert!(send_raw_request(&mut plaintext, "/", "{}").await.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn missing_certificate_fails() -> anyhow::Result<()> {
+    let (server, _) = harness::args(Some("tls_missing_cert.yaml"), None).init()?;
+    let err = ConnectionOptions::new(&server)
+        .err()
+        .expect("the certificate does not exist");
+    assert!(
+        err.to_string()
+            .contains("failed to load TLS certificate from tests/data/tls/missing.pem"),
+        "{err}"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use harness::send_request_with_variables;
+use http_body_util::BodyExt;
+use serde_json_bytes::{Value, json, serde_json};
+use std::sync::Arc;
+use subgraph_mock::state::State;
+
+mod harness;
+
+/// Sends `query` with `variables` and returns the response's `data`
+async fn data(
+    query: &str,
+    variables: Value,
+    schema_name: Option<&str>,
+    state: Arc<State>,
+) -> anyhow::Result<Value> {
+    let resp = send_request_with_variables(
+        query.to_string(),
+        variables,
+        schema_name.map(str::to_string),
+        state,
+        None,
+        false,
+    )
+    .await?;
+    assert!(resp.status().is_success());
+    let body: Value = serde_json::from_slice(&resp.into_body().collect().await?.to_bytes())?;
+
+    Ok(body["data"].clone())
+}
+
+#[tokio::test]
+async fn cached_entities_follow_representations() -> anyhow::Result<()> {
+    // Responses are cached by default
+    let (_, state) = harness::initialize(None, None)?;
+    let query = r#"
+        query($representations: [_Any!]!) {
+            _entities(representations: $representations) { __typename }
+        }
+    "#;
+    let typenames = async |representations: Value| -> anyhow::Result<Vec<Value>> {
+        let data = data(
+            query,
+            json!({ "representations": representations }),
+            None,
+            state.clone(),
+        )
+        .await?;
+        Ok(data["_entities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entity| entity["__typename"].clone())
+            .collect())
+    };
+
+    assert_eq!(
+        typenames(json!([{ "__typename": "User", "id": "1" }])).await?,
+        vec![json!("User")]
+    );
+    assert_eq!(
+        typenames(json!([
+            { "__typename": "Post", "id": "2" },
+            { "__typename": "Post", "id": "3" },
+        ]))
+        .await?,
+        vec![json!("Post"), json!("Post")]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cached_lists_follow_pagination_variables() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("pagination.yaml"), Some("paginated"))?;
+    let query = "query($first: Int) { posts(first: $first) { id } }";
+    let len = async |first: usize| -> anyhow::Result<usize> {
+        let data = data(
+            query,
+            json!({ "first": first }),
+            Some("paginated"),
+            state.clone(),
+        )
+        .await?;
+        Ok(data["posts"].as_array().unwrap().len())
+    };
+
+    assert_eq!(len(2).await?, 2);
+    assert_eq!(len(5).await?, 5);
+    assert_eq!(len(2).await?, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cached_pages_follow_cursor_variables() -> anyhow::Result<()> {
+    let (_, state) = harness::initialize(Some("pagination.yaml"), Some("paginated"))?;
+    let query = r#"
+        query($after: String) {
+            feed(first: 2, after: $after) { edges { cursor } pageInfo { endCursor } }
+        }
+    "#;
+    let page = async |after: Value| -> anyhow::Result<(Vec<Value>, Value)> {
+        let data = data(
+            query,
+            json!({ "after": after }),
+            Some("paginated"),
+            state.clone(),
+        )
+        .await?;
+        let feed = &data["feed"];
+        let cursors = feed["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edge| edge["cursor"].clone())
+            .collect();
+        Ok((cursors, feed["pageInfo"]["endCursor"].clone()))
+    };
+
+    // Each page continues from the end of the one before it
+    let (first_page, end_cursor) = page(Value::Null).await?;
+    assert_eq!(
+        first_page,
+        vec![
+            json!("YXJyYXljb25uZWN0aW9uOjA="),
+            json!("YXJyYXljb25uZWN0aW9uOjE="),
+        ]
+    );
+    let (second_page, end_cursor) = page(end_cursor).await?;
+    assert_eq!(
+        second_page,
+        vec![
+            json!("YXJyYXljb25uZWN0aW9uOjI="),
+            json!("YXJyYXljb25uZWN0aW9uOjM="),
+        ]
+    );
+    let (third_page, _) = page(end_cursor).await?;
+    assert_eq!(
+        third_page,
+        vec![
+            json!("YXJyYXljb25uZWN0aW9uOjQ="),
+            json!("YXJyYXljb25uZWN0aW9uOjU="),
+        ]
+    );
+
+    Ok(())
+}